@@ -1,19 +1,29 @@
 use std::{
-    fs::File, 
-    io::{ BufReader }, 
+    fs::File,
+    io::{ BufReader },
+    path::Path,
     sync::Arc,
     error::Error,
 };
-use rustls::{ ClientConfig, RootCertStore, };
-use rustls_pemfile::certs;
-use rustls::pki_types::{ CertificateDer, };
+use rustls::{ ClientConfig, ConfigBuilder, RootCertStore, DigitallySignedStruct, SignatureScheme };
+use rustls::client::WantsClientCert;
+use rustls::client::danger::{ HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier };
+use rustls::crypto::{ CryptoProvider, verify_tls12_signature, verify_tls13_signature };
+use rustls_pemfile::{ certs, pkcs8_private_keys };
+use rustls::pki_types::{ CertificateDer, PrivateKeyDer, ServerName, UnixTime };
+use sha2::{ Digest, Sha256 };
 
-pub fn load_client_config() -> Result<Arc<ClientConfig>, Box<dyn Error>> {
+//identity: certificate and key files to present when the server asks for a client certificate
+pub fn load_client_config(ca_path: &Path, identity: Option<(&Path, &Path)>) -> Result<Arc<ClientConfig>, Box<dyn Error>> {
     //get ca file
-    let ca_file = &mut BufReader::new(File::open("../certs/ca.crt").expect("missing ca.crt"));
+    let ca_file = &mut BufReader::new(File::open(ca_path)
+        .map_err(|e| format!("Failed to open CA file {}: {e}, use --verify tofu to trust the server on first use instead", ca_path.display()))?);
     //get vec of certs from ca
     let ca_certs: Vec<CertificateDer<'static>> = certs(ca_file)
         .collect::<Result<_,_>>()?;
+    if ca_certs.is_empty() {
+        return Err(format!("No certificates found in {}", ca_path.display()).into());
+    }
 
     //add root for each cert in ca_certs
     let mut roots = RootCertStore::empty();
@@ -22,9 +32,77 @@ pub fn load_client_config() -> Result<Arc<ClientConfig>, Box<dyn Error>> {
     }
 
     //build config
-    let cfg = ClientConfig::builder()
-        .with_root_certificates(roots)
-        .with_no_client_auth();
+    let builder = ClientConfig::builder()
+        .with_root_certificates(roots);
+    with_identity(builder, identity)
+}
+
+//trust on first use, known_hosts::check decides about the certificate before anything is sent
+//pinned: the fingerprint known hosts has for the server, the handshake fails on any other certificate
+//the client certificate is only presented when the server is pinned, a server nobody has trusted yet never sees it
+pub fn load_tofu_config(identity: Option<(&Path, &Path)>, pinned: Option<[u8; 32]>) -> Result<Arc<ClientConfig>, Box<dyn Error>> {
+    let builder = ClientConfig::builder();
+    let verifier = FingerprintVerifier { provider: builder.crypto_provider().clone(), pinned };
+    let builder = builder.dangerous().with_custom_certificate_verifier(Arc::new(verifier));
+    with_identity(builder, identity.filter(|_| pinned.is_some()))
+}
+
+fn with_identity(builder: ConfigBuilder<ClientConfig, WantsClientCert>, identity: Option<(&Path, &Path)>) -> Result<Arc<ClientConfig>, Box<dyn Error>> {
+    let cfg = match identity {
+        None => builder.with_no_client_auth(),
+        Some((cert_path, key_path)) => {
+            let cert_file = &mut BufReader::new(File::open(cert_path)
+                .map_err(|e| format!("Failed to open client certificate {}: {e}", cert_path.display()))?);
+            let cert_chain: Vec<CertificateDer<'static>> = certs(cert_file)
+                .collect::<Result<_,_>>()?;
+            if cert_chain.is_empty() {
+                return Err(format!("No certificates found in {}", cert_path.display()).into());
+            }
+
+            let key_file = &mut BufReader::new(File::open(key_path)
+                .map_err(|e| format!("Failed to open client key {}: {e}", key_path.display()))?);
+            let mut keys: Vec<PrivateKeyDer<'static>> = pkcs8_private_keys(key_file)
+                .map(|res| res.map(PrivateKeyDer::from))
+                .collect::<Result<_,_>>()?;
+            if keys.is_empty() {
+                return Err(format!("No PKCS#8 private key found in {}", key_path.display()).into());
+            }
+
+            builder.with_client_auth_cert(cert_chain, keys.remove(0))
+                .map_err(|e| format!("Client certificate {} doesn't match its key: {e}", cert_path.display()))?
+        }
+    };
 
     Ok(Arc::new(cfg))
-}
\ No newline at end of file
+}
+
+//checks the server holds the key of the certificate it presents, and that it is the pinned one if there is one
+//an unpinned certificate is accepted here and compared with known hosts after the handshake
+#[derive(Debug)]
+struct FingerprintVerifier {
+    provider: Arc<CryptoProvider>,
+    pinned: Option<[u8; 32]>,
+}
+
+impl ServerCertVerifier for FingerprintVerifier {
+    fn verify_server_cert(&self, end_entity: &CertificateDer<'_>, _intermediates: &[CertificateDer<'_>], _server_name: &ServerName<'_>, _ocsp_response: &[u8], _now: UnixTime) -> Result<ServerCertVerified, rustls::Error> {
+        match self.pinned {
+            Some(pinned) if <[u8; 32]>::from(Sha256::digest(end_entity.as_ref())) != pinned => {
+                Err(rustls::Error::General("the server's certificate doesn't match the one in known hosts".to_string()))
+            }
+            _ => Ok(ServerCertVerified::assertion()),
+        }
+    }
+
+    fn verify_tls12_signature(&self, message: &[u8], cert: &CertificateDer<'_>, dss: &DigitallySignedStruct) -> Result<HandshakeSignatureValid, rustls::Error> {
+        verify_tls12_signature(message, cert, dss, &self.provider.signature_verification_algorithms)
+    }
+
+    fn verify_tls13_signature(&self, message: &[u8], cert: &CertificateDer<'_>, dss: &DigitallySignedStruct) -> Result<HandshakeSignatureValid, rustls::Error> {
+        verify_tls13_signature(message, cert, dss, &self.provider.signature_verification_algorithms)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.provider.signature_verification_algorithms.supported_schemes()
+    }
+}
@@ -0,0 +1,445 @@
+//client command line and the saved connection profiles it can use
+use std::{
+    collections::BTreeMap,
+    env,
+    error::Error,
+    fmt,
+    fs,
+    path::{ Path, PathBuf },
+};
+use clap::{ Parser, Subcommand, ValueEnum };
+use serde::Deserialize;
+use common::clipboard::ClipboardPolicy;
+use crate::connect::split_host_port;
+
+const DEFAULT_CA_PATH: &str = "../certs/ca.crt";
+
+#[derive(Parser, Debug)]
+#[command(name = "client", version, about = "Remote desktop client")]
+struct Cli {
+    #[arg(short, long, global = true, env = "CLIENT_CONFIG", help = "profiles file, defaults to remote-desktop/client.toml in the user config directory")]
+    config: Option<PathBuf>,
+    #[command(subcommand)]
+    command: Command,
+}
+
+//parsed once at startup, so the size difference between the variants doesn't matter
+#[allow(clippy::large_enum_variant)]
+#[derive(Subcommand, Debug)]
+enum Command {
+    #[command(about = "Connect to a server by address or by saved profile name")]
+    Connect {
+        #[arg(env = "SERVER_ADDR", help = "host:port or [ipv6]:port to connect to, or the name of a profile")]
+        target: String,
+        #[arg(long, help = "name sent as SNI and checked against the server certificate, defaults to the host being connected to")]
+        server_name: Option<String>,
+        #[arg(long, value_enum, help = "how the server certificate is checked: ca, or tofu to trust it on first use and remember it")]
+        verify: Option<Verify>,
+        #[arg(long, help = "PEM file with the CA certificates to trust")]
+        ca: Option<PathBuf>,
+        #[arg(long, env = "CLIENT_KNOWN_HOSTS", help = "server fingerprints remembered by --verify tofu, defaults to remote-desktop/known_hosts in the user config directory")]
+        known_hosts: Option<PathBuf>,
+        #[arg(long, requires = "client_key", help = "PEM certificate to present to servers that ask for one")]
+        client_cert: Option<PathBuf>,
+        #[arg(long, requires = "client_cert", help = "PEM PKCS#8 private key for the client certificate")]
+        client_key: Option<PathBuf>,
+        #[arg(long, env = "CLIENT_PASSWORD", hide_env_values = true, help = "password or token for servers that ask for one, prompted for when unset")]
+        password: Option<String>,
+        #[arg(long, value_enum, help = "largest picture to ask the server for")]
+        quality: Option<Quality>,
+        #[arg(long, value_enum, help = "how the picture fills the window")]
+        scaling: Option<ScalingMode>,
+        #[arg(long, value_enum, help = "size the server streams at: window to fit the window as it is resized, or native for the screen 1:1")]
+        resolution: Option<Resolution>,
+        #[arg(long, value_enum, env = "CLIENT_CLIPBOARD", help = "clipboard sync direction")]
+        clipboard: Option<ClipboardPolicy>,
+    },
+    #[command(about = "List saved profiles")]
+    List,
+}
+
+//one saved connection in the profiles file, everything but the address is optional
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(deny_unknown_fields)]
+pub struct Profile {
+    pub address: String,
+    pub server_name: Option<String>,
+    pub verify: Option<Verify>,
+    pub ca: Option<PathBuf>,
+    pub client_cert: Option<PathBuf>,
+    pub client_key: Option<PathBuf>,
+    //kept in plain text, leave it out to be prompted instead
+    pub password: Option<String>,
+    pub quality: Option<Quality>,
+    pub scaling: Option<ScalingMode>,
+    pub resolution: Option<Resolution>,
+    pub clipboard: Option<ClipboardPolicy>,
+}
+
+//the profiles file, [profiles.<name>] tables
+#[derive(Deserialize, Debug, Default)]
+#[serde(deny_unknown_fields)]
+struct FileConfig {
+    #[serde(default)]
+    profiles: BTreeMap<String, Profile>,
+}
+
+//how the server certificate is checked, the names are the same in the profiles file and on the command line
+#[derive(ValueEnum, Deserialize, Debug, Copy, Clone, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Verify {
+    //chain to a CA and match the server name
+    Ca,
+    //trust on first use and remember the fingerprint
+    Tofu,
+}
+
+//largest frame the client asks for, the server picks a downscale that fits
+#[derive(ValueEnum, Deserialize, Debug, Copy, Clone, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Quality {
+    Low,
+    Medium,
+    //as large as the monitor the window opens on
+    High,
+}
+
+impl Quality {
+    //frame size to announce for a monitor of the given size
+    pub fn max_size(&self, monitor: (u32, u32)) -> (u32, u32) {
+        let cap = match self {
+            Quality::Low => (1280, 720),
+            Quality::Medium => (1920, 1080),
+            Quality::High => return monitor,
+        };
+        (cap.0.min(monitor.0), cap.1.min(monitor.1))
+    }
+}
+
+//how a frame is drawn into a window of a different size
+#[derive(ValueEnum, Deserialize, Debug, Copy, Clone, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ScalingMode {
+    //fill the whole window, the picture is distorted if the aspect ratios differ
+    Stretch,
+    //as large as fits while keeping the aspect ratio, the rest of the window is black
+    Fit,
+}
+
+impl ScalingMode {
+    //where a frame lands in the window as x, y, width, height in window pixels
+    pub fn viewport(&self, frame: (u32, u32), window: (u32, u32)) -> (u32, u32, u32, u32) {
+        match self {
+            ScalingMode::Stretch => (0, 0, window.0, window.1),
+            ScalingMode::Fit => {
+                if frame.0 == 0 || frame.1 == 0 {
+                    return (0, 0, window.0, window.1);
+                }
+                //scale by whichever side runs out of room first
+                let (w, h) = if window.0 as u64 * frame.1 as u64 <= window.1 as u64 * frame.0 as u64 {
+                    (window.0, (window.0 as u64 * frame.1 as u64 / frame.0 as u64) as u32)
+                } else {
+                    ((window.1 as u64 * frame.0 as u64 / frame.1 as u64) as u32, window.1)
+                };
+                let (w, h) = (w.max(1), h.max(1));
+                ((window.0 - w.min(window.0)) / 2, (window.1 - h.min(window.1)) / 2, w, h)
+            }
+        }
+    }
+}
+
+//the name a value is given on the command line, for listing profiles
+fn value_name<T: ValueEnum>(value: &T, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match value.to_possible_value() {
+        Some(possible) => write!(f, "{}", possible.get_name()),
+        None => Ok(()),
+    }
+}
+
+impl fmt::Display for Verify {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        value_name(self, f)
+    }
+}
+
+impl fmt::Display for Quality {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        value_name(self, f)
+    }
+}
+
+impl fmt::Display for ScalingMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        value_name(self, f)
+    }
+}
+
+//how the server's certificate is trusted
+#[derive(Debug, Clone)]
+pub enum Trust {
+    //it has to chain to a CA in this PEM file and match the server name
+    Ca(PathBuf),
+    //its fingerprint is remembered in this known hosts file the first time and has to match after that
+    KnownHosts(PathBuf),
+}
+
+//what size the server is asked to stream at
+#[derive(ValueEnum, Deserialize, Debug, Copy, Clone, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Resolution {
+    //fitted to the window, the server is told its new size whenever it is resized
+    Window,
+    //the server's screen 1:1 as far as quality allows, scaling still fits it to the window locally
+    Native,
+}
+
+impl fmt::Display for Resolution {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        value_name(self, f)
+    }
+}
+
+//validated settings for one connection
+#[derive(Debug, Clone)]
+pub struct ConnectConfig {
+    //profile the settings came from, None when connecting to an address directly
+    pub profile: Option<String>,
+    pub address: String,
+    pub server_name: String,
+    pub trust: Trust,
+    //certificate and key to authenticate with, None connects without one
+    pub client_identity: Option<(PathBuf, PathBuf)>,
+    //None prompts if the server asks for a password
+    pub password: Option<String>,
+    pub quality: Quality,
+    pub scaling: ScalingMode,
+    pub resolution: Resolution,
+    pub clipboard: ClipboardPolicy,
+}
+
+//what the command line asked for
+pub enum Action {
+    Connect(ConnectConfig),
+    List { path: PathBuf, profiles: BTreeMap<String, Profile> },
+}
+
+//parse the command line and read the profiles file, exits with usage on bad flags
+pub fn load() -> Result<Action, Box<dyn Error>> {
+    let cli = Cli::parse();
+    let (path, explicit) = match cli.config {
+        Some(path) => (path, true),
+        None => (default_config_dir()?.join("client.toml"), false),
+    };
+    //a missing default file just means no profiles have been saved yet
+    let file = if explicit || path.exists() { read_file(&path)? } else { FileConfig::default() };
+    action(cli.command, path, file)
+}
+
+//the profile named by the target with the flags laid over it, or the target as an address
+fn action(command: Command, path: PathBuf, file: FileConfig) -> Result<Action, Box<dyn Error>> {
+    match command {
+        Command::List => Ok(Action::List { path, profiles: file.profiles }),
+        Command::Connect { target, server_name, verify, ca, known_hosts, client_cert, client_key, password, quality, scaling, resolution, clipboard } => {
+            let (name, profile) = match file.profiles.get(&target) {
+                Some(profile) => (Some(target), profile.clone()),
+                None if target.contains(':') => (None, Profile { address: target, ..Profile::default() }),
+                None => {
+                    return Err(format!(
+                        "No profile named {target:?} in {} and it isn't a host:port address",
+                        path.display()
+                    ).into());
+                }
+            };
+            //flags win over the profile, the certificate and key are taken as a pair
+            let (client_cert, client_key) = match (client_cert, client_key) {
+                (Some(cert), Some(key)) => (Some(cert), Some(key)),
+                _ => (profile.client_cert, profile.client_key),
+            };
+            let profile = Profile {
+                address: profile.address,
+                server_name: server_name.or(profile.server_name),
+                verify: verify.or(profile.verify),
+                ca: ca.or(profile.ca),
+                client_cert,
+                client_key,
+                password: password.or(profile.password),
+                quality: quality.or(profile.quality),
+                scaling: scaling.or(profile.scaling),
+                resolution: resolution.or(profile.resolution),
+                clipboard: clipboard.or(profile.clipboard),
+            };
+            Ok(Action::Connect(validate(name, profile, known_hosts)?))
+        }
+    }
+}
+
+fn validate(name: Option<String>, profile: Profile, known_hosts: Option<PathBuf>) -> Result<ConnectConfig, Box<dyn Error>> {
+    //names the profile in errors, the bad value may also have come from a flag
+    let origin = match &name {
+        Some(name) => format!(" for profile {name:?}"),
+        None => String::new(),
+    };
+
+    let (host, _) = split_host_port(&profile.address).map_err(|e| format!("{e}{origin}"))?;
+    let host = host.to_string();
+
+    let trust = match profile.verify.unwrap_or(Verify::Ca) {
+        Verify::Ca => Trust::Ca(profile.ca.unwrap_or_else(|| PathBuf::from(DEFAULT_CA_PATH))),
+        Verify::Tofu => Trust::KnownHosts(match known_hosts {
+            Some(path) => path,
+            None => default_config_dir()?.join("known_hosts"),
+        }),
+    };
+
+    let client_identity = match (profile.client_cert, profile.client_key) {
+        (Some(cert), Some(key)) => Some((cert, key)),
+        (None, None) => None,
+        _ => return Err(format!("client_cert and client_key have to be set together{origin}").into()),
+    };
+
+    Ok(ConnectConfig {
+        profile: name,
+        //an IP literal becomes an IP server name, so the certificate then needs an IP SAN unless server_name is set
+        server_name: profile.server_name.unwrap_or(host),
+        address: profile.address,
+        trust,
+        client_identity,
+        password: profile.password,
+        quality: profile.quality.unwrap_or(Quality::High),
+        scaling: profile.scaling.unwrap_or(ScalingMode::Stretch),
+        resolution: profile.resolution.unwrap_or(Resolution::Window),
+        clipboard: profile.clipboard.unwrap_or(ClipboardPolicy::Both),
+    })
+}
+
+//$XDG_CONFIG_HOME/remote-desktop, falling back to ~/.config, holds client.toml and known_hosts
+fn default_config_dir() -> Result<PathBuf, Box<dyn Error>> {
+    let base = match env::var_os("XDG_CONFIG_HOME") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => {
+            let home = env::var_os("HOME").ok_or("HOME is not set, set XDG_CONFIG_HOME or pass the file with --config or --known-hosts")?;
+            PathBuf::from(home).join(".config")
+        }
+    };
+    Ok(base.join("remote-desktop"))
+}
+
+fn read_file(path: &Path) -> Result<FileConfig, Box<dyn Error>> {
+    let text = fs::read_to_string(path)
+        .map_err(|e| format!("Failed to read profiles file {}: {e}", path.display()))?;
+    let file = toml::from_str(&text)
+        .map_err(|e| format!("Invalid profiles file {}: {e}", path.display()))?;
+    Ok(file)
+}
+
+//print the saved profiles for the list command
+pub fn print_profiles(path: &Path, profiles: &BTreeMap<String, Profile>) {
+    if profiles.is_empty() {
+        println!("No profiles saved in {}", path.display());
+        return;
+    }
+    println!("Profiles in {}:", path.display());
+    let width = profiles.keys().map(|name| name.len()).max().unwrap_or(0);
+    for (name, profile) in profiles {
+        let mut details = Vec::new();
+        if let Some(server_name) = &profile.server_name {
+            details.push(format!("server name {server_name}"));
+        }
+        if let Some(verify) = &profile.verify {
+            details.push(format!("verify {verify}"));
+        }
+        if let Some(ca) = &profile.ca {
+            details.push(format!("ca {}", ca.display()));
+        }
+        if let Some(client_cert) = &profile.client_cert {
+            details.push(format!("client cert {}", client_cert.display()));
+        }
+        if profile.password.is_some() {
+            details.push("password saved".to_string());
+        }
+        if let Some(quality) = &profile.quality {
+            details.push(format!("quality {quality}"));
+        }
+        if let Some(scaling) = &profile.scaling {
+            details.push(format!("scaling {scaling}"));
+        }
+        if let Some(resolution) = &profile.resolution {
+            details.push(format!("resolution {resolution}"));
+        }
+        if let Some(clipboard) = &profile.clipboard {
+            details.push(format!("clipboard {clipboard}"));
+        }
+        if details.is_empty() {
+            println!("  {name:<width$}  {}", profile.address);
+        } else {
+            println!("  {name:<width$}  {}  ({})", profile.address, details.join(", "));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PROFILES: &str = r#"
+        [profiles.work]
+        address = "desk.example.com:7000"
+        verify = "tofu"
+        quality = "medium"
+        scaling = "fit"
+    "#;
+
+    fn connect(args: &[&str], profiles: &str) -> Result<ConnectConfig, Box<dyn Error>> {
+        let cli = Cli::try_parse_from(["client", "connect"].iter().chain(args))?;
+        let file = toml::from_str(profiles)?;
+        match action(cli.command, PathBuf::from("client.toml"), file)? {
+            Action::Connect(settings) => Ok(settings),
+            Action::List { .. } => panic!("connect listed profiles"),
+        }
+    }
+
+    #[test]
+    fn a_profile_is_used_as_saved() {
+        let settings = connect(&["work", "--known-hosts", "hosts"], PROFILES).unwrap();
+        assert_eq!(settings.profile.as_deref(), Some("work"));
+        assert_eq!(settings.address, "desk.example.com:7000");
+        assert_eq!(settings.server_name, "desk.example.com");
+        assert!(matches!(settings.trust, Trust::KnownHosts(path) if path == Path::new("hosts")));
+        assert_eq!(settings.quality, Quality::Medium);
+        assert_eq!(settings.scaling, ScalingMode::Fit);
+    }
+
+    #[test]
+    fn flags_win_over_the_profile() {
+        let settings = connect(&["work", "--verify", "ca", "--ca", "ca.pem", "--quality", "low", "--scaling", "stretch"], PROFILES).unwrap();
+        assert!(matches!(settings.trust, Trust::Ca(path) if path == Path::new("ca.pem")));
+        assert_eq!(settings.quality, Quality::Low);
+        assert_eq!(settings.scaling, ScalingMode::Stretch);
+    }
+
+    #[test]
+    fn an_address_without_a_profile_gets_the_defaults() {
+        let settings = connect(&["10.0.0.5:7000"], PROFILES).unwrap();
+        assert_eq!(settings.profile, None);
+        assert!(matches!(settings.trust, Trust::Ca(path) if path == Path::new(DEFAULT_CA_PATH)));
+        assert_eq!(settings.quality, Quality::High);
+        assert_eq!(settings.scaling, ScalingMode::Stretch);
+    }
+
+    #[test]
+    fn an_unknown_target_is_an_error() {
+        let e = connect(&["home"], PROFILES).unwrap_err().to_string();
+        assert!(e.contains("No profile named \"home\""), "{e}");
+    }
+
+    #[test]
+    fn invalid_values_are_refused_with_the_choices() {
+        let e = connect(&["work", "--quality", "ultra"], PROFILES).unwrap_err().to_string();
+        assert!(e.contains("ultra") && e.contains("low, medium, high"), "{e}");
+        let e = connect(&["work", "--verify", "none"], PROFILES).unwrap_err().to_string();
+        assert!(e.contains("ca, tofu"), "{e}");
+
+        let e = connect(&["work"], "[profiles.work]\naddress = \"desk:7000\"\nscaling = \"zoom\"\n").unwrap_err().to_string();
+        assert!(e.contains("zoom") && e.contains("stretch") && e.contains("fit"), "{e}");
+    }
+}
@@ -0,0 +1,163 @@
+//opening the connection to the server: address parsing, name resolution, TCP and the TLS handshake
+use std::{
+    error::Error,
+    fmt,
+    io,
+    net::{ SocketAddr, TcpStream, ToSocketAddrs },
+    sync::Arc,
+    time::Duration,
+};
+use rustls::{
+    ClientConfig,
+    ClientConnection,
+    pki_types::ServerName,
+};
+
+//how long to wait for each resolved address to accept the TCP connection
+const TCP_CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+
+//split host:port or [ipv6]:port, the brackets are removed from the host
+pub fn split_host_port(address: &str) -> Result<(&str, u16), String> {
+    let (host, port) = if let Some(rest) = address.strip_prefix('[') {
+        let (host, rest) = rest.split_once(']')
+            .ok_or_else(|| format!("Invalid address {address:?}, missing ] after the IPv6 address"))?;
+        let port = rest.strip_prefix(':')
+            .ok_or_else(|| format!("Invalid address {address:?}, expected [ipv6]:port"))?;
+        (host, port)
+    } else {
+        let (host, port) = address.rsplit_once(':')
+            .ok_or_else(|| format!("Invalid address {address:?}, expected host:port"))?;
+        if host.contains(':') {
+            return Err(format!("Invalid address {address:?}, IPv6 addresses need brackets like [::1]:7878"));
+        }
+        (host, port)
+    };
+    if host.is_empty() {
+        return Err(format!("Invalid address {address:?}, the host is empty"));
+    }
+    let port = port.parse::<u16>()
+        .map_err(|_| format!("Invalid address {address:?}, {port:?} is not a port number"))?;
+    Ok((host, port))
+}
+
+//which step of connecting failed, so a typo in the host, a closed port and a bad certificate read differently
+#[derive(Debug)]
+pub enum ConnectError {
+    //the address couldn't be parsed or the server name isn't a valid DNS name or IP
+    Address(String),
+    //the host name didn't resolve to any address
+    Resolve { host: String, source: io::Error },
+    //every resolved address refused or timed out, with the error for each
+    Tcp { address: String, attempts: Vec<(SocketAddr, io::Error)> },
+    //TCP connected but the TLS handshake failed, usually a certificate that doesn't match the server name or CA
+    Tls { server_name: String, source: io::Error },
+}
+
+impl fmt::Display for ConnectError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConnectError::Address(message) => write!(f, "{message}"),
+            ConnectError::Resolve { host, source } => write!(f, "Failed to resolve {host}: {source}"),
+            ConnectError::Tcp { address, attempts } => {
+                write!(f, "Failed to connect to {address}")?;
+                for (addr, e) in attempts {
+                    write!(f, "\n  {addr}: {e}")?;
+                }
+                Ok(())
+            }
+            ConnectError::Tls { server_name, source } => {
+                write!(f, "TLS handshake with {server_name} failed: {source}")
+            }
+        }
+    }
+}
+
+impl Error for ConnectError {}
+
+//resolve address and try each result in turn until one accepts
+fn connect_tcp(address: &str) -> Result<TcpStream, ConnectError> {
+    let (host, port) = split_host_port(address).map_err(ConnectError::Address)?;
+    let addrs: Vec<SocketAddr> = (host, port).to_socket_addrs()
+        .map_err(|source| ConnectError::Resolve { host: host.to_string(), source })?
+        .collect();
+    if addrs.is_empty() {
+        return Err(ConnectError::Resolve {
+            host: host.to_string(),
+            source: io::Error::new(io::ErrorKind::NotFound, "no addresses found"),
+        });
+    }
+
+    let mut attempts = Vec::new();
+    for addr in addrs {
+        match TcpStream::connect_timeout(&addr, TCP_CONNECT_TIMEOUT) {
+            Ok(tcp) => {
+                println!("Connected to {addr}");
+                return Ok(tcp);
+            }
+            Err(e) => attempts.push((addr, e)),
+        }
+    }
+    Err(ConnectError::Tcp { address: address.to_string(), attempts })
+}
+
+//open TCP to address and finish the TLS handshake, checking the certificate against server_name
+pub fn connect(address: &str, server_name: &str, tls_config: Arc<ClientConfig>) -> Result<(ClientConnection, TcpStream), ConnectError> {
+    let name = ServerName::try_from(server_name.to_string())
+        .map_err(|e| ConnectError::Address(format!("Invalid server name {server_name:?}: {e}")))?;
+
+    let mut tcp = connect_tcp(address)?;
+    tcp.set_nodelay(true).ok();
+    tcp.set_read_timeout(Some(Duration::from_secs(2))).ok();
+    tcp.set_write_timeout(Some(Duration::from_secs(2))).ok();
+
+    let tls_error = |source: io::Error| ConnectError::Tls { server_name: server_name.to_string(), source };
+    let mut tls_connection = ClientConnection::new(tls_config, name)
+        .map_err(|e| tls_error(io::Error::other(e)))?;
+    //run the handshake now instead of on the first write so certificate problems are reported as such
+    while tls_connection.is_handshaking() {
+        tls_connection.complete_io(&mut tcp).map_err(tls_error)?;
+    }
+    Ok((tls_connection, tcp))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn host_and_port_are_split() {
+        assert_eq!(split_host_port("example.com:7878"), Ok(("example.com", 7878)));
+        assert_eq!(split_host_port("localhost:1"), Ok(("localhost", 1)));
+        assert_eq!(split_host_port("127.0.0.1:65535"), Ok(("127.0.0.1", 65535)));
+    }
+
+    #[test]
+    fn bracketed_ipv6_addresses_lose_their_brackets() {
+        assert_eq!(split_host_port("[::1]:7878"), Ok(("::1", 7878)));
+        assert_eq!(split_host_port("[fe80::1%eth0]:22"), Ok(("fe80::1%eth0", 22)));
+    }
+
+    #[test]
+    fn ipv6_needs_brackets_and_a_port() {
+        assert!(split_host_port("::1").unwrap_err().contains("need brackets"));
+        assert!(split_host_port("::1:7878").unwrap_err().contains("need brackets"));
+        assert!(split_host_port("[::1]").unwrap_err().contains("expected [ipv6]:port"));
+        assert!(split_host_port("[::1]7878").unwrap_err().contains("expected [ipv6]:port"));
+    }
+
+    #[test]
+    fn a_missing_port_is_an_error() {
+        assert!(split_host_port("example.com").unwrap_err().contains("expected host:port"));
+        assert!(split_host_port("example.com:").unwrap_err().contains("not a port number"));
+        assert!(split_host_port("example.com:http").unwrap_err().contains("not a port number"));
+        assert!(split_host_port("example.com:70000").unwrap_err().contains("not a port number"));
+    }
+
+    #[test]
+    fn malformed_brackets_and_empty_hosts_are_errors() {
+        assert!(split_host_port("[::1:7878").unwrap_err().contains("missing ]"));
+        assert!(split_host_port("::1]:7878").unwrap_err().contains("need brackets"));
+        assert!(split_host_port("[]:7878").unwrap_err().contains("host is empty"));
+        assert!(split_host_port(":7878").unwrap_err().contains("host is empty"));
+    }
+}
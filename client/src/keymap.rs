@@ -0,0 +1,129 @@
+use common::input::{ Key, Modifiers, MouseButton, ScrollDelta, SCROLL_STEPS_PER_LINE };
+use winit::event::{ self, VirtualKeyCode, ModifiersState, MouseScrollDelta };
+
+//protocol key for a winit key, None for keys the protocol has no code for
+pub fn key_from_winit(code: VirtualKeyCode) -> Option<Key> {
+    let key = match code {
+        VirtualKeyCode::Key1 => Key::Digit1, VirtualKeyCode::Key2 => Key::Digit2,
+        VirtualKeyCode::Key3 => Key::Digit3, VirtualKeyCode::Key4 => Key::Digit4,
+        VirtualKeyCode::Key5 => Key::Digit5, VirtualKeyCode::Key6 => Key::Digit6,
+        VirtualKeyCode::Key7 => Key::Digit7, VirtualKeyCode::Key8 => Key::Digit8,
+        VirtualKeyCode::Key9 => Key::Digit9, VirtualKeyCode::Key0 => Key::Digit0,
+
+        VirtualKeyCode::A => Key::A, VirtualKeyCode::B => Key::B, VirtualKeyCode::C => Key::C,
+        VirtualKeyCode::D => Key::D, VirtualKeyCode::E => Key::E, VirtualKeyCode::F => Key::F,
+        VirtualKeyCode::G => Key::G, VirtualKeyCode::H => Key::H, VirtualKeyCode::I => Key::I,
+        VirtualKeyCode::J => Key::J, VirtualKeyCode::K => Key::K, VirtualKeyCode::L => Key::L,
+        VirtualKeyCode::M => Key::M, VirtualKeyCode::N => Key::N, VirtualKeyCode::O => Key::O,
+        VirtualKeyCode::P => Key::P, VirtualKeyCode::Q => Key::Q, VirtualKeyCode::R => Key::R,
+        VirtualKeyCode::S => Key::S, VirtualKeyCode::T => Key::T, VirtualKeyCode::U => Key::U,
+        VirtualKeyCode::V => Key::V, VirtualKeyCode::W => Key::W, VirtualKeyCode::X => Key::X,
+        VirtualKeyCode::Y => Key::Y, VirtualKeyCode::Z => Key::Z,
+
+        VirtualKeyCode::Escape => Key::Escape,
+        VirtualKeyCode::F1 => Key::F1, VirtualKeyCode::F2 => Key::F2, VirtualKeyCode::F3 => Key::F3,
+        VirtualKeyCode::F4 => Key::F4, VirtualKeyCode::F5 => Key::F5, VirtualKeyCode::F6 => Key::F6,
+        VirtualKeyCode::F7 => Key::F7, VirtualKeyCode::F8 => Key::F8, VirtualKeyCode::F9 => Key::F9,
+        VirtualKeyCode::F10 => Key::F10, VirtualKeyCode::F11 => Key::F11, VirtualKeyCode::F12 => Key::F12,
+        VirtualKeyCode::Snapshot => Key::PrintScreen,
+        VirtualKeyCode::Scroll => Key::ScrollLock,
+        VirtualKeyCode::Pause => Key::Pause,
+
+        VirtualKeyCode::Insert => Key::Insert, VirtualKeyCode::Home => Key::Home,
+        VirtualKeyCode::Delete => Key::Delete, VirtualKeyCode::End => Key::End,
+        VirtualKeyCode::PageDown => Key::PageDown, VirtualKeyCode::PageUp => Key::PageUp,
+        VirtualKeyCode::Left => Key::Left, VirtualKeyCode::Up => Key::Up,
+        VirtualKeyCode::Right => Key::Right, VirtualKeyCode::Down => Key::Down,
+
+        VirtualKeyCode::Back => Key::Backspace,
+        VirtualKeyCode::Return => Key::Enter,
+        VirtualKeyCode::Space => Key::Space,
+        VirtualKeyCode::Tab => Key::Tab,
+        VirtualKeyCode::Capital => Key::CapsLock,
+
+        VirtualKeyCode::Numlock => Key::NumLock,
+        VirtualKeyCode::Numpad0 => Key::Kp0, VirtualKeyCode::Numpad1 => Key::Kp1,
+        VirtualKeyCode::Numpad2 => Key::Kp2, VirtualKeyCode::Numpad3 => Key::Kp3,
+        VirtualKeyCode::Numpad4 => Key::Kp4, VirtualKeyCode::Numpad5 => Key::Kp5,
+        VirtualKeyCode::Numpad6 => Key::Kp6, VirtualKeyCode::Numpad7 => Key::Kp7,
+        VirtualKeyCode::Numpad8 => Key::Kp8, VirtualKeyCode::Numpad9 => Key::Kp9,
+        VirtualKeyCode::NumpadAdd => Key::KpPlus,
+        VirtualKeyCode::NumpadDivide => Key::KpDivide,
+        VirtualKeyCode::NumpadDecimal => Key::KpDecimal,
+        VirtualKeyCode::NumpadEnter => Key::KpEnter,
+        VirtualKeyCode::NumpadEquals => Key::KpEqual,
+        VirtualKeyCode::NumpadMultiply => Key::KpMultiply,
+        VirtualKeyCode::NumpadSubtract => Key::KpMinus,
+
+        VirtualKeyCode::Apostrophe => Key::Quote,
+        VirtualKeyCode::Backslash => Key::Backslash,
+        VirtualKeyCode::Comma => Key::Comma,
+        VirtualKeyCode::Equals => Key::Equal,
+        VirtualKeyCode::Grave => Key::Grave,
+        VirtualKeyCode::LBracket => Key::LeftBracket,
+        VirtualKeyCode::RBracket => Key::RightBracket,
+        VirtualKeyCode::Minus => Key::Minus,
+        VirtualKeyCode::Period => Key::Period,
+        VirtualKeyCode::Semicolon => Key::Semicolon,
+        VirtualKeyCode::Slash => Key::Slash,
+        VirtualKeyCode::OEM102 => Key::IntlBackslash,
+        VirtualKeyCode::Apps => Key::Menu,
+
+        VirtualKeyCode::LAlt => Key::LeftAlt, VirtualKeyCode::RAlt => Key::RightAlt,
+        VirtualKeyCode::LControl => Key::LeftCtrl, VirtualKeyCode::RControl => Key::RightCtrl,
+        VirtualKeyCode::LShift => Key::LeftShift, VirtualKeyCode::RShift => Key::RightShift,
+        VirtualKeyCode::LWin => Key::LeftMeta, VirtualKeyCode::RWin => Key::RightMeta,
+
+        VirtualKeyCode::Mute => Key::Mute,
+        VirtualKeyCode::VolumeUp => Key::VolumeUp,
+        VirtualKeyCode::VolumeDown => Key::VolumeDown,
+
+        _ => return None,
+    };
+    Some(key)
+}
+
+pub fn modifiers_from_winit(state: ModifiersState) -> Modifiers {
+    let mut modifiers = Modifiers::NONE;
+    if state.shift() {
+        modifiers = modifiers.union(Modifiers::SHIFT);
+    }
+    if state.ctrl() {
+        modifiers = modifiers.union(Modifiers::CTRL);
+    }
+    if state.alt() {
+        modifiers = modifiers.union(Modifiers::ALT);
+    }
+    if state.logo() {
+        modifiers = modifiers.union(Modifiers::META);
+    }
+    modifiers
+}
+
+//protocol button for a winit button, None for buttons the protocol has no code for
+pub fn button_from_winit(button: event::MouseButton) -> Option<MouseButton> {
+    match button {
+        event::MouseButton::Left => Some(MouseButton::Left),
+        event::MouseButton::Right => Some(MouseButton::Right),
+        event::MouseButton::Middle => Some(MouseButton::Middle),
+        //each platform numbers the side buttons differently: windows 1/2, macos 3/4, x11 8/9, wayland BTN_SIDE/BTN_EXTRA
+        event::MouseButton::Other(1 | 3 | 8 | 0x113) => Some(MouseButton::Back),
+        event::MouseButton::Other(2 | 4 | 9 | 0x114) => Some(MouseButton::Forward),
+        event::MouseButton::Other(_) => None,
+    }
+}
+
+//protocol scroll delta for a winit wheel event
+//both use positive y for scrolling up, but winit's positive x scrolls left so it is flipped
+pub fn scroll_from_winit(delta: MouseScrollDelta) -> ScrollDelta {
+    match delta {
+        MouseScrollDelta::LineDelta(x, y) => ScrollDelta::Lines {
+            x: (-x * SCROLL_STEPS_PER_LINE as f32).round() as i32,
+            y: (y * SCROLL_STEPS_PER_LINE as f32).round() as i32,
+        },
+        MouseScrollDelta::PixelDelta(pos) => ScrollDelta::Pixels {
+            x: (-pos.x).round() as i32,
+            y: pos.y.round() as i32,
+        },
+    }
+}
@@ -0,0 +1,222 @@
+//trust on first use: server certificate fingerprints remembered per address, ssh known_hosts style
+//one "host:port fingerprint" line per server, # starts a comment
+use std::{
+    error::Error,
+    fs::{ self, OpenOptions },
+    io::{ self, BufRead, IsTerminal, Write },
+    path::Path,
+};
+use rustls::ClientConnection;
+use sha2::{ Digest, Sha256 };
+use common::fingerprint::{ fingerprint_hex, parse_fingerprint };
+use crate::connect::split_host_port;
+
+//the same server is found whether it was written as Example.com:7878 or example.com:7878
+fn host_key(address: &str) -> Result<String, String> {
+    let (host, port) = split_host_port(address)?;
+    let host = host.to_ascii_lowercase();
+    Ok(if host.contains(':') { format!("[{host}]:{port}") } else { format!("{host}:{port}") })
+}
+
+//a remembered server and where it is in the file
+struct KnownHost {
+    line: usize,
+    fingerprint: [u8; 32],
+}
+
+//what is stored for host, a missing file knows no hosts
+fn lookup(path: &Path, host: &str) -> Result<Option<KnownHost>, Box<dyn Error>> {
+    let file = match fs::File::open(path) {
+        Ok(file) => file,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(format!("Failed to open known hosts {}: {e}", path.display()).into()),
+    };
+    for (index, line) in io::BufReader::new(file).lines().enumerate() {
+        let line = line?;
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let mut fields = line.split_whitespace();
+        let (Some(name), Some(fingerprint)) = (fields.next(), fields.next()) else {
+            return Err(format!("Invalid line {} in known hosts {}, expected host:port fingerprint", index + 1, path.display()).into());
+        };
+        //host names are written lower case, but a line added by hand may not be
+        if !name.eq_ignore_ascii_case(host) {
+            continue;
+        }
+        let fingerprint = parse_fingerprint(fingerprint)
+            .ok_or_else(|| format!("Invalid fingerprint on line {} in known hosts {}", index + 1, path.display()))?;
+        return Ok(Some(KnownHost { line: index + 1, fingerprint }));
+    }
+    Ok(None)
+}
+
+fn remember(path: &Path, host: &str, fingerprint: &[u8; 32]) -> Result<(), Box<dyn Error>> {
+    if let Some(dir) = path.parent() && !dir.as_os_str().is_empty() {
+        fs::create_dir_all(dir)?;
+    }
+    let mut file = OpenOptions::new().create(true).append(true).open(path)
+        .map_err(|e| format!("Failed to write known hosts {}: {e}", path.display()))?;
+    writeln!(file, "{host} {}", fingerprint_hex(fingerprint))?;
+    Ok(())
+}
+
+//ask before trusting a server for the first time, without a terminal there is nobody to ask
+fn confirm(host: &str, fingerprint: &[u8; 32], path: &Path) -> Result<bool, Box<dyn Error>> {
+    println!("First connection to {host}, its certificate fingerprint is");
+    println!("  SHA256 {}", fingerprint_hex(fingerprint));
+    if !io::stdin().is_terminal() {
+        return Err(format!(
+            "{host} isn't in known hosts {}, connect once from a terminal or add the line \"{host} {}\" after checking the fingerprint on the server",
+            path.display(),
+            fingerprint_hex(fingerprint),
+        ).into());
+    }
+    print!("Trust it and remember it in {}? [y/N] ", path.display());
+    io::stdout().flush()?;
+    let mut answer = String::new();
+    io::stdin().read_line(&mut answer)?;
+    Ok(matches!(answer.trim(), "y" | "Y" | "yes"))
+}
+
+//fingerprint remembered for address, None if this is the first connection to it
+pub fn known_fingerprint(path: &Path, address: &str) -> Result<Option<[u8; 32]>, Box<dyn Error>> {
+    Ok(lookup(path, &host_key(address)?)?.map(|known| known.fingerprint))
+}
+
+//check the certificate the server presented against the one remembered for address, remembering it the first time
+//runs right after the TLS handshake, before anything is sent to the server
+pub fn check(path: &Path, address: &str, conn: &ClientConnection) -> Result<(), Box<dyn Error>> {
+    let cert = conn.peer_certificates().and_then(|certs| certs.first())
+        .ok_or("Server didn't present a certificate")?;
+    let fingerprint: [u8; 32] = Sha256::digest(cert.as_ref()).into();
+    check_fingerprint(path, address, &fingerprint, confirm)
+}
+
+//confirm is asked whether to trust a server that isn't known yet
+fn check_fingerprint(path: &Path, address: &str, fingerprint: &[u8; 32], confirm: impl FnOnce(&str, &[u8; 32], &Path) -> Result<bool, Box<dyn Error>>) -> Result<(), Box<dyn Error>> {
+    let host = host_key(address)?;
+
+    match lookup(path, &host)? {
+        Some(known) if known.fingerprint == *fingerprint => Ok(()),
+        Some(known) => Err(format!(
+            "The certificate of {host} has CHANGED, someone may be intercepting the connection\n  \
+             known:  SHA256 {}\n  \
+             server: SHA256 {}\n\
+             If the server's certificate was replaced on purpose, remove line {} from {} and connect again",
+            fingerprint_hex(&known.fingerprint),
+            fingerprint_hex(fingerprint),
+            known.line,
+            path.display(),
+        ).into()),
+        None => {
+            if !confirm(&host, fingerprint, path)? {
+                return Err(format!("Not connecting to {host}, its certificate wasn't trusted").into());
+            }
+            remember(path, &host, fingerprint)?;
+            println!("Added {host} to {}", path.display());
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+    use tempfile::TempDir;
+    use super::*;
+
+    const FIRST: [u8; 32] = [0x11; 32];
+    const SECOND: [u8; 32] = [0x22; 32];
+
+    //a known hosts file in a directory of its own for each test, removed with it when the test ends
+    struct TempFile {
+        path: PathBuf,
+        _dir: TempDir,
+    }
+
+    impl TempFile {
+        fn new(name: &str, contents: Option<&str>) -> Self {
+            let dir = tempfile::tempdir().unwrap();
+            let path = dir.path().join(name);
+            if let Some(contents) = contents {
+                fs::write(&path, contents).unwrap();
+            }
+            TempFile { path, _dir: dir }
+        }
+    }
+
+    fn trust(_: &str, _: &[u8; 32], _: &Path) -> Result<bool, Box<dyn Error>> {
+        Ok(true)
+    }
+
+    fn refuse(_: &str, _: &[u8; 32], _: &Path) -> Result<bool, Box<dyn Error>> {
+        Ok(false)
+    }
+
+    fn never_asked(host: &str, _: &[u8; 32], _: &Path) -> Result<bool, Box<dyn Error>> {
+        panic!("asked about {host}, which is already known")
+    }
+
+    #[test]
+    fn hosts_are_found_whatever_case_and_with_comments_and_blank_lines() {
+        let contents = format!(
+            "# remembered servers\n\n[::1]:7878 {}\n  Example.com:7878   {}  \nother.net:1 {}\n",
+            fingerprint_hex(&FIRST),
+            fingerprint_hex(&SECOND).replace(':', "").to_ascii_lowercase(),
+            fingerprint_hex(&FIRST),
+        );
+        let file = TempFile::new("lookup", Some(&contents));
+        assert_eq!(known_fingerprint(&file.path, "[::1]:7878").unwrap(), Some(FIRST));
+        assert_eq!(known_fingerprint(&file.path, "EXAMPLE.com:7878").unwrap(), Some(SECOND));
+        assert_eq!(known_fingerprint(&file.path, "example.com:7879").unwrap(), None);
+        assert_eq!(lookup(&file.path, "example.com:7878").unwrap().unwrap().line, 4);
+    }
+
+    #[test]
+    fn a_missing_file_knows_no_hosts() {
+        let file = TempFile::new("missing", None);
+        assert_eq!(known_fingerprint(&file.path, "example.com:7878").unwrap(), None);
+    }
+
+    #[test]
+    fn malformed_lines_are_errors() {
+        let file = TempFile::new("no_fingerprint", Some("example.com:7878\n"));
+        assert!(known_fingerprint(&file.path, "example.com:7878").unwrap_err().to_string().contains("line 1"));
+        let file = TempFile::new("bad_fingerprint", Some("# ok\nexample.com:7878 AB:CD\n"));
+        assert!(known_fingerprint(&file.path, "example.com:7878").unwrap_err().to_string().contains("line 2"));
+        assert!(known_fingerprint(&file.path, "example.com").is_err());
+    }
+
+    #[test]
+    fn first_use_is_remembered_once_trusted() {
+        let file = TempFile::new("first_use", None);
+        check_fingerprint(&file.path, "Example.com:7878", &FIRST, trust).unwrap();
+        assert_eq!(fs::read_to_string(&file.path).unwrap(), format!("example.com:7878 {}\n", fingerprint_hex(&FIRST)));
+        //from then on it is checked without asking
+        check_fingerprint(&file.path, "example.com:7878", &FIRST, never_asked).unwrap();
+
+        check_fingerprint(&file.path, "[::1]:7878", &SECOND, trust).unwrap();
+        assert_eq!(known_fingerprint(&file.path, "[::1]:7878").unwrap(), Some(SECOND));
+        assert_eq!(known_fingerprint(&file.path, "example.com:7878").unwrap(), Some(FIRST));
+    }
+
+    #[test]
+    fn an_untrusted_server_is_refused_and_not_remembered() {
+        let file = TempFile::new("refused", None);
+        assert!(check_fingerprint(&file.path, "example.com:7878", &FIRST, refuse).is_err());
+        assert_eq!(known_fingerprint(&file.path, "example.com:7878").unwrap(), None);
+    }
+
+    #[test]
+    fn a_changed_certificate_is_refused_and_the_known_one_kept() {
+        let contents = format!("example.com:7878 {}\n", fingerprint_hex(&FIRST));
+        let file = TempFile::new("changed", Some(&contents));
+        let err = check_fingerprint(&file.path, "example.com:7878", &SECOND, never_asked).unwrap_err().to_string();
+        assert!(err.contains("CHANGED"));
+        assert!(err.contains(&fingerprint_hex(&FIRST)) && err.contains(&fingerprint_hex(&SECOND)));
+        assert!(err.contains("remove line 1"));
+        assert_eq!(fs::read_to_string(&file.path).unwrap(), contents);
+    }
+}
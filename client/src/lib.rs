@@ -3,10 +3,32 @@ use std::error::Error;
 
 pub mod tcp_server;
 mod client_tls;
+mod config;
+mod connect;
+mod known_hosts;
 mod message_type_handlers;
+mod keymap;
 
-//load client tls config and run server
+//parse the command line, then list profiles or load client tls config and connect
 pub fn run() -> Result<(), Box<dyn Error>> {
-    let cfg = client_tls::load_client_config()?;
-    tcp_server::run(cfg)
+    match config::load()? {
+        config::Action::List { path, profiles } => {
+            config::print_profiles(&path, &profiles);
+            Ok(())
+        }
+        config::Action::Connect(settings) => {
+            let identity = settings.client_identity.as_ref().map(|(cert, key)| (cert.as_path(), key.as_path()));
+            let cfg = match &settings.trust {
+                config::Trust::Ca(ca) => client_tls::load_client_config(ca, identity)?,
+                config::Trust::KnownHosts(path) => {
+                    let pinned = known_hosts::known_fingerprint(path, &settings.address)?;
+                    if identity.is_some() && pinned.is_none() {
+                        println!("Not presenting the client certificate to a server that isn't in known hosts yet, connect again once it is trusted");
+                    }
+                    client_tls::load_tofu_config(identity, pinned)?
+                }
+            };
+            tcp_server::run(cfg, settings)
+        }
+    }
 }
\ No newline at end of file
@@ -1,33 +1,44 @@
 use std::error::Error;
 use std::time::Instant;
 use image::{ImageBuffer, Rgba, imageops::resize, imageops::FilterType};
+use common::{
+    protocol::ErrorMessage,
+    handshake::SessionConfig,
+    input::{ KeyEvent, MouseButton, ScrollDelta },
+    clipboard::ClipboardContent,
+    clipboard_sync::ClipboardSync,
+};
+use crate::config::ScalingMode;
 
 
-pub fn handle_text(payload: &[u8]) -> Result<(), Box<dyn Error>>  {
-    println!("Text message: {:?}", String::from_utf8_lossy(payload));
+pub fn handle_text(text: &str) -> Result<(), Box<dyn Error>>  {
+    println!("Text message: {:?}", text);
 
     Ok(())
 }
 
-pub fn handle_connect(_payload: &[u8]) -> Result<(), Box<dyn Error>>  {
-    println!("Client connected (connect message)");
+pub fn handle_session_config(config: &SessionConfig) -> Result<(), Box<dyn Error>>  {
+    println!(
+        "Session established: protocol {}, codec {:?}, max {}x{}, capabilities {:#x}",
+        config.version, config.codec, config.max_width, config.max_height, config.capabilities.0
+    );
 
     Ok(())
 }
 
-pub fn handle_disconnect(_payload: &[u8]) -> Result<(), Box<dyn Error>>  {
+pub fn handle_disconnect() -> Result<(), Box<dyn Error>>  {
     println!("Client requested disconnect");
 
     Ok(())
 }
 
-pub fn handle_error(payload: &[u8]) -> Result<(), Box<dyn Error>>  {
-    println!("Error message: {:?}", String::from_utf8_lossy(payload));
+pub fn handle_error(err: &ErrorMessage) -> Result<(), Box<dyn Error>>  {
+    println!("Error message: {err}");
 
     Ok(())
 }
 
-pub fn handle_frame_full(width: u32, height: u32, payload: &[u8], pixels: &mut pixels::Pixels) -> Result<(), Box<dyn Error>> {
+pub fn handle_frame_full(width: u32, height: u32, payload: &[u8], pixels: &mut pixels::Pixels, scaling: ScalingMode) -> Result<(), Box<dyn Error>> {
     //get the current display area size
     let t0 = Instant::now();
     let extent = pixels.texture().size();
@@ -36,12 +47,25 @@ pub fn handle_frame_full(width: u32, height: u32, payload: &[u8], pixels: &mut p
     let img = ImageBuffer::<Rgba<u8>, _>::from_raw(width, height, payload.to_vec())
         .ok_or("Invalid frame buffer")?;
 
-    //scale it to fit the actual display surface
-    let scaled = resize(&img, extent.width, extent.height, FilterType::Triangle);
+    //scale it to the part of the display surface the scaling mode gives it
+    let (vx, vy, vw, vh) = scaling.viewport((width, height), (extent.width, extent.height));
+    let scaled = resize(&img, vw, vh, FilterType::Triangle);
 
     //write directly into pixel buffer
     let frame = pixels.frame_mut();
-    frame.copy_from_slice(&scaled);
+    if (vw, vh) == (extent.width, extent.height) {
+        frame.copy_from_slice(&scaled);
+    } else {
+        //clear the borders then copy the picture in row by row
+        for pixel in frame.chunks_exact_mut(4) {
+            pixel.copy_from_slice(&[0, 0, 0, 255]);
+        }
+        let row_len = vw as usize * 4;
+        for (y, row) in scaled.chunks_exact(row_len).enumerate() {
+            let start = ((vy as usize + y) * extent.width as usize + vx as usize) * 4;
+            frame[start..start + row_len].copy_from_slice(row);
+        }
+    }
     println!("Frame handler timer: {}ms", t0.elapsed().as_millis());
     Ok(())
 }
@@ -107,68 +131,59 @@ pub fn handle_cursor_shape(payload: &[u8]) -> Result<(), Box<dyn Error>>  {
     Ok(())
 }
 
-pub fn handle_cursor_pos(payload: &[u8]) -> Result<(), Box<dyn Error>>  {
-    if payload.len() == 8 {
-        let x = u32::from_be_bytes(payload[0..4].try_into().unwrap());
-        let y = u32::from_be_bytes(payload[4..8].try_into().unwrap());
-        println!("Cursor moved to ({x}, {y})");
-    } else {
-        println!("Invalid cursor pos payload");
-    }
+pub fn handle_cursor_pos(x: u32, y: u32) -> Result<(), Box<dyn Error>>  {
+    println!("Cursor moved to ({x}, {y})");
 
     Ok(())
 }
 
-pub fn handle_resize(payload: &[u8]) -> Result<(), Box<dyn Error>>  {
-    if payload.len() == 8 {
-        let w = u32::from_be_bytes(payload[0..4].try_into().unwrap());
-        let h = u32::from_be_bytes(payload[4..8].try_into().unwrap());
-        println!("Resize request: {w}x{h}");
-    } else {
-        println!("Invalid resize payload");
-    }
+pub fn handle_resize(w: u32, h: u32) -> Result<(), Box<dyn Error>>  {
+    println!("Remote screen is {w}x{h}");
 
     Ok(())
 }
 
-pub fn handle_key_down(payload: &[u8]) -> Result<(), Box<dyn Error>>  {
-    println!("Key down: {:?}", payload);
+pub fn handle_key_down(event: &KeyEvent) -> Result<(), Box<dyn Error>>  {
+    println!("Key down: {:?}", event);
 
     Ok(())
 }
 
-pub fn handle_key_up(payload: &[u8]) -> Result<(), Box<dyn Error>>  {
-    println!("Key up: {:?}", payload);
+pub fn handle_key_up(event: &KeyEvent) -> Result<(), Box<dyn Error>>  {
+    println!("Key up: {:?}", event);
 
     Ok(())
 }
 
-pub fn handle_mouse_move(payload: &[u8]) -> Result<(), Box<dyn Error>>  {
-    println!("Mouse move: {:?}", payload);
+pub fn handle_mouse_move(x: u32, y: u32) -> Result<(), Box<dyn Error>>  {
+    println!("Mouse move: x={}, y={}", x, y);
 
     Ok(())
 }
 
-pub fn handle_mouse_down(payload: &[u8]) -> Result<(), Box<dyn Error>>  {
-    println!("Mouse down: {:?}", payload);
+pub fn handle_mouse_down(button: MouseButton) -> Result<(), Box<dyn Error>>  {
+    println!("Mouse down: {:?}", button);
 
     Ok(())
 }
 
-pub fn handle_mouse_up(payload: &[u8]) -> Result<(), Box<dyn Error>>  {
-    println!("Mouse up: {:?}", payload);
+pub fn handle_mouse_up(button: MouseButton) -> Result<(), Box<dyn Error>>  {
+    println!("Mouse up: {:?}", button);
 
     Ok(())
 }
 
-pub fn handle_mouse_scroll(payload: &[u8]) -> Result<(), Box<dyn Error>>  {
-    println!("Mouse scroll: {:?}", payload);
+pub fn handle_mouse_scroll(delta: ScrollDelta) -> Result<(), Box<dyn Error>>  {
+    println!("Mouse scroll: {:?}", delta);
 
     Ok(())
 }
 
-pub fn handle_clipboard(payload: &[u8]) -> Result<(), Box<dyn Error>>  {
-    println!("Clipboard data: {:?}", String::from_utf8_lossy(payload));
+pub fn handle_clipboard(clipboard: Option<&ClipboardSync>, content: ClipboardContent) -> Result<(), Box<dyn Error>>  {
+    println!("Clipboard data: {} bytes", content.len());
 
+    if let Some(clipboard) = clipboard {
+        clipboard.apply(content);
+    }
     Ok(())
 }
\ No newline at end of file
@@ -1,35 +1,51 @@
-use common::message_type::MessageType;
+use common::{
+    protocol::{ Message, MessageEncoder, MessageDecoder, DecodeError },
+    handshake::{ Hello, SessionConfig, Codec, Capabilities },
+    input::KeyEvent,
+    clipboard::{ ClipboardPolicy, DEFAULT_MAX_CLIPBOARD_LEN },
+    clipboard_sync::ClipboardSync,
+};
 use std::{
-    process,
-    net::TcpStream,
-    io::{ Write, Read, },
+    io::{ self, Write, Read, IsTerminal },
     error::Error,
     sync::{ Arc, mpsc },
     time::{ Instant, Duration },
-    env,
 };
 use rustls::{
     ClientConfig,
-    ClientConnection,
     Stream,
-    pki_types::ServerName,
  };
 use winit::{
     event_loop::{ EventLoopBuilder, ControlFlow, EventLoopProxy },
-    event::{ Event, WindowEvent },
+    event::{ Event, WindowEvent, ElementState, Ime, ModifiersState },
     window::WindowBuilder,
+    dpi::PhysicalSize,
  };
 use pixels::{ SurfaceTexture, Pixels, PixelsBuilder, wgpu, };
-use crate::{ message_type_handlers, };
+use crate::{ message_type_handlers, keymap, connect, known_hosts, config::{ ConnectConfig, Resolution, Trust } };
 use lz4_flex::decompress_size_prepended;
 use openh264::decoder::Decoder;
 use openh264::formats::YUVSource;
 
+//how long to wait for the server to answer the Connect hello
+const HELLO_TIMEOUT: Duration = Duration::from_secs(10);
+//how long to wait for the server to check a password
+const AUTH_TIMEOUT: Duration = Duration::from_secs(30);
+//extra time on top of what the server says approval can take, for the answer to arrive
+const APPROVAL_MARGIN: Duration = Duration::from_secs(5);
+//how often the server is told how much video has arrived, when it asks for that
+const RECEIVE_REPORT_INTERVAL: Duration = Duration::from_secs(1);
+//how long to wait for a keyframe the server was asked for before asking again
+const KEYFRAME_RETRY: Duration = Duration::from_secs(2);
+//how long the window has to keep its size before the server is told, so dragging an edge doesn't restart the stream at every step
+const RESIZE_SETTLE: Duration = Duration::from_millis(250);
 
  #[derive(Debug)]
 pub enum UserEvent {
     NewUpdate,
     Redraw,
+    //the server's screen is now this size
+    RemoteResized { w: u32, h: u32 },
 }
 
 pub enum FrameUpdate {
@@ -37,20 +53,37 @@ pub enum FrameUpdate {
     Delta(Vec<u8>),
 }
 
-fn make_mouse_move_packet(x: u32, y: u32) -> Vec<u8> {
-    let mut packet = Vec::with_capacity(1 + 4 + 8);
+//whether the decoder is stuck until a keyframe arrives, and when the server was last asked for one
+struct KeyframeWait {
+    waiting: bool,
+    asked_at: Option<Instant>,
+}
 
-    // 1 byte: message type
-    packet.push(MessageType::MouseMove.to_u8());
+impl KeyframeWait {
+    //a new decoder, after connecting, has nothing to build on until its first keyframe
+    fn new() -> Self {
+        KeyframeWait { waiting: true, asked_at: None }
+    }
 
-    // 4 bytes: payload length (always 8 for two u32s)
-    packet.extend_from_slice(&8u32.to_be_bytes());
+    fn decoded(&mut self) {
+        self.waiting = false;
+        self.asked_at = None;
+    }
 
-    // 8 bytes: payload (x, y coordinates)
-    packet.extend_from_slice(&x.to_be_bytes());
-    packet.extend_from_slice(&y.to_be_bytes());
+    //the stream broke, true if the server should be asked for a keyframe now
+    fn lost(&mut self) -> bool {
+        self.waiting = true;
+        self.should_ask()
+    }
 
-    packet
+    //true while waiting if the server hasn't been asked yet, or was asked too long ago
+    fn should_ask(&mut self) -> bool {
+        if !self.waiting || self.asked_at.is_some_and(|at| at.elapsed() < KEYFRAME_RETRY) {
+            return false;
+        }
+        self.asked_at = Some(Instant::now());
+        true
+    }
 }
 
 fn yuv420p_to_rgba_with_stride(
@@ -90,57 +123,55 @@ fn yuv420p_to_rgba_with_stride(
     out
 }
 
-//to run on local host SERVER_ADDR=127.0.0.1:7878 cargo run --release -p client
-//to run on vm at home comment out other _address vars  and change connection_address to vm_work_address.clone()
-//to run on vm at work comment out other _address vars  and change connection_address to vm_work_address.clone()
-//to run on desktop comment out other _address vars and change connection_address to home_desktop_address.clone()
-pub fn run(tls_config: Arc<ClientConfig>) -> Result<(), Box<dyn Error>> {
-    let home_desktop_address = "192.168.50.105:7878".to_string();
-    let vm_home_address = "192.168.50.209:7878".to_string();
-    let vm_work_address = "10.176.7.73:7878".to_string();
-    //allow for server address override by calling "SERVER_ADDR=<address> cargo run -p client"
-    let connection_address = env::var("SERVER_ADDR").unwrap_or(home_desktop_address.clone());
-    println!("Connecting to server at {}", connection_address);
+//to run on local host cargo run --release -p client -- connect 127.0.0.1:7878
+//to connect to a saved profile cargo run --release -p client -- connect <profile>, cargo run --release -p client -- list shows them
+pub fn run(tls_config: Arc<ClientConfig>, settings: ConnectConfig) -> Result<(), Box<dyn Error>> {
+    let connection_address = settings.address.clone();
+    match &settings.profile {
+        Some(profile) => println!("Connecting to server at {} (profile {})", connection_address, profile),
+        None => println!("Connecting to server at {}", connection_address),
+    }
+    let scaling = settings.scaling;
+    let resolution = settings.resolution;
+
+    //connect and finish the TLS handshake before opening a window so failures are reported straight away
+    let (mut tls_connection, mut tcp) = connect::connect(&connection_address, &settings.server_name, tls_config)?;
+    if let Trust::KnownHosts(path) = &settings.trust {
+        known_hosts::check(path, &connection_address, &tls_connection)?;
+    }
 
     //create the UI, main thread loop
     let event_loop = EventLoopBuilder::<UserEvent>::with_user_event().build();
     //the proxy that allows dispatcher thread to send message to event_loop
     let proxy = event_loop.create_proxy();
 
+    //announce the size of the primary monitor as the largest frame this client wants, capped by the quality setting
+    let monitor = event_loop.primary_monitor()
+        .map(|m| (m.size().width, m.size().height))
+        .unwrap_or((u32::MAX, u32::MAX));
+    let (max_width, max_height) = settings.quality.max_size(monitor);
+    let clipboard = settings.clipboard;
+    let password = settings.password;
+    let mut capabilities = Capabilities::INPUT.union(Capabilities::RECEIVE_REPORTS);
+    if clipboard.enabled() {
+        capabilities = capabilities.union(Capabilities::CLIPBOARD);
+    }
+    let hello = Hello::new(vec![Codec::H264], max_width, max_height, capabilities);
+
     //create the transmitter and reciever for the mpsc channel(message queue) that carries messages of the type FrameUpdate
     let (frame_transmitter, frame_receiver) = mpsc::channel::<FrameUpdate>();
-    let (mouse_transmitter, mouse_receiver) = mpsc::channel::<Vec<u8>>();
+    let (input_transmitter, input_receiver) = mpsc::channel::<Message>();
+    //native is asked for once, before the first frame, a window size only once there is a window
+    if resolution == Resolution::Native {
+        let _ = input_transmitter.send(Message::Resize { w: 0, h: 0 });
+    }
 
     //create thread for dispatcher
     std::thread::spawn(move || {
-        //create tcp connection
-        let addr_str = connection_address.clone();
-        let mut tcp = TcpStream::connect(&addr_str)
-            .unwrap_or_else(|e| {
-                eprintln!("Failed to create TCP connection: {e}");
-                process::exit(1);
-            });
-            tcp.set_nodelay(true).expect("set_nodelay failed");
-            tcp.set_read_timeout(Some(Duration::from_secs(2))).ok();
-            tcp.set_write_timeout(Some(Duration::from_secs(2))).ok();
-
-        //get hostname of server
-        let server_name_str = addr_str.split(':').next().unwrap_or("localhost").to_string();
-        let server_name = ServerName::try_from(server_name_str)
-            .unwrap_or_else(|e| {
-                eprintln!("Invalid server name: {e}");
-                process::exit(1);
-            });
-        //create TLS client state machine
-        let mut tls_connection = ClientConnection::new(tls_config, server_name)
-            .unwrap_or_else(|e| {
-                eprintln!("Failed to create TLS connection: {e}");
-                process::exit(1);
-            });
         //create a TLS stream
         let mut tls = Stream::new(&mut tls_connection, &mut tcp);
 
-        if let Err(e) = dispatcher(&mut tls, frame_transmitter, proxy, mouse_receiver) {
+        if let Err(e) = dispatcher(&mut tls, hello, password, clipboard, frame_transmitter, proxy, input_receiver) {
             eprintln!("Dispatcher error: {e}");
         }
     });
@@ -160,6 +191,8 @@ pub fn run(tls_config: Arc<ClientConfig>) -> Result<(), Box<dyn Error>> {
         .with_title("Remote desktop client")
         .with_inner_size(winit::dpi::LogicalSize::new(width, height))
         .build(&event_loop)?;
+    //lets input methods commit composed text, which is forwarded as text key events
+    window.set_ime_allowed(true);
 
     //get the size of the initial windows drawable area
     let win_size = window.inner_size();
@@ -173,7 +206,7 @@ pub fn run(tls_config: Arc<ClientConfig>) -> Result<(), Box<dyn Error>> {
     //handle_frame_full puts the image into the pixels buffer
     //pixels.render draws whats in the pixels buffer onto the screen
     {
-        message_type_handlers::handle_frame_full(width, height, &first_rgba, &mut pixels)?;
+        message_type_handlers::handle_frame_full(width, height, &first_rgba, &mut pixels, scaling)?;
         pixels.render().unwrap();
     }
 
@@ -181,11 +214,37 @@ pub fn run(tls_config: Arc<ClientConfig>) -> Result<(), Box<dyn Error>> {
     let mut last_frame = Instant::now();
     let mut frame_count = 0u32;
 
+    //size of the server's screen, pointer positions are sent relative to it
+    //until the server says, the first frame's size is the best guess
+    let mut remote = (width, height);
+
+    //window size the server was last told about, and a newer one waiting to settle
+    let mut sent_size = None;
+    let mut pending_size: Option<(PhysicalSize<u32>, Instant)> = None;
+    if resolution == Resolution::Window {
+        pending_size = Some((win_size, Instant::now() - RESIZE_SETTLE));
+    }
+
+    //modifier keys currently held, sent along with every key event
+    let mut modifiers = ModifiersState::empty();
+    //set when the last key pressed has no protocol key, the character it produces is sent as text instead
+    let mut send_next_char = false;
+
     //run eventloop to correctly handle everything
     event_loop.run(move |event, _, control_flow| {
         //tells the event loop to run every 16ms, whether something triggered it or not
         *control_flow = ControlFlow::WaitUntil(Instant::now() + Duration::from_millis(16));
 
+        //the server fits the stream to the window once it has stopped changing size
+        if let Some((size, at)) = pending_size
+            && at.elapsed() >= RESIZE_SETTLE {
+            pending_size = None;
+            if sent_size != Some(size) {
+                sent_size = Some(size);
+                let _ = input_transmitter.send(Message::Resize { w: size.width, h: size.height });
+            }
+        }
+
         //handle all UserEvent types
         match event {
             //handle UserEven::NewUpdates
@@ -201,7 +260,7 @@ pub fn run(tls_config: Arc<ClientConfig>) -> Result<(), Box<dyn Error>> {
 
                 if let Some((w, h, bytes)) = latest {
                     // update one frame, once
-                    if let Err(e) = message_type_handlers::handle_frame_full(w, h, &bytes, &mut pixels) {
+                    if let Err(e) = message_type_handlers::handle_frame_full(w, h, &bytes, &mut pixels, scaling) {
                         eprintln!("Frame full error: {e}");
                     }
                     window.request_redraw();
@@ -211,6 +270,19 @@ pub fn run(tls_config: Arc<ClientConfig>) -> Result<(), Box<dyn Error>> {
             Event::UserEvent(UserEvent::Redraw) => {
                 window.request_redraw();
             }
+            Event::UserEvent(UserEvent::RemoteResized { w, h }) => {
+                if w == 0 || h == 0 {
+                    return;
+                }
+                let aspect = |(w, h): (u32, u32)| w as f64 / h as f64;
+                let reshape = (aspect((w, h)) - aspect(remote)).abs() > 0.01;
+                remote = (w, h);
+                //keep the window as wide as it is and give it the screen's new shape, the pixels buffer follows in Resized
+                if reshape {
+                    let size = window.inner_size();
+                    window.set_inner_size(PhysicalSize::new(size.width, (size.width as u64 * h as u64 / w as u64) as u32));
+                }
+            }
             //window.request_redraw() calls this to redraw window
             Event::RedrawRequested(_) => {
                 //draw the scaled frame
@@ -233,47 +305,82 @@ pub fn run(tls_config: Arc<ClientConfig>) -> Result<(), Box<dyn Error>> {
 
                 //handle cursor being moved
                 WindowEvent::CursorMoved { position, .. } => {
-                    //gets the DPI scale(how many physical pixels each logical pixel is)
-                    let scale = window.scale_factor() as f64;
-                    //position.x & y are in logical pixels, * by scale to convert to physical pixels
-                    let pos_x_px = position.x * scale;
-                    let pos_y_px = position.y * scale;
+                    //winit gives the position in physical pixels already, the same units as the viewport
+                    let (pos_x_px, pos_y_px) = (position.x, position.y);
 
-                    //window size in physical pixels
+                    //part of the window the picture is drawn in, in physical pixels
                     let win_size = window.inner_size();
-                    let win_w = win_size.width as f64;
-                    let win_h = win_size.height as f64;
+                    let (view_x, view_y, view_w, view_h) = scaling.viewport(remote, (win_size.width, win_size.height));
+                    let (view_x, view_y, view_w, view_h) = (view_x as f64, view_y as f64, view_w as f64, view_h as f64);
 
-                    //(pos_x_px / win_w) gives normalized mouse x (0.0 to 1.0) * by width to get actual x position
+                    //((pos_x_px - view_x) / view_w) gives normalized mouse x (0.0 to 1.0) * by width to get actual x position
                     //round ensures no fractions clamp ensures its within valid boundaries
-                    let sx = ((pos_x_px / win_w) * width as f64)
+                    let sx = (((pos_x_px - view_x) / view_w) * remote.0 as f64)
                         .round()
-                        .clamp(0.0, (width - 1) as f64) as u32;
-                    //(pos_y_px / win_h) gives normalized mouse y (0.0 to 1.0) * by height to get actual y position
+                        .clamp(0.0, (remote.0 - 1) as f64) as u32;
+                    //((pos_y_px - view_y) / view_h) gives normalized mouse y (0.0 to 1.0) * by height to get actual y position
                     //round ensures no fractions clamp ensures its within valid boundaries
-                    let sy = ((pos_y_px / win_h) * height as f64)
+                    let sy = (((pos_y_px - view_y) / view_h) * remote.1 as f64)
                         .round()
-                        .clamp(0.0, (height - 1) as f64) as u32;
-                    //builds proper mouse packet to be sent to dispatcher
-                    let packet = make_mouse_move_packet(sx, sy);
-                    //sends to dispatcher
-                    let _ = mouse_transmitter.send(packet);
+                        .clamp(0.0, (remote.1 - 1) as f64) as u32;
+                    //sends mouse move to dispatcher
+                    let _ = input_transmitter.send(Message::MouseMove { x: sx, y: sy });
+                },
+                WindowEvent::MouseInput { state, button, .. } => {
+                    let Some(button) = keymap::button_from_winit(button) else {
+                        return;
+                    };
+                    let msg = match state {
+                        ElementState::Pressed => Message::MouseDown(button),
+                        ElementState::Released => Message::MouseUp(button),
+                    };
+                    let _ = input_transmitter.send(msg);
+                },
+                WindowEvent::MouseWheel { delta, .. } => {
+                    let _ = input_transmitter.send(Message::MouseScroll(keymap::scroll_from_winit(delta)));
+                },
+                WindowEvent::ModifiersChanged(state) => {
+                    modifiers = state;
                 },
-                WindowEvent::Resized(size) => {
-                    //if size actually changed resize the surface and the pixels buffer then redraw the window
-                    if size.width > 0 && size.height > 0 {
-                            pixels.resize_surface(size.width, size.height).unwrap();
-                            pixels.resize_buffer(size.width, size.height).unwrap();
-                            window.request_redraw();
+                //forward physical keys so shortcuts and held keys behave like a local keyboard
+                WindowEvent::KeyboardInput { input, .. } => {
+                    let key = input.virtual_keycode.and_then(keymap::key_from_winit);
+                    let Some(key) = key else {
+                        send_next_char = input.state == ElementState::Pressed;
+                        return;
+                    };
+                    send_next_char = false;
+                    let event = KeyEvent::key(key, keymap::modifiers_from_winit(modifiers));
+                    let msg = match input.state {
+                        ElementState::Pressed => Message::KeyDown(event),
+                        ElementState::Released => Message::KeyUp(event),
+                    };
+                    let _ = input_transmitter.send(msg);
+                },
+                //characters from keys the protocol can't name (dead keys, layout specific symbols) go as text
+                WindowEvent::ReceivedCharacter(c) => {
+                    if send_next_char && !c.is_control() {
+                        let _ = input_transmitter.send(Message::KeyDown(KeyEvent::Text(c.to_string())));
                     }
+                    send_next_char = false;
+                },
+                WindowEvent::Ime(Ime::Commit(text)) if !text.is_empty() => {
+                    let _ = input_transmitter.send(Message::KeyDown(KeyEvent::Text(text)));
                 },
-                WindowEvent::ScaleFactorChanged { new_inner_size, .. } => {
-                    //if size actually changed resize the surface and the pixels buffer then redraw the window
-                    if new_inner_size.width > 0 && new_inner_size.height > 0 {
-                        pixels.resize_surface(new_inner_size.width, new_inner_size.height).unwrap();
-                        //pixels.resize_buffer(new_inner_size.width, new_inner_size.height).unwrap();
-                        window.request_redraw();
+                //if size actually changed resize the surface and the pixels buffer then redraw the window
+                WindowEvent::Resized(size) if size.width > 0 && size.height > 0 => {
+                    pixels.resize_surface(size.width, size.height).unwrap();
+                    pixels.resize_buffer(size.width, size.height).unwrap();
+                    window.request_redraw();
+                    if resolution == Resolution::Window {
+                        pending_size = Some((size, Instant::now()));
                     }
+                },
+                //if size actually changed resize the surface and the pixels buffer then redraw the window
+                WindowEvent::ScaleFactorChanged { new_inner_size, .. } if new_inner_size.width > 0 && new_inner_size.height > 0 => {
+                    pixels.resize_surface(new_inner_size.width, new_inner_size.height).unwrap();
+                    //pixels.resize_buffer(new_inner_size.width, new_inner_size.height).unwrap();
+                    window.request_redraw();
                 }
                 _ => {}
             },
@@ -282,117 +389,207 @@ pub fn run(tls_config: Arc<ClientConfig>) -> Result<(), Box<dyn Error>> {
     });
 }
 
-fn dispatcher<T: Read + Write>(tls: &mut T, frame_transmitter: mpsc::Sender<FrameUpdate>, proxy: EventLoopProxy<UserEvent>, mouse_receiver: mpsc::Receiver<Vec<u8>>) -> Result<(), Box<dyn Error>> {
+//send our hello and wait for the server to pick the session configuration
+//password: what to answer if the server asks for one, prompted for on the terminal when None
+fn handshake<T: Read + Write>(tls: &mut T, encoder: &mut MessageEncoder, decoder: &mut MessageDecoder, hello: Hello, password: Option<String>) -> Result<SessionConfig, Box<dyn Error>> {
+    encoder.write(tls, &Message::Connect(hello))?;
+    let mut password = Some(password);
+    let mut timeout = HELLO_TIMEOUT;
+    //the server may ask for a password and then have someone approve the connection before it sends the session
+    loop {
+        match decoder.read_message(tls, timeout)? {
+            Message::SessionConfig(config) => return Ok(config),
+            Message::Error(err) => return Err(format!("Server rejected connection: {err}").into()),
+            Message::AuthRequired if let Some(password) = password.take() => {
+                let password = match password {
+                    Some(password) => password,
+                    None if io::stdin().is_terminal() => rpassword::prompt_password("Server password: ")?,
+                    None => return Err("Server needs a password, set password in the profile or CLIENT_PASSWORD".into()),
+                };
+                encoder.write(tls, &Message::Auth(password))?;
+                //the server answers wrong passwords slowly on purpose
+                timeout = AUTH_TIMEOUT;
+            }
+            Message::AwaitingApproval { timeout_secs } => {
+                println!("Waiting for the server to approve the connection (up to {timeout_secs} seconds)");
+                timeout = Duration::from_secs(timeout_secs as u64) + APPROVAL_MARGIN;
+            }
+            other => return Err(format!("Expected SessionConfig from server, got {:?}", other.message_type()).into()),
+        }
+    }
+}
+
+fn dispatcher<T: Read + Write>(tls: &mut T, hello: Hello, password: Option<String>, clipboard_policy: ClipboardPolicy, frame_transmitter: mpsc::Sender<FrameUpdate>, proxy: EventLoopProxy<UserEvent>, input_receiver: mpsc::Receiver<Message>) -> Result<(), Box<dyn Error>> {
     //create h264 decoder and buffer for frame
     let mut decoder = Decoder::new().unwrap();
     let mut h264_buffer: Vec<u8> = Vec::new();
-
-    loop {
-        //send outgoing mouse packets
-        while let Ok(packet) = mouse_receiver.try_recv() {
-            tls.write_all(&packet)?;
+    //framing for messages to and from the server
+    let mut encoder = MessageEncoder::new();
+    let mut message_decoder = MessageDecoder::new();
+
+    //nothing else is sent or expected until the server accepts the session
+    let session = handshake(tls, &mut encoder, &mut message_decoder, hello, password)?;
+    message_type_handlers::handle_session_config(&session)?;
+
+    //only watch the clipboard if the server agreed to sync it, stopped when the dispatcher returns
+    let clipboard = if session.capabilities.contains(Capabilities::CLIPBOARD) {
+        match ClipboardSync::start(clipboard_policy.client_sends(), clipboard_policy.server_sends(), DEFAULT_MAX_CLIPBOARD_LEN) {
+            Ok(clipboard) => Some(clipboard),
+            Err(e) => {
+                eprintln!("Clipboard sync unavailable: {e}");
+                None
+            }
         }
+    } else {
+        None
+    };
+
+    //a view-only session has no input, it is dropped here rather than refused by the server
+    let input = session.capabilities.contains(Capabilities::INPUT);
+    //video received so far, reported back so the server can adapt the stream to the link
+    let receive_reports = session.capabilities.contains(Capabilities::RECEIVE_REPORTS);
+    let (mut received_frames, mut received_bytes) = (0u64, 0u64);
+    let mut next_report = Instant::now() + RECEIVE_REPORT_INTERVAL;
+    let mut keyframe = KeyframeWait::new();
 
-        let mut header = [0u8; 5];
-        //read the message header
-        if let Err(e) = tls.read_exact(&mut header) {
-            if e.kind() == std::io::ErrorKind::UnexpectedEof {
-                println!("Server disconnected");
-                break Ok(());
+    loop {
+        //send outgoing keyboard and mouse messages, and window sizes which go whether or not input does
+        while let Ok(msg) = input_receiver.try_recv() {
+            if input || matches!(msg, Message::Resize { .. }) {
+                encoder.write(tls, &msg)?;
             }
-            continue;
+        }
+        //send local clipboard changes
+        while let Some(content) = clipboard.as_ref().and_then(ClipboardSync::next_change) {
+            encoder.write(tls, &Message::Clipboard(content))?;
+        }
+        if receive_reports && Instant::now() >= next_report {
+            encoder.write(tls, &Message::ReceiveReport { frames: received_frames, bytes: received_bytes })?;
+            next_report = Instant::now() + RECEIVE_REPORT_INTERVAL;
         }
 
-        //parse the message header into message type and payload length
-        let msg_type = MessageType::from_u8(header[0]);
-        let payload_len = u32::from_be_bytes([header[1], header[2], header[3], header[4]]);
-        //read the message payload
-        let mut payload = vec![0u8; payload_len as usize];
-        tls.read_exact(&mut payload)?;
-
-
-        match msg_type {
-            MessageType::FrameDelta => {
-                //if there is a frame decode it
-                if let Ok(Some(frame)) = decoder.decode(&payload) {
-                    //get dimentions and yuv planes
-                    let w = frame.width() as usize;
-                    let h = frame.height() as usize;
-                    let y = frame.y();
-                    let u = frame.u();
-                    let v = frame.v();
-
-                    //get the strides for each plane
-                    let y_stride = y.len() / h;
-                    let u_stride = u.len() / ((h + 1) / 2);
-                    let v_stride = v.len() / ((h + 1) / 2);
-
-                    //convert yuv to rgba with proper strides
-                    let rgba = yuv420p_to_rgba_with_stride(y, u, v, w, h, y_stride, u_stride, v_stride);
-                    //send the frame to the main thread frame receiver
-                    frame_transmitter.send(FrameUpdate::Full { w: w as u32, h: h as u32, bytes: rgba }).ok();
-                    //prompt event loop to handle new frame
-                    let _ = proxy.send_event(UserEvent::NewUpdate);
+        //pull the next complete message out of the stream, reading more when only part of one has arrived
+        let msg = match message_decoder.next_message() {
+            Ok(Some(msg)) => msg,
+            Ok(None) => {
+                match message_decoder.read_from(tls) {
+                    Ok(0) => {
+                        println!("Server disconnected");
+                        break Ok(());
+                    }
+                    Ok(_) => {}
+                    //nothing arrived before the read timeout, go round again to send any input
+                    Err(e) if matches!(e.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut | io::ErrorKind::Interrupted) => {}
+                    Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => {
+                        println!("Server disconnected");
+                        break Ok(());
+                    }
+                    //a reset or a TLS error won't go away by reading again
+                    Err(e) => return Err(format!("Lost the connection to the server: {e}").into()),
+                }
+                continue;
+            }
+            //an oversized header means the stream is out of sync, nothing after it can be trusted
+            Err(e @ DecodeError::Oversized { .. }) => return Err(Box::new(e)),
+            Err(e) => {
+                eprintln!("Dropping malformed message: {e}");
+                //it may have been a frame, the ones after it can't be decoded properly without it
+                if keyframe.lost() {
+                    println!("Asking the server for a keyframe");
+                    encoder.write(tls, &Message::RequestKeyframe)?;
                 }
+                continue;
+            }
+        };
+
+        //result of decoding a frame, when the message was one
+        let mut decoded = None;
+        match msg {
+            Message::FrameDelta(payload) => {
+                received_bytes += payload.len() as u64;
+                decoded = Some(decode_frame(&mut decoder, &payload, &frame_transmitter, &proxy));
             },
-            MessageType::FrameEnd => {
+            Message::FrameEnd => {
+                received_frames += 1;
                 if h264_buffer.is_empty() {
                     continue;
                 }
                 //if anything is left in the buffer try to decode it
-                match decoder.decode(&h264_buffer) {
-                    Ok(Some(frame)) => {
-                        //get dimentions and yuv planes
-                        let w = frame.width() as usize;
-                        let h = frame.height() as usize;
-                        let y = frame.y();
-                        let u = frame.u();
-                        let v = frame.v();
-
-                        //get the strides for each plane
-                        let y_stride = y.len() / h;
-                        let u_stride = u.len() / ((h + 1) / 2);
-                        let v_stride = v.len() / ((h + 1) / 2);
-
-                        //convert yuv to rgba with proper strides
-                        let rgba = yuv420p_to_rgba_with_stride(y, u, v, w, h, y_stride, u_stride, v_stride);
-
-                        //send the frame to the main thread frame receiver
-                        frame_transmitter.send(FrameUpdate::Full { w: w as u32, h: h as u32, bytes: rgba }).ok();
-                        //prompt event loop to handle new frame
-                        let _ = proxy.send_event(UserEvent::NewUpdate);
-                    }
-                    //if no complete frame yet just wait for more data
-                    Ok(None) => println!("Decoder waiting for complete frame"),
-                    Err(e) => {
-                        eprintln!("Decode error: {:?}", e);
-                        decoder = Decoder::new().unwrap();
-                    }
-                }
+                decoded = Some(decode_frame(&mut decoder, &h264_buffer, &frame_transmitter, &proxy));
                 //clear the h264 buffer for the next frame
                 h264_buffer.clear();
             },
-            MessageType::FrameFull => {},
-            MessageType::Text => message_type_handlers::handle_text(&payload)?,
-            MessageType::Connect => message_type_handlers::handle_connect(&payload)?,
-            MessageType::Disconnect => message_type_handlers::handle_disconnect(&payload)?,
-            MessageType::Error => message_type_handlers::handle_error(&payload)?,
-            MessageType::CursorShape => message_type_handlers::handle_cursor_shape(&payload)?,
-            MessageType::CursorPos => message_type_handlers::handle_cursor_pos(&payload)?,
-            MessageType::Resize => message_type_handlers::handle_resize(&payload)?,
-
-            MessageType::KeyDown => message_type_handlers::handle_key_down(&payload)?,
-            MessageType::KeyUp => message_type_handlers::handle_key_up(&payload)?,
-            MessageType::MouseMove => message_type_handlers::handle_mouse_move(&payload)?,
-            MessageType::MouseDown => message_type_handlers::handle_mouse_down(&payload)?,
-            MessageType::MouseUp => message_type_handlers::handle_mouse_up(&payload)?,
-            MessageType::MouseScroll => message_type_handlers::handle_mouse_scroll(&payload)?,
-
-            MessageType::Clipboard => message_type_handlers::handle_clipboard(&payload)?,
-
-            MessageType::Unknown(code) => {
-                println!("Unknown message type: {code:#X}, skipping {payload_len} bytes");
+            Message::FrameFull(_) => {},
+            Message::Text(text) => message_type_handlers::handle_text(&text)?,
+            Message::Connect(_) | Message::AuthRequired | Message::Auth(_) | Message::AwaitingApproval { .. } => {}
+            Message::ReceiveReport { .. } | Message::RequestKeyframe => {}
+            Message::SessionConfig(config) => message_type_handlers::handle_session_config(&config)?,
+            Message::Disconnect => message_type_handlers::handle_disconnect()?,
+            Message::Error(err) => message_type_handlers::handle_error(&err)?,
+            Message::CursorShape(payload) => message_type_handlers::handle_cursor_shape(&payload)?,
+            Message::CursorPos { x, y } => message_type_handlers::handle_cursor_pos(x, y)?,
+            Message::Resize { w, h } => {
+                message_type_handlers::handle_resize(w, h)?;
+                let _ = proxy.send_event(UserEvent::RemoteResized { w, h });
+            }
+
+            Message::KeyDown(event) => message_type_handlers::handle_key_down(&event)?,
+            Message::KeyUp(event) => message_type_handlers::handle_key_up(&event)?,
+            Message::MouseMove { x, y } => message_type_handlers::handle_mouse_move(x, y)?,
+            Message::MouseDown(button) => message_type_handlers::handle_mouse_down(button)?,
+            Message::MouseUp(button) => message_type_handlers::handle_mouse_up(button)?,
+            Message::MouseScroll(delta) => message_type_handlers::handle_mouse_scroll(delta)?,
+
+            Message::Clipboard(content) => message_type_handlers::handle_clipboard(clipboard.as_ref(), content)?,
+
+            Message::Unknown { code, payload } => {
+                println!("Unknown message type: {code:#X}, skipping {} bytes", payload.len());
+            }
+        }
+
+        let ask = match decoded {
+            None => false,
+            Some(Ok(true)) => {
+                keyframe.decoded();
+                false
             }
+            //no picture, which only matters while the decoder is waiting for a keyframe
+            Some(Ok(false)) => keyframe.should_ask(),
+            Some(Err(e)) => {
+                eprintln!("Decode error: {:?}", e);
+                decoder = Decoder::new().unwrap();
+                keyframe.lost()
+            }
+        };
+        if ask {
+            println!("Asking the server for a keyframe");
+            encoder.write(tls, &Message::RequestKeyframe)?;
         }
     }
+}
+
+//decode one frame and hand the picture to the event loop, false if the decoder had no picture to show
+fn decode_frame(decoder: &mut Decoder, data: &[u8], frame_transmitter: &mpsc::Sender<FrameUpdate>, proxy: &EventLoopProxy<UserEvent>) -> Result<bool, openh264::Error> {
+    let Some(frame) = decoder.decode(data)? else {
+        return Ok(false);
+    };
+    //get dimentions and yuv planes
+    let w = frame.width() as usize;
+    let h = frame.height() as usize;
+    let y = frame.y();
+    let u = frame.u();
+    let v = frame.v();
+
+    //get the strides for each plane
+    let y_stride = y.len() / h;
+    let u_stride = u.len() / ((h + 1) / 2);
+    let v_stride = v.len() / ((h + 1) / 2);
+
+    //convert yuv to rgba with proper strides
+    let rgba = yuv420p_to_rgba_with_stride(y, u, v, w, h, y_stride, u_stride, v_stride);
+    //send the frame to the main thread frame receiver
+    frame_transmitter.send(FrameUpdate::Full { w: w as u32, h: h as u32, bytes: rgba }).ok();
+    //prompt event loop to handle new frame
+    let _ = proxy.send_event(UserEvent::NewUpdate);
+    Ok(true)
 }
\ No newline at end of file
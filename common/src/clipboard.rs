@@ -0,0 +1,195 @@
+//clipboard contents exchanged between client and server, each entry is tagged with its MIME type
+use std::fmt;
+use clap::ValueEnum;
+use serde::Deserialize;
+use crate::protocol::{ PayloadReader, DecodeError, put_string };
+
+//largest clipboard change either side sends or accepts unless configured otherwise
+pub const DEFAULT_MAX_CLIPBOARD_LEN: usize = 8 * 1024 * 1024;
+
+//formats the clipboard sync understands, anything else is carried by its MIME type so it still decodes
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum ClipboardFormat {
+    Text,
+    Html,
+    Png,
+    //Catch all others
+    Unknown(String),
+}
+
+impl ClipboardFormat {
+    pub fn from_mime(mime: &str) -> Self {
+        match mime {
+            "text/plain;charset=utf-8" | "text/plain" => ClipboardFormat::Text,
+            "text/html" => ClipboardFormat::Html,
+            "image/png" => ClipboardFormat::Png,
+            other => ClipboardFormat::Unknown(other.to_string()),
+        }
+    }
+
+    pub fn mime(&self) -> &str {
+        match self {
+            ClipboardFormat::Text => "text/plain;charset=utf-8",
+            ClipboardFormat::Html => "text/html",
+            ClipboardFormat::Png => "image/png",
+            ClipboardFormat::Unknown(mime) => mime,
+        }
+    }
+}
+
+//one representation of the clipboard, text formats are utf-8
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ClipboardEntry {
+    pub format: ClipboardFormat,
+    pub data: Vec<u8>,
+}
+
+//everything copied in one go, html is sent together with its plain text so either can be pasted
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct ClipboardContent {
+    pub entries: Vec<ClipboardEntry>,
+}
+
+impl ClipboardContent {
+    pub fn text(text: impl Into<String>) -> Self {
+        ClipboardContent { entries: vec![ClipboardEntry { format: ClipboardFormat::Text, data: text.into().into_bytes() }] }
+    }
+
+    pub fn get(&self, format: &ClipboardFormat) -> Option<&[u8]> {
+        self.entries.iter().find(|e| e.format == *format).map(|e| e.data.as_slice())
+    }
+
+    //text entry as a string, None if there isn't one or it isn't utf-8
+    pub fn get_str(&self, format: &ClipboardFormat) -> Option<&str> {
+        self.get(format).and_then(|data| std::str::from_utf8(data).ok())
+    }
+
+    //total size of all entries, what the size cap is checked against
+    pub fn len(&self) -> usize {
+        self.entries.iter().map(|e| e.data.len()).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.iter().all(|e| e.data.is_empty())
+    }
+
+    //entry count, then for each entry its MIME type, a big-endian u32 length and the data
+    pub fn encode(&self, out: &mut Vec<u8>) {
+        let count = self.entries.len().min(u8::MAX as usize);
+        out.push(count as u8);
+        for entry in &self.entries[..count] {
+            put_string(out, entry.format.mime());
+            out.extend_from_slice(&(entry.data.len() as u32).to_be_bytes());
+            out.extend_from_slice(&entry.data);
+        }
+    }
+
+    pub fn decode(r: &mut PayloadReader) -> Result<Self, DecodeError> {
+        let count = r.u8()?;
+        let mut entries = Vec::with_capacity(count as usize);
+        for _ in 0..count {
+            let format = ClipboardFormat::from_mime(&r.string()?);
+            let len = r.u32()? as usize;
+            let data = r.bytes(len)?.to_vec();
+            entries.push(ClipboardEntry { format, data });
+        }
+        Ok(ClipboardContent { entries })
+    }
+}
+
+//which way clipboard changes are allowed to go, each side enforces its own setting
+//the names are the same on the command line and in config files
+#[derive(ValueEnum, Deserialize, Debug, Copy, Clone, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum ClipboardPolicy {
+    Both,
+    ClientToServer,
+    ServerToClient,
+    Off,
+}
+
+impl ClipboardPolicy {
+    pub fn from_name(name: &str) -> Option<Self> {
+        <ClipboardPolicy as ValueEnum>::from_str(name, false).ok()
+    }
+
+    pub fn client_sends(&self) -> bool {
+        matches!(self, ClipboardPolicy::Both | ClipboardPolicy::ClientToServer)
+    }
+
+    pub fn server_sends(&self) -> bool {
+        matches!(self, ClipboardPolicy::Both | ClipboardPolicy::ServerToClient)
+    }
+
+    pub fn enabled(&self) -> bool {
+        *self != ClipboardPolicy::Off
+    }
+}
+
+impl fmt::Display for ClipboardPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.to_possible_value() {
+            Some(value) => write!(f, "{}", value.get_name()),
+            None => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::message_type::MessageType;
+
+    fn round_trip(content: &ClipboardContent) -> ClipboardContent {
+        let mut out = Vec::new();
+        content.encode(&mut out);
+        let mut r = PayloadReader::new(MessageType::Clipboard, &out);
+        let decoded = ClipboardContent::decode(&mut r).unwrap();
+        assert!(r.bytes(1).is_err(), "bytes left over after the clipboard");
+        decoded
+    }
+
+    #[test]
+    fn every_entry_survives_encoding_in_order() {
+        let content = ClipboardContent {
+            entries: vec![
+                ClipboardEntry { format: ClipboardFormat::Html, data: b"<b>hi</b>".to_vec() },
+                ClipboardEntry { format: ClipboardFormat::Text, data: "hi ✓".as_bytes().to_vec() },
+                ClipboardEntry { format: ClipboardFormat::Png, data: vec![0x89, b'P', b'N', b'G'] },
+                ClipboardEntry { format: ClipboardFormat::Unknown("application/x-custom".to_string()), data: Vec::new() },
+            ],
+        };
+        let decoded = round_trip(&content);
+        assert_eq!(decoded, content);
+        assert_eq!(decoded.get_str(&ClipboardFormat::Text), Some("hi ✓"));
+        assert_eq!(decoded.len(), 9 + 6 + 4);
+        assert_eq!(round_trip(&ClipboardContent::default()), ClipboardContent::default());
+    }
+
+    #[test]
+    fn only_the_first_255_entries_are_sent() {
+        let entries: Vec<ClipboardEntry> = (0..300)
+            .map(|n| ClipboardEntry { format: ClipboardFormat::Unknown(format!("application/x-{n}")), data: vec![n as u8] })
+            .collect();
+        let decoded = round_trip(&ClipboardContent { entries: entries.clone() });
+        assert_eq!(decoded.entries, entries[..255]);
+    }
+
+    #[test]
+    fn policies_are_parsed_by_name_and_say_which_way_changes_go() {
+        let policies = [
+            ("both", ClipboardPolicy::Both, true, true),
+            ("client-to-server", ClipboardPolicy::ClientToServer, true, false),
+            ("server-to-client", ClipboardPolicy::ServerToClient, false, true),
+            ("off", ClipboardPolicy::Off, false, false),
+        ];
+        for (name, policy, client_sends, server_sends) in policies {
+            assert_eq!(ClipboardPolicy::from_name(name), Some(policy));
+            assert_eq!(policy.to_string(), name);
+            assert_eq!((policy.client_sends(), policy.server_sends()), (client_sends, server_sends));
+            assert_eq!(policy.enabled(), policy != ClipboardPolicy::Off);
+        }
+        assert_eq!(ClipboardPolicy::from_name("Both"), None);
+        assert_eq!(ClipboardPolicy::from_name("client_to_server"), None);
+    }
+}
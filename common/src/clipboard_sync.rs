@@ -0,0 +1,265 @@
+//keeps the local clipboard in step with the peer's, used by the client and by the one watcher the server shares between sessions
+use std::{
+    error::Error,
+    hash::{ DefaultHasher, Hash, Hasher },
+    io::Cursor,
+    sync::mpsc::{ self, Receiver, RecvTimeoutError, Sender },
+    thread,
+    time::Duration,
+};
+use arboard::{ Clipboard, ImageData };
+use image::{ DynamicImage, ImageFormat, ImageOutputFormat, RgbaImage };
+use crate::clipboard::{ ClipboardContent, ClipboardEntry, ClipboardFormat };
+
+//there is no portable change notification, so the clipboard is polled
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+//what the local clipboard holds, images stay as raw pixels until they need to be sent
+enum Local {
+    Text(String),
+    Html { html: String, text: Option<String> },
+    Image { width: usize, height: usize, rgba: Vec<u8> },
+}
+
+impl Local {
+    fn read(clipboard: &mut Clipboard) -> Option<Local> {
+        if let Ok(image) = clipboard.get_image() {
+            return Some(Local::Image { width: image.width, height: image.height, rgba: image.bytes.into_owned() });
+        }
+        if let Ok(html) = clipboard.get().html() {
+            return Some(Local::Html { html, text: clipboard.get_text().ok() });
+        }
+        clipboard.get_text().ok().map(Local::Text)
+    }
+
+    //identifies the contents without converting them, used to spot changes and our own writes
+    fn fingerprint(&self) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        match self {
+            Local::Text(text) => (0u8, text).hash(&mut hasher),
+            Local::Html { html, text } => (1u8, html, text).hash(&mut hasher),
+            Local::Image { width, height, rgba } => (2u8, width, height, rgba).hash(&mut hasher),
+        }
+        hasher.finish()
+    }
+
+    fn into_content(self) -> Result<ClipboardContent, Box<dyn Error>> {
+        let mut entries = Vec::new();
+        match self {
+            Local::Text(text) => {
+                entries.push(ClipboardEntry { format: ClipboardFormat::Text, data: text.into_bytes() });
+            }
+            Local::Html { html, text } => {
+                entries.push(ClipboardEntry { format: ClipboardFormat::Html, data: html.into_bytes() });
+                if let Some(text) = text {
+                    entries.push(ClipboardEntry { format: ClipboardFormat::Text, data: text.into_bytes() });
+                }
+            }
+            Local::Image { width, height, rgba } => {
+                let image = RgbaImage::from_raw(width as u32, height as u32, rgba)
+                    .ok_or("Clipboard image is smaller than its size")?;
+                let mut png = Cursor::new(Vec::new());
+                DynamicImage::ImageRgba8(image).write_to(&mut png, ImageOutputFormat::Png)?;
+                entries.push(ClipboardEntry { format: ClipboardFormat::Png, data: png.into_inner() });
+            }
+        }
+        Ok(ClipboardContent { entries })
+    }
+}
+
+//put the peer's clipboard on the local one using the richest format we understand
+fn write(clipboard: &mut Clipboard, content: &ClipboardContent) -> Result<(), Box<dyn Error>> {
+    if let Some(png) = content.get(&ClipboardFormat::Png) {
+        let image = image::load_from_memory_with_format(png, ImageFormat::Png)?.to_rgba8();
+        let (width, height) = image.dimensions();
+        clipboard.set_image(ImageData { width: width as usize, height: height as usize, bytes: image.into_raw().into() })?;
+    } else if let Some(html) = content.get_str(&ClipboardFormat::Html) {
+        clipboard.set_html(html, content.get_str(&ClipboardFormat::Text))?;
+    } else if let Some(text) = content.get_str(&ClipboardFormat::Text) {
+        clipboard.set_text(text)?;
+    } else {
+        return Err("no clipboard format we can use".into());
+    }
+    Ok(())
+}
+
+//what the watcher lets through, kept apart from the clipboard itself so it can be tested without one
+struct Gate {
+    send: bool,
+    receive: bool,
+    max_len: usize,
+    //fingerprint of the contents the peer already has, whatever is there at the start was never the peer's to get
+    //writes from the peer set this too so they aren't sent straight back
+    last: Option<u64>,
+}
+
+impl Gate {
+    //whether the peer's clipboard should be put on the local one
+    fn accepts(&self, content: &ClipboardContent) -> bool {
+        if !self.receive {
+            println!("Ignoring clipboard from peer, receiving is turned off");
+            return false;
+        }
+        if content.len() > self.max_len {
+            println!("Ignoring clipboard from peer, {} bytes is over the {} byte limit", content.len(), self.max_len);
+            return false;
+        }
+        true
+    }
+
+    //what the local clipboard holds after the peer's clipboard was written to it
+    fn written(&mut self, fingerprint: Option<u64>) {
+        self.last = fingerprint;
+    }
+
+    //whether the local clipboard changed since it was last seen and the change should be sent
+    fn changed(&mut self, fingerprint: u64) -> bool {
+        if self.last == Some(fingerprint) {
+            return false;
+        }
+        self.last = Some(fingerprint);
+        self.send
+    }
+
+    //whether a change read from the local clipboard is small enough to send
+    fn fits(&self, content: &ClipboardContent) -> bool {
+        if content.len() > self.max_len {
+            println!("Not sending clipboard, {} bytes is over the {} byte limit", content.len(), self.max_len);
+            return false;
+        }
+        true
+    }
+}
+
+//a thread that watches the local clipboard and applies the peer's changes to it
+//the thread stops when this is dropped
+pub struct ClipboardSync {
+    incoming: Sender<ClipboardContent>,
+    changes: Receiver<ClipboardContent>,
+}
+
+impl ClipboardSync {
+    //send: report local changes, receive: apply the peer's changes, both are capped at max_len bytes
+    pub fn start(send: bool, receive: bool, max_len: usize) -> Result<Self, Box<dyn Error>> {
+        let (incoming, incoming_receiver) = mpsc::channel::<ClipboardContent>();
+        let (changes_transmitter, changes) = mpsc::channel::<ClipboardContent>();
+        let (ready_transmitter, ready) = mpsc::channel::<Result<(), String>>();
+
+        thread::Builder::new()
+            .name("clipboard".to_string())
+            .spawn(move || {
+                //the clipboard is opened on the thread that uses it, not every platform lets it move between threads
+                let mut clipboard = match Clipboard::new() {
+                    Ok(clipboard) => clipboard,
+                    Err(e) => {
+                        let _ = ready_transmitter.send(Err(e.to_string()));
+                        return;
+                    }
+                };
+                let _ = ready_transmitter.send(Ok(()));
+
+                let last = Local::read(&mut clipboard).map(|local| local.fingerprint());
+                let mut gate = Gate { send, receive, max_len, last };
+
+                loop {
+                    match incoming_receiver.recv_timeout(POLL_INTERVAL) {
+                        Ok(content) => {
+                            if !gate.accepts(&content) {
+                                continue;
+                            }
+                            if let Err(e) = write(&mut clipboard, &content) {
+                                eprintln!("Failed to set clipboard: {e}");
+                                continue;
+                            }
+                            //read it back since the platform may hand it out slightly differently than it was written
+                            gate.written(Local::read(&mut clipboard).map(|local| local.fingerprint()));
+                        }
+                        Err(RecvTimeoutError::Timeout) => {
+                            let Some(local) = Local::read(&mut clipboard) else {
+                                continue;
+                            };
+                            if !gate.changed(local.fingerprint()) {
+                                continue;
+                            }
+                            let content = match local.into_content() {
+                                Ok(content) => content,
+                                Err(e) => {
+                                    eprintln!("Failed to read clipboard: {e}");
+                                    continue;
+                                }
+                            };
+                            if !gate.fits(&content) {
+                                continue;
+                            }
+                            if changes_transmitter.send(content).is_err() {
+                                return;
+                            }
+                        }
+                        Err(RecvTimeoutError::Disconnected) => return,
+                    }
+                }
+            })?;
+
+        match ready.recv() {
+            Ok(Ok(())) => Ok(ClipboardSync { incoming, changes }),
+            Ok(Err(e)) => Err(format!("Failed to open clipboard: {e}").into()),
+            Err(_) => Err("Clipboard thread exited before opening the clipboard".into()),
+        }
+    }
+
+    //queue the peer's clipboard to be applied locally
+    pub fn apply(&self, content: ClipboardContent) {
+        let _ = self.incoming.send(content);
+    }
+
+    //next local change to send to the peer, None if nothing changed
+    pub fn next_change(&self) -> Option<ClipboardContent> {
+        self.changes.try_recv().ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fingerprint(text: &str) -> u64 {
+        Local::Text(text.to_string()).fingerprint()
+    }
+
+    #[test]
+    fn what_the_peer_wrote_is_not_sent_back() {
+        let mut gate = Gate { send: true, receive: true, max_len: 1024, last: Some(fingerprint("already there")) };
+        //nothing changed since the watcher started
+        assert!(!gate.changed(fingerprint("already there")));
+
+        let from_peer = ClipboardContent::text("from the peer");
+        assert!(gate.accepts(&from_peer));
+        gate.written(Some(fingerprint("from the peer")));
+        assert!(!gate.changed(fingerprint("from the peer")));
+
+        //copying something new locally is sent once
+        assert!(gate.changed(fingerprint("copied here")));
+        assert!(!gate.changed(fingerprint("copied here")));
+        //and copying what the peer sent again counts as a change too
+        assert!(gate.changed(fingerprint("from the peer")));
+    }
+
+    #[test]
+    fn turned_off_directions_let_nothing_through() {
+        let mut gate = Gate { send: false, receive: false, max_len: 1024, last: None };
+        assert!(!gate.accepts(&ClipboardContent::text("from the peer")));
+        assert!(!gate.changed(fingerprint("copied here")));
+        //the change is still remembered, so turning sending on doesn't send what was copied before
+        gate.send = true;
+        assert!(!gate.changed(fingerprint("copied here")));
+    }
+
+    #[test]
+    fn clipboards_over_the_limit_go_neither_way() {
+        let gate = Gate { send: true, receive: true, max_len: 8, last: None };
+        assert!(gate.accepts(&ClipboardContent::text("eight by")));
+        assert!(gate.fits(&ClipboardContent::text("eight by")));
+        assert!(!gate.accepts(&ClipboardContent::text("nine byte")));
+        assert!(!gate.fits(&ClipboardContent::text("nine byte")));
+    }
+}
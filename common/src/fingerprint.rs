@@ -0,0 +1,45 @@
+//SHA-256 certificate fingerprints as people copy them between the server, its config and the client's known hosts
+//written the way openssl x509 -fingerprint -sha256 prints them, AB:CD:...
+
+pub fn fingerprint_hex(fingerprint: &[u8; 32]) -> String {
+    fingerprint.iter().map(|b| format!("{b:02X}")).collect::<Vec<_>>().join(":")
+}
+
+//64 hex digits in either case, the colons between bytes are optional
+pub fn parse_fingerprint(text: &str) -> Option<[u8; 32]> {
+    let hex: String = text.chars().filter(|c| *c != ':').collect();
+    if hex.len() != 64 || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+        return None;
+    }
+    let mut fingerprint = [0u8; 32];
+    for (i, byte) in fingerprint.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&hex[i * 2..i * 2 + 2], 16).ok()?;
+    }
+    Some(fingerprint)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fingerprints_round_trip() {
+        let fingerprint: [u8; 32] = std::array::from_fn(|i| (i * 37 + 5) as u8);
+        let hex = fingerprint_hex(&fingerprint);
+        assert_eq!(hex.len(), 32 * 3 - 1);
+        assert!(hex.starts_with("05:2A:4F:"));
+        assert_eq!(parse_fingerprint(&hex), Some(fingerprint));
+        assert_eq!(parse_fingerprint(&hex.replace(':', "")), Some(fingerprint));
+        assert_eq!(parse_fingerprint(&hex.to_ascii_lowercase()), Some(fingerprint));
+    }
+
+    #[test]
+    fn malformed_fingerprints_are_rejected() {
+        let hex = fingerprint_hex(&[0xAB; 32]);
+        assert_eq!(parse_fingerprint(&hex[..hex.len() - 3]), None);
+        assert_eq!(parse_fingerprint(&format!("{hex}:00")), None);
+        assert_eq!(parse_fingerprint(&hex.replacen("AB", "AG", 1)), None);
+        assert_eq!(parse_fingerprint(&hex.replacen("AB", "+B", 1)), None);
+        assert_eq!(parse_fingerprint(""), None);
+    }
+}
@@ -0,0 +1,269 @@
+//hello exchange sent on Connect, both peers announce what they support and the server picks the session configuration
+//later versions only ever add fields at the end of Hello and SessionConfig, older peers ignore what they don't know
+use crate::protocol::{ PayloadReader, DecodeError, ErrorMessage, ErrorCode };
+
+//oldest and newest protocol versions this build can speak
+pub const MIN_PROTOCOL_VERSION: u16 = 1;
+pub const PROTOCOL_VERSION: u16 = 1;
+
+//video codecs a peer can encode/decode
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Codec {
+    H264,
+    //Catch all others, kept so a newer peer's list still decodes
+    Unknown(u8),
+}
+
+impl Codec {
+    pub fn from_u8(v: u8) -> Self {
+        match v {
+            0x01 => Codec::H264,
+            other => Codec::Unknown(other),
+        }
+    }
+
+    pub fn to_u8(&self) -> u8 {
+        match self {
+            Codec::H264 => 0x01,
+            Codec::Unknown(code) => *code,
+        }
+    }
+}
+
+//optional features a peer supports, stored as bit flags
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub struct Capabilities(pub u32);
+
+impl Capabilities {
+    pub const NONE: Capabilities = Capabilities(0);
+    //remote keyboard and pointer input
+    pub const INPUT: Capabilities = Capabilities(1 << 0);
+    //clipboard sync
+    pub const CLIPBOARD: Capabilities = Capabilities(1 << 1);
+    //the client sends ReceiveReport messages, the server adapts the stream to what gets through
+    pub const RECEIVE_REPORTS: Capabilities = Capabilities(1 << 2);
+
+    pub fn contains(&self, other: Capabilities) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    //capabilities both sides have
+    pub fn intersect(&self, other: Capabilities) -> Capabilities {
+        Capabilities(self.0 & other.0)
+    }
+
+    pub fn union(&self, other: Capabilities) -> Capabilities {
+        Capabilities(self.0 | other.0)
+    }
+
+    pub fn difference(&self, other: Capabilities) -> Capabilities {
+        Capabilities(self.0 & !other.0)
+    }
+}
+
+//what one peer announces in its Connect message
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Hello {
+    pub min_version: u16,
+    pub max_version: u16,
+    //supported codecs in order of preference
+    pub codecs: Vec<Codec>,
+    //largest frame the peer wants to send or receive
+    pub max_width: u32,
+    pub max_height: u32,
+    pub capabilities: Capabilities,
+}
+
+impl Hello {
+    //hello for this build with the given codecs, size limit and capabilities
+    pub fn new(codecs: Vec<Codec>, max_width: u32, max_height: u32, capabilities: Capabilities) -> Self {
+        Hello {
+            min_version: MIN_PROTOCOL_VERSION,
+            max_version: PROTOCOL_VERSION,
+            codecs,
+            max_width,
+            max_height,
+            capabilities,
+        }
+    }
+
+    pub fn encode(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(&self.min_version.to_be_bytes());
+        out.extend_from_slice(&self.max_version.to_be_bytes());
+        out.push(self.codecs.len().min(u8::MAX as usize) as u8);
+        for codec in self.codecs.iter().take(u8::MAX as usize) {
+            out.push(codec.to_u8());
+        }
+        out.extend_from_slice(&self.max_width.to_be_bytes());
+        out.extend_from_slice(&self.max_height.to_be_bytes());
+        out.extend_from_slice(&self.capabilities.0.to_be_bytes());
+    }
+
+    pub fn decode(r: &mut PayloadReader) -> Result<Hello, DecodeError> {
+        let min_version = r.u16()?;
+        let max_version = r.u16()?;
+        let codec_count = r.u8()? as usize;
+        let codecs = r.bytes(codec_count)?.iter().map(|c| Codec::from_u8(*c)).collect();
+        let hello = Hello {
+            min_version,
+            max_version,
+            codecs,
+            max_width: r.u32()?,
+            max_height: r.u32()?,
+            capabilities: Capabilities(r.u32()?),
+        };
+        //a newer peer may append fields after these, they are skipped so it can still be understood
+        r.rest();
+        Ok(hello)
+    }
+}
+
+//what the server decided for this session, sent back in reply to the client's hello
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SessionConfig {
+    pub version: u16,
+    pub codec: Codec,
+    //largest frame the server will send
+    pub max_width: u32,
+    pub max_height: u32,
+    //features enabled for this session
+    pub capabilities: Capabilities,
+}
+
+impl SessionConfig {
+    pub fn encode(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(&self.version.to_be_bytes());
+        out.push(self.codec.to_u8());
+        out.extend_from_slice(&self.max_width.to_be_bytes());
+        out.extend_from_slice(&self.max_height.to_be_bytes());
+        out.extend_from_slice(&self.capabilities.0.to_be_bytes());
+    }
+
+    pub fn decode(r: &mut PayloadReader) -> Result<SessionConfig, DecodeError> {
+        let config = SessionConfig {
+            version: r.u16()?,
+            codec: Codec::from_u8(r.u8()?),
+            max_width: r.u32()?,
+            max_height: r.u32()?,
+            capabilities: Capabilities(r.u32()?),
+        };
+        //fields a newer server appends are skipped like in Hello
+        r.rest();
+        Ok(config)
+    }
+}
+
+//pick the session configuration for a client/server pair, or the error to send back if they can't talk
+pub fn negotiate(client: &Hello, server: &Hello) -> Result<SessionConfig, ErrorMessage> {
+    //highest version inside both ranges
+    let version = client.max_version.min(server.max_version);
+    if version < client.min_version.max(server.min_version) {
+        return Err(ErrorMessage::new(
+            ErrorCode::VersionMismatch,
+            format!(
+                "client speaks protocol {}-{}, server speaks {}-{}",
+                client.min_version, client.max_version, server.min_version, server.max_version
+            ),
+        ));
+    }
+
+    //first codec the client prefers that the server also has
+    let codec = client.codecs.iter()
+        .copied()
+        .find(|c| !matches!(c, Codec::Unknown(_)) && server.codecs.contains(c))
+        .ok_or_else(|| ErrorMessage::new(
+            ErrorCode::UnsupportedCodec,
+            format!("no common codec, client offers {:?}, server offers {:?}", client.codecs, server.codecs),
+        ))?;
+
+    if client.max_width == 0 || client.max_height == 0 {
+        return Err(ErrorMessage::new(
+            ErrorCode::Protocol,
+            format!("invalid max resolution {}x{}", client.max_width, client.max_height),
+        ));
+    }
+
+    Ok(SessionConfig {
+        version,
+        codec,
+        max_width: client.max_width.min(server.max_width),
+        max_height: client.max_height.min(server.max_height),
+        capabilities: client.capabilities.intersect(server.capabilities),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ message_type::MessageType, protocol::Message };
+
+    fn hello(min_version: u16, max_version: u16, capabilities: Capabilities) -> Hello {
+        Hello { min_version, max_version, codecs: vec![Codec::H264], max_width: 1920, max_height: 1080, capabilities }
+    }
+
+    #[test]
+    fn highest_shared_version_is_picked() {
+        let session = negotiate(&hello(1, 3, Capabilities::NONE), &hello(2, 5, Capabilities::NONE)).unwrap();
+        assert_eq!(session.version, 3);
+    }
+
+    #[test]
+    fn versions_that_dont_overlap_are_a_mismatch() {
+        let err = negotiate(&hello(3, 4, Capabilities::NONE), &hello(1, 2, Capabilities::NONE)).unwrap_err();
+        assert_eq!(err.code, ErrorCode::VersionMismatch);
+        let err = negotiate(&hello(1, 1, Capabilities::NONE), &hello(2, 2, Capabilities::NONE)).unwrap_err();
+        assert_eq!(err.code, ErrorCode::VersionMismatch);
+    }
+
+    #[test]
+    fn only_shared_capabilities_are_enabled() {
+        let client = hello(1, 1, Capabilities::INPUT.union(Capabilities::RECEIVE_REPORTS).union(Capabilities(1 << 20)));
+        let server = hello(1, 1, Capabilities::INPUT.union(Capabilities::CLIPBOARD));
+        assert_eq!(negotiate(&client, &server).unwrap().capabilities, Capabilities::INPUT);
+        let server = hello(1, 1, Capabilities::NONE);
+        assert_eq!(negotiate(&client, &server).unwrap().capabilities, Capabilities::NONE);
+    }
+
+    #[test]
+    fn codec_and_size_are_agreed_on() {
+        let mut client = hello(1, 1, Capabilities::NONE);
+        client.codecs = vec![Codec::Unknown(7), Codec::H264];
+        client.max_width = 3840;
+        client.max_height = 720;
+        let session = negotiate(&client, &hello(1, 1, Capabilities::NONE)).unwrap();
+        assert_eq!((session.codec, session.max_width, session.max_height), (Codec::H264, 1920, 720));
+
+        client.codecs = vec![Codec::Unknown(7)];
+        assert_eq!(negotiate(&client, &hello(1, 1, Capabilities::NONE)).unwrap_err().code, ErrorCode::UnsupportedCodec);
+
+        let mut client = hello(1, 1, Capabilities::NONE);
+        client.max_height = 0;
+        assert_eq!(negotiate(&client, &hello(1, 1, Capabilities::NONE)).unwrap_err().code, ErrorCode::Protocol);
+    }
+
+    #[test]
+    fn fields_appended_by_a_newer_peer_are_skipped() {
+        let sent = hello(1, 2, Capabilities::INPUT);
+        let mut payload = Vec::new();
+        sent.encode(&mut payload);
+        payload.extend_from_slice(&[0xAB; 6]);
+        assert_eq!(Message::decode(MessageType::Connect, &payload), Ok(Message::Connect(sent)));
+
+        let config = SessionConfig { version: 2, codec: Codec::H264, max_width: 800, max_height: 600, capabilities: Capabilities::CLIPBOARD };
+        let mut payload = Vec::new();
+        config.encode(&mut payload);
+        payload.push(1);
+        assert_eq!(Message::decode(MessageType::SessionConfig, &payload), Ok(Message::SessionConfig(config)));
+    }
+
+    #[test]
+    fn hello_cut_short_is_truncated() {
+        let mut payload = Vec::new();
+        hello(1, 1, Capabilities::NONE).encode(&mut payload);
+        payload.truncate(payload.len() - 1);
+        assert!(matches!(
+            Message::decode(MessageType::Connect, &payload),
+            Err(DecodeError::Truncated { msg_type: MessageType::Connect, .. })
+        ));
+    }
+}
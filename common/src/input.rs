@@ -0,0 +1,211 @@
+//keyboard and mouse input sent from the client to the server, independent of either side's platform
+use crate::protocol::{ PayloadReader, DecodeError };
+
+//defines Key with its wire code, the code is the USB HID keyboard usage id so both sides share one numbering
+macro_rules! keys {
+    ($($name:ident = $code:expr,)*) => {
+        #[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+        pub enum Key {
+            $($name,)*
+            //Catch all others, kept so keys a newer peer knows about still decode
+            Unknown(u16),
+        }
+
+        impl Key {
+            pub fn from_u16(v: u16) -> Self {
+                match v {
+                    $($code => Key::$name,)*
+                    other => Key::Unknown(other),
+                }
+            }
+
+            pub fn to_u16(&self) -> u16 {
+                match self {
+                    $(Key::$name => $code,)*
+                    Key::Unknown(code) => *code,
+                }
+            }
+        }
+    };
+}
+
+keys! {
+    A = 0x04, B = 0x05, C = 0x06, D = 0x07, E = 0x08, F = 0x09, G = 0x0A, H = 0x0B,
+    I = 0x0C, J = 0x0D, K = 0x0E, L = 0x0F, M = 0x10, N = 0x11, O = 0x12, P = 0x13,
+    Q = 0x14, R = 0x15, S = 0x16, T = 0x17, U = 0x18, V = 0x19, W = 0x1A, X = 0x1B,
+    Y = 0x1C, Z = 0x1D,
+    Digit1 = 0x1E, Digit2 = 0x1F, Digit3 = 0x20, Digit4 = 0x21, Digit5 = 0x22,
+    Digit6 = 0x23, Digit7 = 0x24, Digit8 = 0x25, Digit9 = 0x26, Digit0 = 0x27,
+    Enter = 0x28, Escape = 0x29, Backspace = 0x2A, Tab = 0x2B, Space = 0x2C,
+    Minus = 0x2D, Equal = 0x2E, LeftBracket = 0x2F, RightBracket = 0x30, Backslash = 0x31,
+    Semicolon = 0x33, Quote = 0x34, Grave = 0x35, Comma = 0x36, Period = 0x37, Slash = 0x38,
+    CapsLock = 0x39,
+    F1 = 0x3A, F2 = 0x3B, F3 = 0x3C, F4 = 0x3D, F5 = 0x3E, F6 = 0x3F,
+    F7 = 0x40, F8 = 0x41, F9 = 0x42, F10 = 0x43, F11 = 0x44, F12 = 0x45,
+    PrintScreen = 0x46, ScrollLock = 0x47, Pause = 0x48,
+    Insert = 0x49, Home = 0x4A, PageUp = 0x4B, Delete = 0x4C, End = 0x4D, PageDown = 0x4E,
+    Right = 0x4F, Left = 0x50, Down = 0x51, Up = 0x52,
+    NumLock = 0x53, KpDivide = 0x54, KpMultiply = 0x55, KpMinus = 0x56, KpPlus = 0x57, KpEnter = 0x58,
+    Kp1 = 0x59, Kp2 = 0x5A, Kp3 = 0x5B, Kp4 = 0x5C, Kp5 = 0x5D,
+    Kp6 = 0x5E, Kp7 = 0x5F, Kp8 = 0x60, Kp9 = 0x61, Kp0 = 0x62, KpDecimal = 0x63,
+    //the extra key next to left shift on ISO keyboards
+    IntlBackslash = 0x64,
+    Menu = 0x65, KpEqual = 0x67,
+    Mute = 0x7F, VolumeUp = 0x80, VolumeDown = 0x81,
+    LeftCtrl = 0xE0, LeftShift = 0xE1, LeftAlt = 0xE2, LeftMeta = 0xE3,
+    RightCtrl = 0xE4, RightShift = 0xE5, RightAlt = 0xE6, RightMeta = 0xE7,
+}
+
+impl Key {
+    //the modifier this key holds down, None for ordinary keys
+    pub fn modifier(&self) -> Option<Modifiers> {
+        match self {
+            Key::LeftShift | Key::RightShift => Some(Modifiers::SHIFT),
+            Key::LeftCtrl | Key::RightCtrl => Some(Modifiers::CTRL),
+            Key::LeftAlt | Key::RightAlt => Some(Modifiers::ALT),
+            Key::LeftMeta | Key::RightMeta => Some(Modifiers::META),
+            _ => None,
+        }
+    }
+}
+
+//modifier keys held on the client when a key event happened, stored as bit flags
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub struct Modifiers(pub u8);
+
+impl Modifiers {
+    pub const NONE: Modifiers = Modifiers(0);
+    pub const SHIFT: Modifiers = Modifiers(1 << 0);
+    pub const CTRL: Modifiers = Modifiers(1 << 1);
+    pub const ALT: Modifiers = Modifiers(1 << 2);
+    //windows/command/super key
+    pub const META: Modifiers = Modifiers(1 << 3);
+
+    pub fn contains(&self, other: Modifiers) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    pub fn union(&self, other: Modifiers) -> Modifiers {
+        Modifiers(self.0 | other.0)
+    }
+}
+
+//payload kinds of a KeyEvent
+const KEY_EVENT_KEY: u8 = 0x00;
+const KEY_EVENT_TEXT: u8 = 0x01;
+
+//payload of KeyDown and KeyUp
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum KeyEvent {
+    //a physical key changed state
+    Key { key: Key, modifiers: Modifiers },
+    //text from an IME or a character with no key of its own, typed as is
+    //only meaningful in KeyDown since there is nothing to release
+    Text(String),
+}
+
+impl KeyEvent {
+    pub fn key(key: Key, modifiers: Modifiers) -> Self {
+        KeyEvent::Key { key, modifiers }
+    }
+
+    //kind, then key code and modifiers or the text as UTF-8
+    pub fn encode(&self, out: &mut Vec<u8>) {
+        match self {
+            KeyEvent::Key { key, modifiers } => {
+                out.push(KEY_EVENT_KEY);
+                out.extend_from_slice(&key.to_u16().to_be_bytes());
+                out.push(modifiers.0);
+            }
+            KeyEvent::Text(text) => {
+                out.push(KEY_EVENT_TEXT);
+                out.extend_from_slice(text.as_bytes());
+            }
+        }
+    }
+
+    pub fn decode(r: &mut PayloadReader) -> Result<Self, DecodeError> {
+        match r.u8()? {
+            KEY_EVENT_KEY => Ok(KeyEvent::Key {
+                key: Key::from_u16(r.u16()?),
+                modifiers: Modifiers(r.u8()?),
+            }),
+            KEY_EVENT_TEXT => Ok(KeyEvent::Text(r.rest_string()?)),
+            other => Err(r.invalid("key event kind", other as u32)),
+        }
+    }
+}
+
+//mouse buttons, sent as the payload of MouseDown and MouseUp
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum MouseButton {
+    Left,
+    Right,
+    Middle,
+    //the side buttons browsers use for back and forward
+    Back,
+    Forward,
+    //Catch all others, kept so buttons a newer peer knows about still decode
+    Unknown(u8),
+}
+
+impl MouseButton {
+    pub fn from_u8(v: u8) -> Self {
+        match v {
+            0x00 => MouseButton::Left,
+            0x01 => MouseButton::Right,
+            0x02 => MouseButton::Middle,
+            0x03 => MouseButton::Back,
+            0x04 => MouseButton::Forward,
+            other => MouseButton::Unknown(other),
+        }
+    }
+
+    pub fn to_u8(&self) -> u8 {
+        match self {
+            MouseButton::Left => 0x00,
+            MouseButton::Right => 0x01,
+            MouseButton::Middle => 0x02,
+            MouseButton::Back => 0x03,
+            MouseButton::Forward => 0x04,
+            MouseButton::Unknown(code) => *code,
+        }
+    }
+}
+
+//line deltas are sent in 1/120ths of a line so smooth scrolling wheels and touchpads keep their precision
+pub const SCROLL_STEPS_PER_LINE: i32 = 120;
+
+//payload kinds of a ScrollDelta
+const SCROLL_LINES: u8 = 0x00;
+const SCROLL_PIXELS: u8 = 0x01;
+
+//payload of MouseScroll, positive y scrolls up and positive x scrolls right
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ScrollDelta {
+    //from a mouse wheel, in SCROLL_STEPS_PER_LINE steps per line
+    Lines { x: i32, y: i32 },
+    //from a touchpad or other precise device, in screen pixels
+    Pixels { x: i32, y: i32 },
+}
+
+impl ScrollDelta {
+    //kind, then x and y as big-endian i32
+    pub fn encode(&self, out: &mut Vec<u8>) {
+        let (kind, x, y) = match *self {
+            ScrollDelta::Lines { x, y } => (SCROLL_LINES, x, y),
+            ScrollDelta::Pixels { x, y } => (SCROLL_PIXELS, x, y),
+        };
+        out.push(kind);
+        out.extend_from_slice(&x.to_be_bytes());
+        out.extend_from_slice(&y.to_be_bytes());
+    }
+
+    pub fn decode(r: &mut PayloadReader) -> Result<Self, DecodeError> {
+        match r.u8()? {
+            SCROLL_LINES => Ok(ScrollDelta::Lines { x: r.i32()?, y: r.i32()? }),
+            SCROLL_PIXELS => Ok(ScrollDelta::Pixels { x: r.i32()?, y: r.i32()? }),
+            other => Err(r.invalid("scroll kind", other as u32)),
+        }
+    }
+}
@@ -1 +1,7 @@
 pub mod message_type;
+pub mod protocol;
+pub mod handshake;
+pub mod input;
+pub mod clipboard;
+pub mod clipboard_sync;
+pub mod fingerprint;
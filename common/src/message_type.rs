@@ -7,6 +7,10 @@ pub enum MessageType {
     Connect     = 0x02,
     Disconnect  = 0x03,
     Error       = 0x04,
+    SessionConfig = 0x05,
+    AuthRequired = 0x06,
+    Auth        = 0x07,
+    AwaitingApproval = 0x08,
 
     // Display / Frames
     FrameFull   = 0x10,
@@ -15,6 +19,8 @@ pub enum MessageType {
     CursorShape = 0x13,
     CursorPos   = 0x14,
     Resize      = 0x15,
+    ReceiveReport = 0x16,
+    RequestKeyframe = 0x17,
 
     // Input
     KeyDown     = 0x20,
@@ -39,6 +45,10 @@ impl MessageType {
             0x02 => MessageType::Connect,
             0x03 => MessageType::Disconnect,
             0x04 => MessageType::Error,
+            0x05 => MessageType::SessionConfig,
+            0x06 => MessageType::AuthRequired,
+            0x07 => MessageType::Auth,
+            0x08 => MessageType::AwaitingApproval,
 
             0x10 => MessageType::FrameFull,
             0x11 => MessageType::FrameDelta,
@@ -47,6 +57,8 @@ impl MessageType {
             0x13 => MessageType::CursorShape,
             0x14 => MessageType::CursorPos,
             0x15 => MessageType::Resize,
+            0x16 => MessageType::ReceiveReport,
+            0x17 => MessageType::RequestKeyframe,
 
             0x20 => MessageType::KeyDown,
             0x21 => MessageType::KeyUp,
@@ -67,6 +79,10 @@ impl MessageType {
             MessageType::Connect     => 0x02,
             MessageType::Disconnect  => 0x03,
             MessageType::Error       => 0x04,
+            MessageType::SessionConfig => 0x05,
+            MessageType::AuthRequired => 0x06,
+            MessageType::Auth        => 0x07,
+            MessageType::AwaitingApproval => 0x08,
 
             MessageType::FrameFull   => 0x10,
             MessageType::FrameDelta  => 0x11,
@@ -74,6 +90,8 @@ impl MessageType {
             MessageType::CursorShape => 0x13,
             MessageType::CursorPos   => 0x14,
             MessageType::Resize      => 0x15,
+            MessageType::ReceiveReport => 0x16,
+            MessageType::RequestKeyframe => 0x17,
 
             MessageType::KeyDown     => 0x20,
             MessageType::KeyUp       => 0x21,
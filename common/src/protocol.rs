@@ -0,0 +1,689 @@
+//typed protocol messages and the framing that puts them on the wire
+//every message is a 5 byte header (1 byte message type, 4 byte big-endian payload length) followed by the payload
+use std::{
+    fmt,
+    error::Error,
+    io::{ self, Read, Write, ErrorKind },
+    time::{ Duration, Instant },
+};
+use crate::{
+    message_type::MessageType,
+    handshake::{ Hello, SessionConfig },
+    input::{ KeyEvent, MouseButton, ScrollDelta },
+    clipboard::ClipboardContent,
+};
+
+//size of the type + length header in front of every payload
+pub const HEADER_LEN: usize = 5;
+//largest payload the decoder will accept before treating the stream as corrupt
+pub const MAX_PAYLOAD_LEN: usize = 64 * 1024 * 1024;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Message {
+    // Session
+    Text(String),
+    Connect(Hello),
+    Disconnect,
+    Error(ErrorMessage),
+    SessionConfig(SessionConfig),
+    //server wants a password or token before it answers the Connect hello
+    AuthRequired,
+    //the client's password or token, only ever sent inside TLS
+    Auth(String),
+    //someone at the server has to approve the connection, it is denied if they haven't within timeout_secs
+    AwaitingApproval { timeout_secs: u32 },
+
+    // Display / Frames
+    FrameFull(Vec<u8>),
+    FrameDelta(Vec<u8>),
+    FrameEnd,
+    CursorShape(Vec<u8>),
+    CursorPos { x: u32, y: u32 },
+    //server to client, the size of the captured screen, pointer positions are relative to it
+    //client to server, the size of the viewer's window in physical pixels for the stream to fit, 0x0 asks for the screen 1:1
+    Resize { w: u32, h: u32 },
+    //client to server, video received since the session started, sent about once a second so the server can tell how far behind the link is
+    ReceiveReport { frames: u64, bytes: u64 },
+    //client to server, the client can't decode what it has and needs the next frame to be a keyframe
+    RequestKeyframe,
+
+    // Input
+    KeyDown(KeyEvent),
+    KeyUp(KeyEvent),
+    MouseMove { x: u32, y: u32 },
+    MouseDown(MouseButton),
+    MouseUp(MouseButton),
+    MouseScroll(ScrollDelta),
+
+    // Clipboard
+    Clipboard(ClipboardContent),
+
+    //Catch all others, payload is kept so it can be skipped or forwarded
+    Unknown { code: u8, payload: Vec<u8> },
+}
+
+//reason codes carried by Error messages so the peer can react without parsing the text
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ErrorCode {
+    Generic,
+    //peer sent something that doesn't fit the protocol state
+    Protocol,
+    //no protocol version both sides speak
+    VersionMismatch,
+    //no codec both sides support
+    UnsupportedCodec,
+    //server already has as many clients as it allows
+    ServerFull,
+    //client isn't allowed to connect, e.g. its certificate isn't on the server's allow-list
+    Unauthorized,
+    //the server's approval policy turned the client away or nobody approved it in time
+    Denied,
+    //the session isn't permitted to do what the message asked, the session carries on
+    Forbidden,
+
+    //Catch all others
+    Unknown(u16),
+}
+
+impl ErrorCode {
+    pub fn from_u16(v: u16) -> Self {
+        match v {
+            0x0000 => ErrorCode::Generic,
+            0x0001 => ErrorCode::Protocol,
+            0x0002 => ErrorCode::VersionMismatch,
+            0x0003 => ErrorCode::UnsupportedCodec,
+            0x0004 => ErrorCode::ServerFull,
+            0x0005 => ErrorCode::Unauthorized,
+            0x0006 => ErrorCode::Denied,
+            0x0007 => ErrorCode::Forbidden,
+            other => ErrorCode::Unknown(other),
+        }
+    }
+
+    pub fn to_u16(&self) -> u16 {
+        match self {
+            ErrorCode::Generic => 0x0000,
+            ErrorCode::Protocol => 0x0001,
+            ErrorCode::VersionMismatch => 0x0002,
+            ErrorCode::UnsupportedCodec => 0x0003,
+            ErrorCode::ServerFull => 0x0004,
+            ErrorCode::Unauthorized => 0x0005,
+            ErrorCode::Denied => 0x0006,
+            ErrorCode::Forbidden => 0x0007,
+            ErrorCode::Unknown(code) => *code,
+        }
+    }
+}
+
+//payload of an Error message, a reason code plus a human readable description
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ErrorMessage {
+    pub code: ErrorCode,
+    pub message: String,
+}
+
+impl ErrorMessage {
+    pub fn new(code: ErrorCode, message: impl Into<String>) -> Self {
+        ErrorMessage { code, message: message.into() }
+    }
+}
+
+impl fmt::Display for ErrorMessage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}: {}", self.code, self.message)
+    }
+}
+
+impl Error for ErrorMessage {}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DecodeError {
+    //payload ended before all fields of the message could be read
+    Truncated { msg_type: MessageType, needed: usize, len: usize },
+    //payload had bytes left over after all fields of the message were read
+    TrailingBytes { msg_type: MessageType, expected: usize, len: usize },
+    //header announced a payload larger than the decoder allows
+    Oversized { msg_type: MessageType, len: usize, max: usize },
+    //a text field was not valid utf-8
+    InvalidUtf8 { msg_type: MessageType },
+    //a field held a value that has no meaning for this message
+    InvalidValue { msg_type: MessageType, field: &'static str, value: u32 },
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DecodeError::Truncated { msg_type, needed, len } => {
+                write!(f, "truncated {msg_type:?} payload: needed {needed} bytes, got {len}")
+            }
+            DecodeError::TrailingBytes { msg_type, expected, len } => {
+                write!(f, "{msg_type:?} payload has {} trailing bytes after its fields: expected {expected} bytes, got {len}", len - expected)
+            }
+            DecodeError::Oversized { msg_type, len, max } => {
+                write!(f, "{msg_type:?} payload of {len} bytes exceeds limit of {max} bytes")
+            }
+            DecodeError::InvalidUtf8 { msg_type } => {
+                write!(f, "{msg_type:?} payload is not valid utf-8")
+            }
+            DecodeError::InvalidValue { msg_type, field, value } => {
+                write!(f, "invalid {field} value {value} in {msg_type:?} payload")
+            }
+        }
+    }
+}
+
+impl Error for DecodeError {}
+
+//reads big-endian fields out of a payload and reports exactly where it ran short
+pub struct PayloadReader<'a> {
+    msg_type: MessageType,
+    buf: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> PayloadReader<'a> {
+    pub fn new(msg_type: MessageType, buf: &'a [u8]) -> Self {
+        PayloadReader { msg_type, buf, pos: 0 }
+    }
+
+    pub fn bytes(&mut self, n: usize) -> Result<&'a [u8], DecodeError> {
+        let end = self.pos + n;
+        if end > self.buf.len() {
+            return Err(DecodeError::Truncated { msg_type: self.msg_type, needed: end, len: self.buf.len() });
+        }
+        let out = &self.buf[self.pos..end];
+        self.pos = end;
+        Ok(out)
+    }
+
+    pub fn u8(&mut self) -> Result<u8, DecodeError> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    pub fn u16(&mut self) -> Result<u16, DecodeError> {
+        Ok(u16::from_be_bytes(self.bytes(2)?.try_into().unwrap()))
+    }
+
+    pub fn u32(&mut self) -> Result<u32, DecodeError> {
+        Ok(u32::from_be_bytes(self.bytes(4)?.try_into().unwrap()))
+    }
+
+    pub fn u64(&mut self) -> Result<u64, DecodeError> {
+        Ok(u64::from_be_bytes(self.bytes(8)?.try_into().unwrap()))
+    }
+
+    pub fn i32(&mut self) -> Result<i32, DecodeError> {
+        Ok(i32::from_be_bytes(self.bytes(4)?.try_into().unwrap()))
+    }
+
+    //u16 length prefix followed by utf-8 bytes
+    pub fn string(&mut self) -> Result<String, DecodeError> {
+        let len = self.u16()? as usize;
+        let bytes = self.bytes(len)?;
+        String::from_utf8(bytes.to_vec()).map_err(|_| DecodeError::InvalidUtf8 { msg_type: self.msg_type })
+    }
+
+    //everything that has not been read yet
+    pub fn rest(&mut self) -> &'a [u8] {
+        let out = &self.buf[self.pos..];
+        self.pos = self.buf.len();
+        out
+    }
+
+    //everything that has not been read yet as utf-8
+    pub fn rest_string(&mut self) -> Result<String, DecodeError> {
+        let msg_type = self.msg_type;
+        String::from_utf8(self.rest().to_vec()).map_err(|_| DecodeError::InvalidUtf8 { msg_type })
+    }
+
+    pub fn invalid(&self, field: &'static str, value: u32) -> DecodeError {
+        DecodeError::InvalidValue { msg_type: self.msg_type, field, value }
+    }
+
+    //error out if the payload is longer than the fields that were read
+    pub fn finish(self) -> Result<(), DecodeError> {
+        if self.pos != self.buf.len() {
+            return Err(DecodeError::TrailingBytes { msg_type: self.msg_type, expected: self.pos, len: self.buf.len() });
+        }
+        Ok(())
+    }
+}
+
+//u16 length prefix followed by utf-8 bytes, strings longer than u16::MAX are cut at a char boundary
+pub fn put_string(out: &mut Vec<u8>, s: &str) {
+    let mut end = s.len().min(u16::MAX as usize);
+    while !s.is_char_boundary(end) {
+        end -= 1;
+    }
+    out.extend_from_slice(&(end as u16).to_be_bytes());
+    out.extend_from_slice(&s.as_bytes()[..end]);
+}
+
+impl Message {
+    pub fn message_type(&self) -> MessageType {
+        match self {
+            Message::Text(_) => MessageType::Text,
+            Message::Connect(_) => MessageType::Connect,
+            Message::Disconnect => MessageType::Disconnect,
+            Message::Error(_) => MessageType::Error,
+            Message::SessionConfig(_) => MessageType::SessionConfig,
+            Message::AuthRequired => MessageType::AuthRequired,
+            Message::Auth(_) => MessageType::Auth,
+            Message::AwaitingApproval { .. } => MessageType::AwaitingApproval,
+
+            Message::FrameFull(_) => MessageType::FrameFull,
+            Message::FrameDelta(_) => MessageType::FrameDelta,
+            Message::FrameEnd => MessageType::FrameEnd,
+            Message::CursorShape(_) => MessageType::CursorShape,
+            Message::CursorPos { .. } => MessageType::CursorPos,
+            Message::Resize { .. } => MessageType::Resize,
+            Message::ReceiveReport { .. } => MessageType::ReceiveReport,
+            Message::RequestKeyframe => MessageType::RequestKeyframe,
+
+            Message::KeyDown(_) => MessageType::KeyDown,
+            Message::KeyUp(_) => MessageType::KeyUp,
+            Message::MouseMove { .. } => MessageType::MouseMove,
+            Message::MouseDown(_) => MessageType::MouseDown,
+            Message::MouseUp(_) => MessageType::MouseUp,
+            Message::MouseScroll(_) => MessageType::MouseScroll,
+
+            Message::Clipboard(_) => MessageType::Clipboard,
+
+            Message::Unknown { code, .. } => MessageType::Unknown(*code),
+        }
+    }
+
+    //append the payload bytes (no header) to out
+    pub fn encode_payload(&self, out: &mut Vec<u8>) {
+        match self {
+            Message::Text(text) | Message::Auth(text) => {
+                out.extend_from_slice(text.as_bytes());
+            }
+            Message::Connect(hello) => hello.encode(out),
+            Message::Error(err) => {
+                out.extend_from_slice(&err.code.to_u16().to_be_bytes());
+                out.extend_from_slice(err.message.as_bytes());
+            }
+            Message::SessionConfig(config) => config.encode(out),
+            Message::AwaitingApproval { timeout_secs } => out.extend_from_slice(&timeout_secs.to_be_bytes()),
+            Message::KeyDown(event) | Message::KeyUp(event) => event.encode(out),
+            Message::MouseDown(button) | Message::MouseUp(button) => out.push(button.to_u8()),
+            Message::MouseScroll(delta) => delta.encode(out),
+            Message::Clipboard(content) => content.encode(out),
+            Message::Disconnect | Message::FrameEnd | Message::AuthRequired | Message::RequestKeyframe => {}
+
+            Message::FrameFull(bytes)
+            | Message::FrameDelta(bytes)
+            | Message::CursorShape(bytes)
+            | Message::Unknown { payload: bytes, .. } => out.extend_from_slice(bytes),
+
+            Message::CursorPos { x, y } | Message::MouseMove { x, y } => {
+                out.extend_from_slice(&x.to_be_bytes());
+                out.extend_from_slice(&y.to_be_bytes());
+            }
+            Message::Resize { w, h } => {
+                out.extend_from_slice(&w.to_be_bytes());
+                out.extend_from_slice(&h.to_be_bytes());
+            }
+            Message::ReceiveReport { frames, bytes } => {
+                out.extend_from_slice(&frames.to_be_bytes());
+                out.extend_from_slice(&bytes.to_be_bytes());
+            }
+        }
+    }
+
+    //parse a payload that has already been split off the stream by its header
+    pub fn decode(msg_type: MessageType, payload: &[u8]) -> Result<Message, DecodeError> {
+        let mut r = PayloadReader::new(msg_type, payload);
+        let msg = match msg_type {
+            MessageType::Text => Message::Text(r.rest_string()?),
+            MessageType::Connect => Message::Connect(Hello::decode(&mut r)?),
+            MessageType::Disconnect => Message::Disconnect,
+            MessageType::Error => Message::Error(ErrorMessage {
+                code: ErrorCode::from_u16(r.u16()?),
+                message: r.rest_string()?,
+            }),
+            MessageType::SessionConfig => Message::SessionConfig(SessionConfig::decode(&mut r)?),
+            MessageType::AuthRequired => Message::AuthRequired,
+            MessageType::Auth => Message::Auth(r.rest_string()?),
+            MessageType::AwaitingApproval => Message::AwaitingApproval { timeout_secs: r.u32()? },
+
+            MessageType::FrameFull => Message::FrameFull(r.rest().to_vec()),
+            MessageType::FrameDelta => Message::FrameDelta(r.rest().to_vec()),
+            MessageType::FrameEnd => Message::FrameEnd,
+            MessageType::CursorShape => Message::CursorShape(r.rest().to_vec()),
+            MessageType::CursorPos => Message::CursorPos { x: r.u32()?, y: r.u32()? },
+            MessageType::Resize => Message::Resize { w: r.u32()?, h: r.u32()? },
+            MessageType::ReceiveReport => Message::ReceiveReport { frames: r.u64()?, bytes: r.u64()? },
+            MessageType::RequestKeyframe => Message::RequestKeyframe,
+
+            MessageType::KeyDown => Message::KeyDown(KeyEvent::decode(&mut r)?),
+            MessageType::KeyUp => Message::KeyUp(KeyEvent::decode(&mut r)?),
+            MessageType::MouseMove => Message::MouseMove { x: r.u32()?, y: r.u32()? },
+            MessageType::MouseDown => Message::MouseDown(MouseButton::from_u8(r.u8()?)),
+            MessageType::MouseUp => Message::MouseUp(MouseButton::from_u8(r.u8()?)),
+            MessageType::MouseScroll => Message::MouseScroll(ScrollDelta::decode(&mut r)?),
+
+            MessageType::Clipboard => Message::Clipboard(ClipboardContent::decode(&mut r)?),
+
+            MessageType::Unknown(code) => Message::Unknown { code, payload: r.rest().to_vec() },
+        };
+        r.finish()?;
+        Ok(msg)
+    }
+}
+
+//turns messages into header + payload frames, reusing one buffer between messages
+#[derive(Default)]
+pub struct MessageEncoder {
+    buf: Vec<u8>,
+}
+
+impl MessageEncoder {
+    pub fn new() -> Self {
+        MessageEncoder { buf: Vec::new() }
+    }
+
+    //encode msg into a complete frame and return a view of it
+    pub fn encode(&mut self, msg: &Message) -> &[u8] {
+        self.buf.clear();
+        //reserve the header, payload length is patched in once the payload is written
+        self.buf.push(msg.message_type().to_u8());
+        self.buf.extend_from_slice(&[0u8; 4]);
+        msg.encode_payload(&mut self.buf);
+        let payload_len = (self.buf.len() - HEADER_LEN) as u32;
+        self.buf[1..HEADER_LEN].copy_from_slice(&payload_len.to_be_bytes());
+        &self.buf
+    }
+
+    //encode msg and write the whole frame, retrying while a non-blocking socket is full
+    pub fn write<T: Write>(&mut self, stream: &mut T, msg: &Message) -> io::Result<()> {
+        let frame = self.encode(msg);
+        let mut offset = 0;
+        while offset < frame.len() {
+            match stream.write(&frame[offset..]) {
+                Ok(0) => return Err(io::Error::new(ErrorKind::WriteZero, "Socket closed while writing")),
+                Ok(n) => offset += n,
+                Err(ref e) if e.kind() == ErrorKind::WouldBlock || e.kind() == ErrorKind::TimedOut => {
+                    std::thread::sleep(Duration::from_millis(1));
+                    continue;
+                }
+                Err(ref e) if e.kind() == ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+            }
+        }
+        //a tls stream flushes by writing out what it buffered, which can hit a full socket too
+        loop {
+            match stream.flush() {
+                Err(ref e) if e.kind() == ErrorKind::WouldBlock || e.kind() == ErrorKind::TimedOut => {
+                    std::thread::sleep(Duration::from_millis(1));
+                }
+                Err(ref e) if e.kind() == ErrorKind::Interrupted => {}
+                result => return result,
+            }
+        }
+    }
+}
+
+//encode a single message into a new frame
+pub fn encode_message(msg: &Message) -> Vec<u8> {
+    MessageEncoder::new().encode(msg).to_vec()
+}
+
+//buffers raw stream bytes and splits them into messages, partial headers/payloads wait for more data
+pub struct MessageDecoder {
+    buf: Vec<u8>,
+    max_payload: usize,
+}
+
+impl Default for MessageDecoder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MessageDecoder {
+    pub fn new() -> Self {
+        Self::with_max_payload(MAX_PAYLOAD_LEN)
+    }
+
+    pub fn with_max_payload(max_payload: usize) -> Self {
+        MessageDecoder { buf: Vec::new(), max_payload }
+    }
+
+    //change the limit for messages still to come, e.g. once the peer has authenticated
+    pub fn set_max_payload(&mut self, max_payload: usize) {
+        self.max_payload = max_payload;
+    }
+
+    //add bytes received from the stream
+    pub fn feed(&mut self, data: &[u8]) {
+        self.buf.extend_from_slice(data);
+    }
+
+    //do a single read from the stream into the buffer, returns the read result unchanged
+    pub fn read_from<T: Read>(&mut self, stream: &mut T) -> io::Result<usize> {
+        let mut chunk = [0u8; 16 * 1024];
+        let n = stream.read(&mut chunk)?;
+        self.feed(&chunk[..n]);
+        Ok(n)
+    }
+
+    //bytes received but not yet returned as a message
+    pub fn buffered(&self) -> usize {
+        self.buf.len()
+    }
+
+    //block until a complete message arrives or timeout passes, read timeouts on the stream are retried
+    pub fn read_message<T: Read>(&mut self, stream: &mut T, timeout: Duration) -> Result<Message, Box<dyn Error>> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            if let Some(msg) = self.next_message()? {
+                return Ok(msg);
+            }
+            if Instant::now() >= deadline {
+                return Err(Box::new(io::Error::new(ErrorKind::TimedOut, "Timed out waiting for message")));
+            }
+            match self.read_from(stream) {
+                Ok(0) => return Err(Box::new(io::Error::new(ErrorKind::UnexpectedEof, "Peer disconnected"))),
+                Ok(_) => {}
+                Err(ref e) if e.kind() == ErrorKind::WouldBlock || e.kind() == ErrorKind::TimedOut => {
+                    std::thread::sleep(Duration::from_millis(1));
+                }
+                Err(ref e) if e.kind() == ErrorKind::Interrupted => {}
+                Err(e) => return Err(Box::new(e)),
+            }
+        }
+    }
+
+    //pop the next complete message, Ok(None) means more data is needed
+    pub fn next_message(&mut self) -> Result<Option<Message>, DecodeError> {
+        if self.buf.len() < HEADER_LEN {
+            return Ok(None);
+        }
+
+        let msg_type = MessageType::from_u8(self.buf[0]);
+        let payload_len = u32::from_be_bytes([self.buf[1], self.buf[2], self.buf[3], self.buf[4]]) as usize;
+        if payload_len > self.max_payload {
+            return Err(DecodeError::Oversized { msg_type, len: payload_len, max: self.max_payload });
+        }
+        if self.buf.len() < HEADER_LEN + payload_len {
+            return Ok(None);
+        }
+
+        let msg = Message::decode(msg_type, &self.buf[HEADER_LEN..HEADER_LEN + payload_len]);
+        //drop the frame even when it failed to decode, the header still told us where the next one starts
+        self.buf.drain(..HEADER_LEN + payload_len);
+        msg.map(Some)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        handshake::{ Capabilities, Codec },
+        input::{ Key, Modifiers },
+        clipboard::{ ClipboardEntry, ClipboardFormat },
+    };
+
+    //one of every message, the match makes this stop compiling when a variant is added without a case here
+    fn every_message() -> Vec<Message> {
+        let messages = vec![
+            Message::Text("hello ✓".to_string()),
+            Message::Connect(Hello::new(vec![Codec::H264, Codec::Unknown(9)], 1920, 1080, Capabilities::INPUT.union(Capabilities::CLIPBOARD))),
+            Message::Disconnect,
+            Message::Error(ErrorMessage::new(ErrorCode::Unknown(0x1234), "something broke")),
+            Message::SessionConfig(SessionConfig { version: 1, codec: Codec::H264, max_width: 1280, max_height: 720, capabilities: Capabilities::RECEIVE_REPORTS }),
+            Message::AuthRequired,
+            Message::Auth("hunter2".to_string()),
+            Message::AwaitingApproval { timeout_secs: 60 },
+            Message::FrameFull(vec![1, 2, 3]),
+            Message::FrameDelta(vec![0, 0, 0, 1, 0x65]),
+            Message::FrameEnd,
+            Message::CursorShape(vec![9; 16]),
+            Message::CursorPos { x: 10, y: u32::MAX },
+            Message::Resize { w: 2560, h: 1440 },
+            Message::ReceiveReport { frames: u64::MAX, bytes: 1 << 40 },
+            Message::RequestKeyframe,
+            Message::KeyDown(KeyEvent::key(Key::A, Modifiers::SHIFT.union(Modifiers::CTRL))),
+            Message::KeyUp(KeyEvent::key(Key::Unknown(0x1FF), Modifiers::NONE)),
+            Message::KeyDown(KeyEvent::Text("é".to_string())),
+            Message::MouseMove { x: 0, y: 1079 },
+            Message::MouseDown(MouseButton::Back),
+            Message::MouseUp(MouseButton::Unknown(7)),
+            Message::MouseScroll(ScrollDelta::Lines { x: -120, y: 240 }),
+            Message::MouseScroll(ScrollDelta::Pixels { x: 3, y: -4 }),
+            Message::Clipboard(ClipboardContent {
+                entries: vec![
+                    ClipboardEntry { format: ClipboardFormat::Text, data: b"copied".to_vec() },
+                    ClipboardEntry { format: ClipboardFormat::Unknown("application/x-test".to_string()), data: vec![0, 255] },
+                ],
+            }),
+            Message::Unknown { code: 0x7E, payload: vec![4, 5, 6] },
+        ];
+        for msg in &messages {
+            match msg {
+                Message::Text(_) | Message::Connect(_) | Message::Disconnect | Message::Error(_)
+                | Message::SessionConfig(_) | Message::AuthRequired | Message::Auth(_) | Message::AwaitingApproval { .. }
+                | Message::FrameFull(_) | Message::FrameDelta(_) | Message::FrameEnd | Message::CursorShape(_)
+                | Message::CursorPos { .. } | Message::Resize { .. } | Message::ReceiveReport { .. } | Message::RequestKeyframe
+                | Message::KeyDown(_) | Message::KeyUp(_) | Message::MouseMove { .. } | Message::MouseDown(_)
+                | Message::MouseUp(_) | Message::MouseScroll(_) | Message::Clipboard(_) | Message::Unknown { .. } => {}
+            }
+        }
+        messages
+    }
+
+    #[test]
+    fn every_message_round_trips() {
+        for msg in every_message() {
+            let frame = encode_message(&msg);
+            assert_eq!(frame[0], msg.message_type().to_u8());
+            assert_eq!(u32::from_be_bytes(frame[1..HEADER_LEN].try_into().unwrap()) as usize, frame.len() - HEADER_LEN);
+            let mut decoder = MessageDecoder::new();
+            decoder.feed(&frame);
+            assert_eq!(decoder.next_message(), Ok(Some(msg.clone())), "{msg:?}");
+            assert_eq!(decoder.buffered(), 0);
+        }
+    }
+
+    #[test]
+    fn messages_split_across_reads_come_out_whole_and_in_order() {
+        let messages = every_message();
+        let stream: Vec<u8> = messages.iter().flat_map(encode_message).collect();
+        let mut decoder = MessageDecoder::new();
+        let mut decoded = Vec::new();
+        for byte in stream {
+            decoder.feed(&[byte]);
+            while let Some(msg) = decoder.next_message().unwrap() {
+                decoded.push(msg);
+            }
+        }
+        assert_eq!(decoded, messages);
+    }
+
+    #[test]
+    fn truncated_header_waits_for_more() {
+        let frame = encode_message(&Message::Resize { w: 1, h: 2 });
+        let mut decoder = MessageDecoder::new();
+        decoder.feed(&frame[..HEADER_LEN - 1]);
+        assert_eq!(decoder.next_message(), Ok(None));
+        decoder.feed(&frame[HEADER_LEN - 1..]);
+        assert_eq!(decoder.next_message(), Ok(Some(Message::Resize { w: 1, h: 2 })));
+    }
+
+    #[test]
+    fn truncated_payload_waits_for_more() {
+        let frame = encode_message(&Message::FrameDelta(vec![7; 100]));
+        let mut decoder = MessageDecoder::new();
+        decoder.feed(&frame[..frame.len() - 1]);
+        assert_eq!(decoder.next_message(), Ok(None));
+        assert_eq!(decoder.buffered(), frame.len() - 1);
+    }
+
+    #[test]
+    fn payload_shorter_than_its_fields_is_truncated() {
+        //a complete frame whose payload stops halfway through h
+        let mut decoder = MessageDecoder::new();
+        decoder.feed(&[MessageType::Resize.to_u8(), 0, 0, 0, 6, 0, 0, 0, 1, 0, 0]);
+        assert_eq!(
+            decoder.next_message(),
+            Err(DecodeError::Truncated { msg_type: MessageType::Resize, needed: 8, len: 6 })
+        );
+        //the bad frame is skipped, what follows still decodes
+        decoder.feed(&encode_message(&Message::FrameEnd));
+        assert_eq!(decoder.next_message(), Ok(Some(Message::FrameEnd)));
+    }
+
+    #[test]
+    fn payload_longer_than_its_fields_is_trailing_bytes() {
+        let mut payload = Vec::new();
+        Message::CursorPos { x: 1, y: 2 }.encode_payload(&mut payload);
+        payload.push(0);
+        let err = Message::decode(MessageType::CursorPos, &payload).unwrap_err();
+        assert_eq!(err, DecodeError::TrailingBytes { msg_type: MessageType::CursorPos, expected: 8, len: 9 });
+        assert!(err.to_string().contains("1 trailing bytes"), "{err}");
+    }
+
+    #[test]
+    fn payload_over_the_limit_is_rejected_from_the_header() {
+        let mut decoder = MessageDecoder::new();
+        let len = (MAX_PAYLOAD_LEN + 1) as u32;
+        let mut header = vec![MessageType::FrameDelta.to_u8()];
+        header.extend_from_slice(&len.to_be_bytes());
+        decoder.feed(&header);
+        assert_eq!(
+            decoder.next_message(),
+            Err(DecodeError::Oversized { msg_type: MessageType::FrameDelta, len: MAX_PAYLOAD_LEN + 1, max: MAX_PAYLOAD_LEN })
+        );
+
+        let mut decoder = MessageDecoder::with_max_payload(4);
+        decoder.feed(&encode_message(&Message::FrameDelta(vec![0; 5])));
+        assert!(matches!(decoder.next_message(), Err(DecodeError::Oversized { len: 5, max: 4, .. })));
+    }
+
+    #[test]
+    fn invalid_values_are_reported() {
+        assert_eq!(
+            Message::decode(MessageType::MouseScroll, &[9, 0, 0, 0, 0, 0, 0, 0, 0]),
+            Err(DecodeError::InvalidValue { msg_type: MessageType::MouseScroll, field: "scroll kind", value: 9 })
+        );
+        assert_eq!(
+            Message::decode(MessageType::Text, &[0xFF]),
+            Err(DecodeError::InvalidUtf8 { msg_type: MessageType::Text })
+        );
+    }
+
+    #[test]
+    fn long_strings_are_cut_at_a_char_boundary() {
+        let text = "é".repeat(u16::MAX as usize);
+        let mut out = Vec::new();
+        put_string(&mut out, &text);
+        let mut r = PayloadReader::new(MessageType::Text, &out);
+        let decoded = r.string().unwrap();
+        r.finish().unwrap();
+        assert!(decoded.len() <= u16::MAX as usize && text.starts_with(&decoded));
+        assert_eq!(decoded.len(), u16::MAX as usize - 1);
+    }
+}
@@ -0,0 +1,299 @@
+//adaptive quality: bitrate, frame rate and scale follow what the link to each client keeps up with
+//when video backs up the bitrate drops first, then the frame rate, then the picture is scaled down further,
+//and once the link has been clear for a while they come back in the opposite order
+use std::{
+    fmt,
+    sync::{ Mutex, atomic::{ AtomicU64, Ordering } },
+    time::{ Duration, Instant },
+};
+use openh264::encoder::Encoder;
+use crate::frame_queue::QueueStats;
+use openh264_sys2::{ ENCODER_OPTION_BITRATE, ENCODER_OPTION_FRAME_RATE, SBitrateInfo, SPATIAL_LAYER_ALL };
+
+//how often the link is measured
+const INTERVAL: Duration = Duration::from_secs(1);
+//video waiting to reach the client, in seconds of playback, before quality goes down, frames dropped from the queue always count
+const CONGESTED_SECS: f64 = 0.5;
+//and below which the link counts as clear
+const CLEAR_SECS: f64 = 0.15;
+//after a change the link gets this long to show its effect before quality goes down again
+const SETTLE: Duration = Duration::from_secs(3);
+//clear intervals in a row before quality goes up a step
+const CLEAR_INTERVALS: u32 = 5;
+//a congested link gets this share of what it was measured to carry
+const HEADROOM: f64 = 0.85;
+const DECREASE: f64 = 0.7;
+const INCREASE: f64 = 1.15;
+
+//what the encoder is currently asked for
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Quality {
+    pub bitrate_bps: u32,
+    pub fps: u32,
+    //what the captured width and height are divided by, not always a whole number when fitting a client's window
+    pub scale: f64,
+}
+
+impl fmt::Display for Quality {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} kbps, {} fps, scale {}", self.bitrate_bps / 1000, self.fps, (self.scale * 100.0).round() / 100.0)
+    }
+}
+
+//how far adaptation may go, best is where a session starts
+//max_scale below best.scale is taken as best.scale, the best scale has to fit the client whatever was configured
+#[derive(Debug, Copy, Clone)]
+pub struct Bounds {
+    pub best: Quality,
+    pub min_bitrate_bps: u32,
+    pub min_fps: u32,
+    pub max_scale: f64,
+}
+
+//a session's traffic, updated by the dispatcher as it writes and by the client's receive reports
+#[derive(Default)]
+pub struct LinkStats {
+    //frames written by the dispatcher
+    sent_frames: AtomicU64,
+    sent_bytes: AtomicU64,
+    //the client's latest receive report, None until it sends one
+    received: Mutex<Option<Received>>,
+}
+
+//totals the client reported, and frames sent when the report arrived, as the report is up to a second old when it's read
+#[derive(Copy, Clone)]
+struct Received {
+    frames: u64,
+    bytes: u64,
+    sent_frames: u64,
+}
+
+impl LinkStats {
+    pub fn bytes_sent(&self, bytes: usize) {
+        self.sent_bytes.fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+
+    pub fn frame_sent(&self) {
+        self.sent_frames.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn client_report(&self, frames: u64, bytes: u64) {
+        let sent_frames = self.sent_frames.load(Ordering::Relaxed);
+        *self.received.lock().unwrap() = Some(Received { frames, bytes, sent_frames });
+    }
+}
+
+//a change to the stream and why it was made
+pub struct Adaptation {
+    pub from: Quality,
+    pub to: Quality,
+    pub reason: String,
+}
+
+impl fmt::Display for Adaptation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} -> {} ({})", self.from, self.to, self.reason)
+    }
+}
+
+//decides on changes from a session's LinkStats, owned by the session's encode loop
+pub struct Adapter {
+    bounds: Bounds,
+    current: Quality,
+    measured_at: Instant,
+    last_sent_bytes: u64,
+    last_received_bytes: Option<u64>,
+    last_dropped: u64,
+    changed_at: Instant,
+    clear_intervals: u32,
+}
+
+impl Adapter {
+    pub fn new(bounds: Bounds) -> Self {
+        let now = Instant::now();
+        Adapter {
+            bounds,
+            current: bounds.best,
+            measured_at: now,
+            last_sent_bytes: 0,
+            last_received_bytes: None,
+            last_dropped: 0,
+            changed_at: now,
+            clear_intervals: 0,
+        }
+    }
+
+    //measure the link once an interval has passed, returns the change to make if one is needed
+    pub fn update(&mut self, link: &LinkStats, queue: QueueStats) -> Option<Adaptation> {
+        self.update_at(link, queue, Instant::now())
+    }
+
+    fn update_at(&mut self, link: &LinkStats, queue: QueueStats, now: Instant) -> Option<Adaptation> {
+        let elapsed = now - self.measured_at;
+        if elapsed < INTERVAL {
+            return None;
+        }
+        self.measured_at = now;
+
+        let sent_bytes = link.sent_bytes.load(Ordering::Relaxed);
+        let queued = queue.depth as u64;
+        let dropped = queue.dropped - self.last_dropped;
+        self.last_dropped = queue.dropped;
+        let received = *link.received.lock().unwrap();
+        //frames the client hasn't got yet, whether still queued here or somewhere on the way,
+        //less the one that is normally being written at any moment
+        let in_flight = received.map_or(0, |received| received.sent_frames.saturating_sub(received.frames));
+        let behind = (queued + in_flight).saturating_sub(1);
+        let behind_secs = behind as f64 / self.current.fps as f64;
+
+        let send_bps = (sent_bytes - self.last_sent_bytes) as f64 * 8.0 / elapsed.as_secs_f64();
+        self.last_sent_bytes = sent_bytes;
+        //what reached the client is the better measure of the link when there is one
+        let receive_bps = received.and_then(|received| {
+            let last = self.last_received_bytes.replace(received.bytes)?;
+            Some(received.bytes.saturating_sub(last) as f64 * 8.0 / elapsed.as_secs_f64())
+        });
+        let throughput_bps = receive_bps.unwrap_or(send_bps);
+
+        if behind_secs > CONGESTED_SECS || dropped > 0 {
+            self.clear_intervals = 0;
+            if now - self.changed_at < SETTLE {
+                return None;
+            }
+            let reason = format!("{behind_secs:.1}s behind, {queued} frames queued, {dropped} dropped, getting through {:.0} kbps", throughput_bps / 1000.0);
+            return self.change(self.lower(throughput_bps), reason, now);
+        }
+        if behind_secs < CLEAR_SECS {
+            self.clear_intervals += 1;
+            if self.clear_intervals >= CLEAR_INTERVALS {
+                self.clear_intervals = 0;
+                let reason = format!("link clear for {CLEAR_INTERVALS}s, getting through {:.0} kbps", throughput_bps / 1000.0);
+                return self.change(self.raise(), reason, now);
+            }
+        } else {
+            self.clear_intervals = 0;
+        }
+        None
+    }
+
+    fn change(&mut self, to: Quality, reason: String, now: Instant) -> Option<Adaptation> {
+        if to == self.current {
+            return None;
+        }
+        let from = self.current;
+        self.current = to;
+        self.changed_at = now;
+        Some(Adaptation { from, to, reason })
+    }
+
+    //the capture or the client's window changed size and best_scale is what fits it now,
+    //scaling down added by adaptation stays on top of it
+    pub fn rescale(&mut self, best_scale: f64) -> Quality {
+        let added = self.current.scale - self.bounds.best.scale;
+        self.bounds.best.scale = best_scale;
+        self.current.scale = (best_scale + added).min(self.max_scale());
+        self.current
+    }
+
+    //the most the picture may be scaled down, never less than what it takes to fit the client
+    fn max_scale(&self) -> f64 {
+        self.bounds.max_scale.max(self.bounds.best.scale)
+    }
+
+    //one step down: bitrate to what the link carries, then fewer frames, then a smaller picture
+    fn lower(&self, throughput_bps: f64) -> Quality {
+        let mut quality = self.current;
+        if quality.bitrate_bps > self.bounds.min_bitrate_bps {
+            let mut target = quality.bitrate_bps as f64 * DECREASE;
+            if throughput_bps > 0.0 {
+                target = target.min(throughput_bps * HEADROOM);
+            }
+            quality.bitrate_bps = (target as u32).max(self.bounds.min_bitrate_bps);
+        } else if quality.fps > self.bounds.min_fps {
+            quality.fps = (quality.fps * 2 / 3).max(self.bounds.min_fps);
+        } else if quality.scale < self.max_scale() {
+            quality.scale = (quality.scale + 1.0).min(self.max_scale());
+        }
+        quality
+    }
+
+    //one step up, undoing the last thing lower gave up first
+    fn raise(&self) -> Quality {
+        let best = self.bounds.best;
+        let mut quality = self.current;
+        if quality.scale > best.scale {
+            quality.scale = (quality.scale - 1.0).max(best.scale);
+        } else if quality.fps < best.fps {
+            quality.fps = (quality.fps * 3 / 2).max(quality.fps + 1).min(best.fps);
+        } else if quality.bitrate_bps < best.bitrate_bps {
+            quality.bitrate_bps = ((quality.bitrate_bps as f64 * INCREASE) as u32).min(best.bitrate_bps);
+        }
+        quality
+    }
+}
+
+//change the target bitrate and frame rate of a running encoder, unlike a new encoder this doesn't start over with a keyframe
+pub fn set_rate(encoder: &mut Encoder, bitrate_bps: u32, fps: u32) -> Result<(), String> {
+    let mut bitrate = SBitrateInfo { iLayer: SPATIAL_LAYER_ALL, iBitrate: bitrate_bps as i32 };
+    let mut frame_rate = fps as f32;
+    //both options are plain values the encoder copies, nothing it relies on is replaced
+    let (bitrate_result, frame_rate_result) = unsafe {
+        let api = encoder.raw_api();
+        (
+            api.set_option(ENCODER_OPTION_BITRATE, (&mut bitrate as *mut SBitrateInfo).cast()),
+            api.set_option(ENCODER_OPTION_FRAME_RATE, (&mut frame_rate as *mut f32).cast()),
+        )
+    };
+    if bitrate_result != 0 || frame_rate_result != 0 {
+        return Err(format!("encoder refused bitrate {bitrate_bps} bps at {fps} fps"));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scale;
+
+    #[test]
+    fn falling_throughput_keeps_scaled_sizes_even_and_within_max_scale() {
+        let best = Quality { bitrate_bps: 10_000_000, fps: 30, scale: 1.5 };
+        let bounds = Bounds { best, min_bitrate_bps: 500_000, min_fps: 5, max_scale: 4.0 };
+        let mut adapter = Adapter::new(bounds);
+        let link = LinkStats::default();
+        let mut queue = QueueStats::default();
+        let start = Instant::now();
+        let mut lowest = best;
+
+        //every interval something is dropped and less gets through than the last
+        for step in 1..=40u32 {
+            queue.dropped += 1;
+            link.bytes_sent(1_000_000 / step as usize);
+            let now = start + SETTLE * step;
+            if let Some(adaptation) = adapter.update_at(&link, queue, now) {
+                let to = adaptation.to;
+                assert!(to.scale <= bounds.max_scale, "scale {} is past max_scale", to.scale);
+                for (width, height) in [(1366, 768), (1365, 767), (2560, 1440)] {
+                    let (scaled_width, scaled_height) = scale::scaled_size(width, height, to.scale);
+                    assert!(scaled_width % 2 == 0 && scaled_height % 2 == 0, "{width}x{height} at scale {} gave {scaled_width}x{scaled_height}", to.scale);
+                }
+                lowest = to;
+            }
+        }
+        assert_eq!(lowest.bitrate_bps, bounds.min_bitrate_bps);
+        assert_eq!(lowest.fps, bounds.min_fps);
+        assert_eq!(lowest.scale, bounds.max_scale);
+    }
+
+    #[test]
+    fn scale_never_goes_below_what_fits_the_client() {
+        let best = Quality { bitrate_bps: 1_000_000, fps: 10, scale: 5.0 };
+        let bounds = Bounds { best, min_bitrate_bps: 1_000_000, min_fps: 10, max_scale: 2.0 };
+        let mut adapter = Adapter::new(bounds);
+        let link = LinkStats::default();
+        let queue = QueueStats { dropped: 1, ..QueueStats::default() };
+        //max_scale below the best scale leaves nothing to lower
+        assert!(adapter.update_at(&link, queue, Instant::now() + SETTLE).is_none());
+        assert_eq!(adapter.rescale(6.0).scale, 6.0);
+    }
+}
@@ -0,0 +1,165 @@
+//whether a new client is let in straight away, turned away, or has to be approved by someone at the server
+use std::{
+    error::Error,
+    io::{ self, BufRead, IsTerminal, Write },
+    sync::mpsc::{ self, Receiver, RecvTimeoutError, Sender },
+    thread,
+    time::{ Duration, Instant },
+};
+use clap::ValueEnum;
+use serde::Deserialize;
+
+#[derive(ValueEnum, Deserialize, Debug, Copy, Clone, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ApprovalPolicy {
+    //every client that gets through authentication gets a session
+    Auto,
+    //nobody new gets a session
+    Deny,
+    //the server's terminal is asked about each client
+    Ask,
+}
+
+//one client waiting for an answer
+struct Request {
+    who: String,
+    deadline: Instant,
+    answer: Sender<bool>,
+}
+
+//asks on the server's terminal one client at a time, clients that connect meanwhile wait their turn
+pub struct Approver {
+    requests: Sender<Request>,
+}
+
+impl Approver {
+    //takes over stdin, so only start it when the policy is ask
+    pub fn start() -> Result<Self, Box<dyn Error>> {
+        if !io::stdin().is_terminal() {
+            return Err("approval = \"ask\" needs the server to run in a terminal to ask in".into());
+        }
+        let (requests, request_receiver) = mpsc::channel::<Request>();
+
+        //lines are read on their own thread so a prompt can time out while nobody types
+        let (line_transmitter, lines) = mpsc::channel::<String>();
+        thread::Builder::new()
+            .name("approval input".to_string())
+            .spawn(move || {
+                for line in io::stdin().lock().lines() {
+                    let Ok(line) = line else { return };
+                    if line_transmitter.send(line).is_err() {
+                        return;
+                    }
+                }
+            })?;
+
+        thread::Builder::new()
+            .name("approval".to_string())
+            .spawn(move || {
+                for request in request_receiver {
+                    let approved = prompt(&request, &lines);
+                    let _ = request.answer.send(approved);
+                }
+            })?;
+
+        Ok(Approver { requests })
+    }
+
+    //block until someone answers for who, false if they say no or don't answer within timeout
+    pub fn ask(&self, who: &str, timeout: Duration) -> bool {
+        let (answer, answer_receiver) = mpsc::channel();
+        let request = Request { who: who.to_string(), deadline: Instant::now() + timeout, answer };
+        if self.requests.send(request).is_err() {
+            return false;
+        }
+        answer_receiver.recv().unwrap_or(false)
+    }
+}
+
+fn prompt(request: &Request, lines: &Receiver<String>) -> bool {
+    //the client may have waited its whole turn in the queue
+    let Some(left) = request.deadline.checked_duration_since(Instant::now()) else {
+        println!("Connection from {} timed out waiting for approval", request.who);
+        return false;
+    };
+    //anything typed before the question was asked isn't an answer to it
+    while lines.try_recv().is_ok() {}
+    print!("Allow connection from {}? [y/N] (denied in {}s) ", request.who, left.as_secs_f32().ceil());
+    let _ = io::stdout().flush();
+    match lines.recv_timeout(left) {
+        Ok(line) if matches!(line.trim(), "y" | "Y" | "yes") => {
+            println!("Approved {}", request.who);
+            true
+        }
+        Ok(_) => {
+            println!("Denied {}", request.who);
+            false
+        }
+        Err(RecvTimeoutError::Timeout) => {
+            println!();
+            println!("No answer, denied {}", request.who);
+            false
+        }
+        Err(RecvTimeoutError::Disconnected) => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(who: &str, timeout: Duration) -> (Request, Receiver<bool>) {
+        let (answer, answer_receiver) = mpsc::channel();
+        (Request { who: who.to_string(), deadline: Instant::now() + timeout, answer }, answer_receiver)
+    }
+
+    //answer is typed a little after the question is asked, like someone at the terminal would
+    fn answered(answer: &str, timeout: Duration) -> bool {
+        let (request, _) = request("10.0.0.5:50000", timeout);
+        let (line_transmitter, lines) = mpsc::channel();
+        let answer = answer.to_string();
+        let typist = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(100));
+            let _ = line_transmitter.send(answer);
+            //keep the channel open so a missed answer times out instead of disconnecting
+            thread::sleep(Duration::from_millis(400));
+        });
+        let approved = prompt(&request, &lines);
+        typist.join().unwrap();
+        approved
+    }
+
+    #[test]
+    fn only_yes_approves() {
+        assert!(answered("y", Duration::from_secs(5)));
+        assert!(answered(" yes ", Duration::from_secs(5)));
+        assert!(!answered("n", Duration::from_secs(5)));
+        assert!(!answered("", Duration::from_secs(5)));
+        assert!(!answered("yeah", Duration::from_secs(5)));
+    }
+
+    #[test]
+    fn nobody_answering_in_time_denies() {
+        assert!(!answered("y", Duration::from_millis(20)));
+    }
+
+    #[test]
+    fn a_request_that_expired_in_the_queue_is_denied_without_asking() {
+        let (request, _) = request("10.0.0.5:50000", Duration::ZERO);
+        let (line_transmitter, lines) = mpsc::channel();
+        line_transmitter.send("y".to_string()).unwrap();
+        thread::sleep(Duration::from_millis(1));
+        assert!(!prompt(&request, &lines));
+        //nothing was read, the line is still there for the next question to throw away
+        assert_eq!(lines.try_recv().as_deref(), Ok("y"));
+    }
+
+    #[test]
+    fn lines_typed_before_the_question_are_not_answers() {
+        let (request, _) = request("10.0.0.5:50000", Duration::from_millis(50));
+        let (line_transmitter, lines) = mpsc::channel();
+        line_transmitter.send("y".to_string()).unwrap();
+        line_transmitter.send("yes".to_string()).unwrap();
+        assert!(!prompt(&request, &lines));
+    }
+}
@@ -0,0 +1,293 @@
+//audit log: one JSON object per line for every session and what it did, rotated by size
+//path.1 is the newest rotated file, path.<keep> the oldest, anything older is deleted
+use std::{
+    error::Error,
+    fs::{ self, File, OpenOptions },
+    io::Write,
+    net::SocketAddr,
+    path::{ Path, PathBuf },
+    sync::{ Arc, Mutex, atomic::{ AtomicU64, Ordering } },
+    time::Instant,
+};
+use serde::Serialize;
+use time::{ OffsetDateTime, format_description::well_known::Rfc3339 };
+use common::{ clipboard::ClipboardContent, fingerprint::fingerprint_hex };
+use crate::{ permissions::Permissions, tls::ClientIdentity };
+
+//what happened, the event field of each line
+#[derive(Serialize, Debug)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Event<'a> {
+    //turned away before getting a session, by the client limit, allow-list, password, permissions or approval,
+    //or the connection failed first: a failed TLS handshake, a timeout or a client that went away
+    Rejected {
+        peer: SocketAddr,
+        #[serde(flatten)]
+        client: Client<'a>,
+        code: String,
+        reason: &'a str,
+    },
+    SessionStarted {
+        session: u64,
+        peer: SocketAddr,
+        #[serde(flatten)]
+        client: Client<'a>,
+        invite: Option<&'a str>,
+        permissions: Vec<&'static str>,
+    },
+    Clipboard {
+        session: u64,
+        //to_server or to_client
+        direction: &'static str,
+        formats: Vec<&'a str>,
+        bytes: usize,
+    },
+    //a message the session's permissions don't cover, only the first of each kind is recorded
+    Refused {
+        session: u64,
+        message: String,
+        permission: String,
+    },
+    SessionEnded {
+        session: u64,
+        peer: SocketAddr,
+        duration_secs: f64,
+        reason: &'a str,
+    },
+}
+
+//the client certificate, both null when the server doesn't ask for one
+#[derive(Serialize, Debug)]
+pub struct Client<'a> {
+    cert_name: Option<&'a str>,
+    cert_fingerprint: Option<String>,
+}
+
+impl<'a> Client<'a> {
+    pub fn new(identity: Option<&'a ClientIdentity>) -> Self {
+        Client {
+            cert_name: identity.map(|identity| identity.common_name.as_str()).filter(|name| !name.is_empty()),
+            cert_fingerprint: identity.map(|identity| format!("sha256:{}", fingerprint_hex(&identity.fingerprint))),
+        }
+    }
+}
+
+//one line as written, the time goes first so the file reads in order
+#[derive(Serialize)]
+struct Line<'a> {
+    time: String,
+    #[serde(flatten)]
+    event: &'a Event<'a>,
+}
+
+struct Output {
+    file: File,
+    size: u64,
+}
+
+//where audit lines go, shared by every client thread, None in place of a file when no audit log is configured
+pub struct AuditLog {
+    path: PathBuf,
+    max_bytes: u64,
+    keep: u32,
+    output: Option<Mutex<Output>>,
+    //numbers sessions so their lines can be told apart
+    next_session: AtomicU64,
+}
+
+impl AuditLog {
+    pub fn disabled() -> Self {
+        AuditLog { path: PathBuf::new(), max_bytes: 0, keep: 0, output: None, next_session: AtomicU64::new(1) }
+    }
+
+    //append to path, creating it if needed, the directory has to exist
+    pub fn open(path: &Path, max_bytes: u64, keep: u32) -> Result<Self, Box<dyn Error>> {
+        let file = open_append(path)?;
+        let size = file.metadata()?.len();
+        Ok(AuditLog {
+            path: path.to_path_buf(),
+            max_bytes,
+            keep,
+            output: Some(Mutex::new(Output { file, size })),
+            next_session: AtomicU64::new(1),
+        })
+    }
+
+    //write one line, a failing audit log is reported but doesn't take sessions down with it
+    pub fn record(&self, event: &Event) {
+        let Some(output) = &self.output else { return };
+        let time = OffsetDateTime::now_utc().format(&Rfc3339).unwrap_or_default();
+        let mut line = match serde_json::to_string(&Line { time, event }) {
+            Ok(line) => line,
+            Err(e) => {
+                eprintln!("Failed to write audit log: {e}");
+                return;
+            }
+        };
+        line.push('\n');
+
+        let mut output = output.lock().unwrap();
+        if output.size > 0 && output.size + line.len() as u64 > self.max_bytes
+            && let Err(e) = self.rotate(&mut output) {
+            eprintln!("Failed to rotate audit log {}: {e}", self.path.display());
+        }
+        match output.file.write_all(line.as_bytes()) {
+            Ok(()) => output.size += line.len() as u64,
+            Err(e) => eprintln!("Failed to write audit log {}: {e}", self.path.display()),
+        }
+    }
+
+    //shift path.1 .. path.<keep-1> up by one, the oldest falls off, then start a fresh file
+    fn rotate(&self, output: &mut Output) -> Result<(), Box<dyn Error>> {
+        output.file.flush()?;
+        for n in (1..self.keep).rev() {
+            let from = rotated_path(&self.path, n);
+            if from.exists() {
+                fs::rename(&from, rotated_path(&self.path, n + 1))?;
+            }
+        }
+        if self.keep > 0 {
+            fs::rename(&self.path, rotated_path(&self.path, 1))?;
+        } else {
+            fs::remove_file(&self.path)?;
+        }
+        output.file = open_append(&self.path)?;
+        output.size = 0;
+        Ok(())
+    }
+
+    //a client turned away, or a connection that failed before it got a session
+    pub fn rejected(&self, peer: SocketAddr, identity: Option<&ClientIdentity>, code: &str, reason: &str) {
+        self.record(&Event::Rejected { peer, client: Client::new(identity), code: code.to_string(), reason });
+    }
+
+    //the handle a session records the rest of its lines through, the session_started line is written here
+    pub fn start_session(self: &Arc<Self>, peer: SocketAddr, identity: Option<&ClientIdentity>, invite: Option<&str>, permissions: Permissions) -> SessionAudit {
+        let session = self.next_session.fetch_add(1, Ordering::Relaxed);
+        self.record(&Event::SessionStarted {
+            session,
+            peer,
+            client: Client::new(identity),
+            invite,
+            permissions: permissions.names(),
+        });
+        SessionAudit { log: self.clone(), session, peer, started: Instant::now() }
+    }
+}
+
+fn rotated_path(path: &Path, n: u32) -> PathBuf {
+    let mut name = path.as_os_str().to_os_string();
+    name.push(format!(".{n}"));
+    PathBuf::from(name)
+}
+
+fn open_append(path: &Path) -> Result<File, Box<dyn Error>> {
+    Ok(OpenOptions::new().create(true).append(true).open(path)
+        .map_err(|e| format!("Failed to open audit log {}: {e}", path.display()))?)
+}
+
+//lines for one session
+pub struct SessionAudit {
+    log: Arc<AuditLog>,
+    session: u64,
+    peer: SocketAddr,
+    started: Instant,
+}
+
+impl SessionAudit {
+    pub fn clipboard(&self, direction: &'static str, content: &ClipboardContent) {
+        self.log.record(&Event::Clipboard {
+            session: self.session,
+            direction,
+            formats: content.entries.iter().map(|entry| entry.format.mime()).collect(),
+            bytes: content.len(),
+        });
+    }
+
+    pub fn refused(&self, message: String, permission: Permissions) {
+        self.log.record(&Event::Refused { session: self.session, message, permission: permission.to_string() });
+    }
+
+    pub fn ended(&self, reason: &str) {
+        self.log.record(&Event::SessionEnded {
+            session: self.session,
+            peer: self.peer,
+            duration_secs: self.started.elapsed().as_millis() as f64 / 1000.0,
+            reason,
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::TempDir;
+    use super::*;
+
+    //an audit log in a directory of its own for each test, its rotated files are removed with it when the test ends
+    struct TempLog {
+        path: PathBuf,
+        keep: u32,
+        _dir: TempDir,
+    }
+
+    impl TempLog {
+        fn new(keep: u32) -> Self {
+            let dir = tempfile::tempdir().unwrap();
+            TempLog { path: dir.path().join("audit.log"), keep, _dir: dir }
+        }
+    }
+
+    fn refused(log: &AuditLog, n: u64) {
+        log.record(&Event::Refused { session: n, message: "KeyDown".to_string(), permission: "keyboard".to_string() });
+    }
+
+    //every line is a whole JSON object, returns the session numbers in the file
+    fn sessions(path: &Path) -> Vec<u64> {
+        let contents = fs::read_to_string(path).unwrap();
+        assert!(contents.ends_with('\n'), "{} ends part way through a line", path.display());
+        contents.lines().map(|line| {
+            let value: serde_json::Value = serde_json::from_str(line).unwrap();
+            assert_eq!(value["event"], "refused");
+            value["session"].as_u64().unwrap()
+        }).collect()
+    }
+
+    #[test]
+    fn the_log_rotates_by_size_without_splitting_lines() {
+        let temp = TempLog::new(2);
+        let max_bytes = 400;
+        let log = AuditLog::open(&temp.path, max_bytes, temp.keep).unwrap();
+        for n in 0..40 {
+            refused(&log, n);
+        }
+
+        let files = [temp.path.clone(), rotated_path(&temp.path, 1), rotated_path(&temp.path, 2)];
+        for file in &files {
+            assert!(fs::metadata(file).unwrap().len() <= max_bytes, "{} is over the limit", file.display());
+        }
+        assert!(!rotated_path(&temp.path, 3).exists());
+
+        //the oldest file holds the oldest lines that were kept, and nothing is missing after them
+        let kept: Vec<u64> = files.iter().rev().flat_map(|file| sessions(file)).collect();
+        assert_eq!(kept.last(), Some(&39));
+        assert!(kept.windows(2).all(|pair| pair[1] == pair[0] + 1));
+        assert!(kept.len() < 40);
+    }
+
+    #[test]
+    fn reopening_the_log_appends_and_counts_what_is_there() {
+        let temp = TempLog::new(1);
+        let line_len = {
+            let log = AuditLog::open(&temp.path, u64::MAX, temp.keep).unwrap();
+            refused(&log, 0);
+            fs::metadata(&temp.path).unwrap().len()
+        };
+        //room for one more line, the third goes in a new file, with some slack since the time isn't always as long
+        let log = AuditLog::open(&temp.path, line_len * 5 / 2, temp.keep).unwrap();
+        refused(&log, 1);
+        assert_eq!(sessions(&temp.path), vec![0, 1]);
+        refused(&log, 2);
+        assert_eq!(sessions(&temp.path), vec![2]);
+        assert_eq!(sessions(&rotated_path(&temp.path, 1)), vec![0, 1]);
+    }
+}
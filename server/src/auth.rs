@@ -0,0 +1,203 @@
+//password or token check after the TLS handshake, and the lockout that slows down guessing
+use std::{
+    collections::HashMap,
+    error::Error,
+    net::IpAddr,
+    sync::Mutex,
+    time::{ Duration, Instant },
+};
+use argon2::{
+    Argon2,
+    password_hash::{ PasswordHash, PasswordHasher, PasswordVerifier, SaltString, rand_core::{ OsRng, RngCore } },
+};
+
+//after a wrong password the address has to wait this long before its next one is checked
+const FAILURE_DELAY: Duration = Duration::from_secs(2);
+//wrong passwords in a row from one address before it is locked out
+const MAX_FAILURES: u32 = 5;
+//how long a locked out address has to wait, doubled for every lockout after the first
+const LOCKOUT: Duration = Duration::from_secs(60);
+const MAX_LOCKOUT: Duration = Duration::from_secs(60 * 60);
+
+//argon2id PHC string for the config file, a fresh random salt every time
+pub fn hash_password(password: &str) -> Result<String, Box<dyn Error>> {
+    let salt = SaltString::generate(&mut OsRng);
+    let hash = Argon2::default().hash_password(password.as_bytes(), &salt)
+        .map_err(|e| format!("Failed to hash password: {e}"))?;
+    Ok(hash.to_string())
+}
+
+//random invite token, 128 bits as hex so it can be read out or pasted anywhere
+pub fn generate_token() -> String {
+    let mut bytes = [0u8; 16];
+    OsRng.fill_bytes(&mut bytes);
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+//make sure a hash from the config file can be used before any client needs it
+pub fn check_hash(hash: &str) -> Result<(), String> {
+    PasswordHash::new(hash).map(|_| ()).map_err(|e| e.to_string())
+}
+
+//hash has already passed check_hash
+pub fn verify_password(hash: &str, password: &str) -> bool {
+    match PasswordHash::new(hash) {
+        Ok(parsed) => Argon2::default().verify_password(password.as_bytes(), &parsed).is_ok(),
+        Err(_) => false,
+    }
+}
+
+struct Failures {
+    //wrong passwords since the last lockout or success
+    count: u32,
+    //lockouts so far, each one lasts twice as long as the last
+    lockouts: u32,
+    locked_until: Option<Instant>,
+    last: Instant,
+    //passwords from this address being checked right now, they count against the limit until they are decided
+    checking: u32,
+}
+
+impl Failures {
+    //nothing left worth remembering about the address
+    fn is_clear(&self) -> bool {
+        self.count == 0 && self.lockouts == 0 && self.checking == 0
+    }
+}
+
+//failed attempts per client address, shared by every client thread
+#[derive(Default)]
+pub struct AuthLimiter {
+    failures: Mutex<HashMap<IpAddr, Failures>>,
+}
+
+impl AuthLimiter {
+    pub fn new() -> Self {
+        AuthLimiter::default()
+    }
+
+    //time left before addr may try again, None if it isn't locked out
+    pub fn locked_for(&self, addr: IpAddr) -> Option<Duration> {
+        let failures = self.failures.lock().unwrap();
+        let until = failures.get(&addr)?.locked_until?;
+        until.checked_duration_since(Instant::now())
+    }
+
+    //take one of addr's attempts before its password is checked, or how long it has to wait if it has none left
+    //attempts still being checked are counted, so parallel connections get no more guesses than one after another
+    pub fn begin(&self, addr: IpAddr) -> Result<AuthAttempt<'_>, Duration> {
+        let mut failures = self.failures.lock().unwrap();
+        let now = Instant::now();
+        //forget addresses that haven't failed for longer than the longest lockout
+        failures.retain(|_, f| f.checking > 0 || now.duration_since(f.last) < MAX_LOCKOUT);
+
+        let entry = failures.entry(addr).or_insert(Failures { count: 0, lockouts: 0, locked_until: None, last: now, checking: 0 });
+        if let Some(wait) = entry.locked_until.and_then(|until| until.checked_duration_since(now)) {
+            return Err(wait);
+        }
+        if entry.count + entry.checking >= MAX_FAILURES {
+            return Err(FAILURE_DELAY);
+        }
+        entry.checking += 1;
+        entry.last = now;
+        Ok(AuthAttempt { limiter: self, addr })
+    }
+}
+
+//one password being checked, taken from AuthLimiter::begin
+//dropping it without saying how it went gives the attempt back
+pub struct AuthAttempt<'a> {
+    limiter: &'a AuthLimiter,
+    addr: IpAddr,
+}
+
+impl AuthAttempt<'_> {
+    //a correct password clears the address's history
+    pub fn succeeded(self) {
+        let mut failures = self.limiter.failures.lock().unwrap();
+        if let Some(entry) = failures.get_mut(&self.addr) {
+            entry.count = 0;
+            entry.lockouts = 0;
+            entry.locked_until = None;
+        }
+    }
+
+    //count a wrong password, returns how long addr is now locked out for if this was one too many
+    //otherwise the address only has to wait FAILURE_DELAY before its next attempt
+    pub fn failed(self) -> Option<Duration> {
+        let mut failures = self.limiter.failures.lock().unwrap();
+        let entry = failures.get_mut(&self.addr)?;
+        let now = Instant::now();
+        entry.count += 1;
+        entry.last = now;
+        //another attempt may have locked the address out while this one was checked, that lockout stands
+        if entry.count < MAX_FAILURES {
+            entry.locked_until = entry.locked_until.max(Some(now + FAILURE_DELAY));
+            return None;
+        }
+        let lockout = LOCKOUT.saturating_mul(1 << entry.lockouts.min(6)).min(MAX_LOCKOUT);
+        entry.count = 0;
+        entry.lockouts += 1;
+        entry.locked_until = entry.locked_until.max(Some(now + lockout));
+        Some(lockout)
+    }
+}
+
+impl Drop for AuthAttempt<'_> {
+    fn drop(&mut self) {
+        let mut failures = self.limiter.failures.lock().unwrap();
+        if let Some(entry) = failures.get_mut(&self.addr) {
+            entry.checking -= 1;
+            if entry.is_clear() {
+                failures.remove(&self.addr);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv4Addr;
+    use super::*;
+
+    const ADDR: IpAddr = IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1));
+    const OTHER: IpAddr = IpAddr::V4(Ipv4Addr::new(192, 0, 2, 2));
+
+    #[test]
+    fn attempts_in_progress_count_against_the_limit() {
+        let limiter = AuthLimiter::new();
+        let attempts: Vec<_> = (0..MAX_FAILURES).map(|_| limiter.begin(ADDR).unwrap()).collect();
+        assert!(limiter.begin(ADDR).is_err());
+        assert!(limiter.begin(OTHER).is_ok());
+
+        //every one of them fails, the last locks the address out
+        let lockouts: Vec<_> = attempts.into_iter().map(AuthAttempt::failed).collect();
+        assert_eq!(lockouts.iter().filter(|lockout| lockout.is_some()).count(), 1);
+        assert_eq!(lockouts.last(), Some(&Some(LOCKOUT)));
+        assert!(limiter.locked_for(ADDR).is_some_and(|wait| wait > LOCKOUT - Duration::from_secs(5)));
+        assert!(limiter.begin(ADDR).is_err());
+    }
+
+    #[test]
+    fn a_wrong_password_makes_the_address_wait_before_the_next() {
+        let limiter = AuthLimiter::new();
+        assert_eq!(limiter.begin(ADDR).unwrap().failed(), None);
+        let wait = limiter.begin(ADDR).err().unwrap();
+        assert!(wait > Duration::ZERO && wait <= FAILURE_DELAY);
+        assert!(limiter.locked_for(OTHER).is_none());
+    }
+
+    #[test]
+    fn undecided_attempts_are_given_back_and_success_clears_the_address() {
+        let limiter = AuthLimiter::new();
+        for _ in 0..MAX_FAILURES * 2 {
+            drop(limiter.begin(ADDR).unwrap());
+        }
+        let failing = limiter.begin(ADDR).unwrap();
+        let succeeding = limiter.begin(ADDR).unwrap();
+        failing.failed();
+        succeeding.succeeded();
+        assert!(limiter.locked_for(ADDR).is_none());
+        assert!(limiter.failures.lock().unwrap().is_empty());
+    }
+}
@@ -1,34 +1,111 @@
-use std::sync::mpsc::{channel, Receiver};
-use std::time::Duration;
+use std::{
+    error::Error,
+    sync::{
+        mpsc::{ channel, Receiver },
+        atomic::{ AtomicBool, Ordering },
+        Arc,
+    },
+    time::{ Duration, Instant },
+};
 use xcap::Monitor;
+use super::{ CaptureSource, Frame, PixelFormat };
 
-pub fn start_sck_stream() -> Receiver<(usize, usize, Vec<u8>)> {
-    let (tx, rx) = channel();
+//captures one monitor through xcap
+pub struct XcapSource {
+    //index into xcap's monitor list
+    monitor: usize,
+    //set to tell the running capture thread to exit
+    stop: Option<Arc<AtomicBool>>,
+    dimensions: Option<(usize, usize)>,
+}
 
-    std::thread::spawn(move || {
-        // Get all monitors
-        let monitors = Monitor::all().expect("Failed to query monitors");
-        let monitor = monitors.first().expect("No monitors found");
+impl XcapSource {
+    pub fn new(monitor: usize) -> Self {
+        XcapSource { monitor, stop: None, dimensions: None }
+    }
+}
 
-        loop {
-            match monitor.capture_image() {
-                Ok(img) => {
-                    let width = img.width() as usize;
-                    let height = img.height() as usize;
+impl Default for XcapSource {
+    fn default() -> Self {
+        Self::new(0)
+    }
+}
 
-                    // Convert image to RGBA8
-                    let rgba = img.into_raw();
+impl CaptureSource for XcapSource {
+    fn name(&self) -> &str {
+        "xcap"
+    }
 
-                    let _ = tx.send((width, height, rgba));
-                }
+    fn start(&mut self) -> Result<Receiver<Frame>, Box<dyn Error>> {
+        // Get all monitors, failing here instead of on the capture thread gives the caller a real error
+        let monitors = Monitor::all()?;
+        let monitor = monitors.get(self.monitor)
+            .ok_or_else(|| format!("No monitor {}, found {} monitors", self.monitor, monitors.len()))?;
+        self.dimensions = Some((monitor.width() as usize, monitor.height() as usize));
+
+        let (tx, rx) = channel();
+        let stop = Arc::new(AtomicBool::new(false));
+        self.stop = Some(stop.clone());
+
+        let index = self.monitor;
+        std::thread::spawn(move || {
+            //monitors are looked up again because they can't be moved across threads
+            let monitors = match Monitor::all() {
+                Ok(monitors) => monitors,
                 Err(err) => {
-                    eprintln!("Capture error: {:?}", err);
-                    std::thread::sleep(Duration::from_millis(16));
+                    eprintln!("Failed to query monitors: {:?}", err);
+                    return;
+                }
+            };
+            let Some(monitor) = monitors.get(index) else {
+                eprintln!("Monitor {index} went away");
+                return;
+            };
+            let started = Instant::now();
+
+            while !stop.load(Ordering::Relaxed) {
+                match monitor.capture_image() {
+                    Ok(img) => {
+                        let width = img.width() as usize;
+                        let height = img.height() as usize;
+                        let timestamp = started.elapsed();
+
+                        // Convert image to RGBA8
+                        let data = img.into_raw();
+
+                        //stop capturing once nobody is receiving frames
+                        let frame = Frame { width, height, format: PixelFormat::Rgba8, timestamp, data };
+                        if tx.send(frame).is_err() {
+                            break;
+                        }
+                    }
+                    Err(err) => {
+                        eprintln!("Capture error: {:?}", err);
+                        std::thread::sleep(Duration::from_millis(16));
+                    }
                 }
             }
+        });
+
+        Ok(rx)
+    }
+
+    fn stop(&mut self) {
+        if let Some(stop) = self.stop.take() {
+            stop.store(true, Ordering::Relaxed);
         }
-    });
+    }
 
-    rx
-}
+    //before capture starts the monitor is looked up so callers can size things ahead of time
+    fn dimensions(&self) -> Option<(usize, usize)> {
+        self.dimensions.or_else(|| {
+            let monitors = Monitor::all().ok()?;
+            let monitor = monitors.get(self.monitor)?;
+            Some((monitor.width() as usize, monitor.height() as usize))
+        })
+    }
 
+    fn pixel_format(&self) -> PixelFormat {
+        PixelFormat::Rgba8
+    }
+}
@@ -4,13 +4,18 @@ use std::sync::{
     Mutex, LazyLock,
 };
 use std::os::raw::{c_uint, c_uchar};
+use std::error::Error;
+use std::time::Instant;
+use core_graphics::display::CGDisplay;
+use super::{ CaptureSource, Frame, PixelFormat };
 
-// Safe global sender storage
-static FRAME_SENDER: LazyLock<Mutex<Option<Sender<(usize, usize, Vec<u8>)>>>> =
+// Safe global sender storage, along with when capture started for frame timestamps
+static FRAME_SENDER: LazyLock<Mutex<Option<(Sender<Frame>, Instant)>>> =
     LazyLock::new(|| Mutex::new(None));
 
 unsafe extern "C" {
     fn sck_start_capture(cb: extern "C" fn(*const c_uchar, c_uint, c_uint, c_uint));
+    fn sck_stop_capture();
 }
 
 // This callback is invoked from Objective-C each time a new frame is ready
@@ -41,15 +46,67 @@ extern "C" fn sck_frame_cb(
     }
 
     // Send to main server loop
-    let guard = FRAME_SENDER.lock().unwrap();
-    if let Some(sender) = &*guard {
-        let _ = sender.send((width, height, rgba));
+    let mut guard = FRAME_SENDER.lock().unwrap();
+    let receiver_gone = match &*guard {
+        Some((sender, started)) => {
+            // maccapture.m already converted BGRA to RGBA
+            let frame = Frame { width, height, format: PixelFormat::Rgba8, timestamp: started.elapsed(), data: rgba };
+            sender.send(frame).is_err()
+        }
+        None => false,
+    };
+    //receiver was dropped, nobody is watching so stop ScreenCaptureKit until the next start
+    if receiver_gone {
+        *guard = None;
+        unsafe { sck_stop_capture() };
+    }
+}
+
+//captures the main display through ScreenCaptureKit
+pub struct ScreenCaptureKitSource {
+    running: bool,
+}
+
+impl ScreenCaptureKitSource {
+    pub fn new() -> Self {
+        ScreenCaptureKitSource { running: false }
     }
 }
 
-pub fn start_sck_stream() -> Receiver<(usize, usize, Vec<u8>)> {
-    let (tx, rx) = channel();
-    *FRAME_SENDER.lock().unwrap() = Some(tx);
-    unsafe { sck_start_capture(sck_frame_cb) };
-    rx
-}
\ No newline at end of file
+impl Default for ScreenCaptureKitSource {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl CaptureSource for ScreenCaptureKitSource {
+    fn name(&self) -> &str {
+        "ScreenCaptureKit"
+    }
+
+    fn start(&mut self) -> Result<Receiver<Frame>, Box<dyn Error>> {
+        let (tx, rx) = channel();
+        *FRAME_SENDER.lock().unwrap() = Some((tx, Instant::now()));
+        unsafe { sck_start_capture(sck_frame_cb) };
+        self.running = true;
+        Ok(rx)
+    }
+
+    fn stop(&mut self) {
+        if self.running {
+            *FRAME_SENDER.lock().unwrap() = None;
+            unsafe { sck_stop_capture() };
+            self.running = false;
+        }
+    }
+
+    fn dimensions(&self) -> Option<(usize, usize)> {
+        // ScreenCaptureKit is configured with the display size in points
+        let bounds = CGDisplay::main().bounds();
+        Some((bounds.size.width as usize, bounds.size.height as usize))
+    }
+
+    fn pixel_format(&self) -> PixelFormat {
+        PixelFormat::Rgba8
+    }
+}
@@ -1,9 +1,71 @@
+use std::{
+    error::Error,
+    sync::mpsc::Receiver,
+    time::Duration,
+};
+
 #[cfg(target_os = "macos")]
 mod mac;
 #[cfg(target_os = "macos")]
-pub use mac::start_sck_stream;
+pub use mac::ScreenCaptureKitSource;
 
 #[cfg(target_os = "linux")]
 mod linux;
 #[cfg(target_os = "linux")]
-pub use linux::start_sck_stream;
\ No newline at end of file
+pub use linux::XcapSource;
+
+mod synthetic;
+pub use synthetic::{ SyntheticSource, SyntheticPattern };
+
+mod shared;
+pub use shared::SharedCapture;
+
+//byte order of the pixels in a captured frame, 4 bytes per pixel either way
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum PixelFormat {
+    Rgba8,
+    Bgra8,
+}
+
+//one captured image
+pub struct Frame {
+    pub width: usize,
+    pub height: usize,
+    pub format: PixelFormat,
+    //time since the source was started
+    pub timestamp: Duration,
+    //width * height * 4 bytes with no row padding
+    pub data: Vec<u8>,
+}
+
+//anything that can produce a stream of frames, a real screen or a generated test pattern
+pub trait CaptureSource: Send {
+    //short name used in logs
+    fn name(&self) -> &str;
+
+    //start producing frames, the stream ends when stop is called or the receiver is dropped
+    fn start(&mut self) -> Result<Receiver<Frame>, Box<dyn Error>>;
+
+    //stop producing frames, start can be called again afterwards
+    fn stop(&mut self);
+
+    //size of the frames this source produces, None if it isn't known until capture starts
+    fn dimensions(&self) -> Option<(usize, usize)>;
+
+    fn pixel_format(&self) -> PixelFormat;
+}
+
+//screen capture backend for this platform, monitor counts from 0 in the order the platform lists them
+#[cfg(target_os = "linux")]
+pub fn screen_source(monitor: usize) -> Box<dyn CaptureSource> {
+    Box::new(XcapSource::new(monitor))
+}
+
+//screen capture backend for this platform, ScreenCaptureKit always captures the main display
+#[cfg(target_os = "macos")]
+pub fn screen_source(monitor: usize) -> Box<dyn CaptureSource> {
+    if monitor != 0 {
+        println!("Monitor selection is not supported on macos, capturing the main display");
+    }
+    Box::new(ScreenCaptureKitSource::new())
+}
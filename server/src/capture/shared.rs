@@ -0,0 +1,278 @@
+use std::{
+    collections::VecDeque,
+    sync::{
+        mpsc::{ Receiver, RecvTimeoutError },
+        Arc, Mutex, Condvar,
+    },
+    time::Duration,
+};
+use super::{ CaptureSource, Frame };
+
+//captured frame shared between clients without copying
+pub type SharedFrame = Arc<Frame>;
+
+//frames a subscriber can have waiting before the oldest one is dropped
+const SUBSCRIBER_QUEUE_DEPTH: usize = 2;
+//how often the pump checks if everyone left while no frames are arriving
+const IDLE_CHECK_INTERVAL: Duration = Duration::from_millis(200);
+
+//one subscriber's pending frames, a slow subscriber loses its oldest frames instead of holding up the others
+struct FrameQueue {
+    state: Mutex<QueueState>,
+    ready: Condvar,
+}
+
+struct QueueState {
+    frames: VecDeque<SharedFrame>,
+    //set when the capture stream ends so waiting subscribers wake up
+    closed: bool,
+    //frames thrown away because the subscriber fell behind
+    dropped: u64,
+}
+
+impl FrameQueue {
+    fn new() -> Self {
+        FrameQueue {
+            state: Mutex::new(QueueState { frames: VecDeque::new(), closed: false, dropped: 0 }),
+            ready: Condvar::new(),
+        }
+    }
+
+    fn push(&self, frame: SharedFrame) {
+        let mut state = self.state.lock().unwrap();
+        if state.frames.len() >= SUBSCRIBER_QUEUE_DEPTH {
+            state.frames.pop_front();
+            state.dropped += 1;
+        }
+        state.frames.push_back(frame);
+        self.ready.notify_all();
+    }
+
+    fn close(&self) {
+        self.state.lock().unwrap().closed = true;
+        self.ready.notify_all();
+    }
+}
+
+//one capture stream whose frames are broadcast to every subscribed client
+//capture starts with the first subscriber and stops after the last one leaves
+pub struct SharedCapture {
+    state: Mutex<CaptureState>,
+}
+
+struct CaptureState {
+    source: Box<dyn CaptureSource>,
+    subscribers: Vec<(u64, Arc<FrameQueue>)>,
+    next_id: u64,
+    //true while a pump thread owns a capture stream
+    running: bool,
+    //most recent frame, given to new subscribers so they don't wait for the screen to change
+    latest: Option<SharedFrame>,
+}
+
+impl SharedCapture {
+    pub fn new(source: Box<dyn CaptureSource>) -> Arc<SharedCapture> {
+        Arc::new(SharedCapture {
+            state: Mutex::new(CaptureState {
+                source,
+                subscribers: Vec::new(),
+                next_id: 0,
+                running: false,
+                latest: None,
+            }),
+        })
+    }
+
+    //start receiving frames, starts the capture stream if nobody else is watching
+    pub fn subscribe(self: &Arc<Self>) -> FrameSubscription {
+        let queue = Arc::new(FrameQueue::new());
+        let mut state = self.state.lock().unwrap();
+
+        let id = state.next_id;
+        state.next_id += 1;
+        if let Some(frame) = &state.latest {
+            queue.push(frame.clone());
+        }
+        state.subscribers.push((id, queue.clone()));
+
+        if !state.running {
+            match state.source.start() {
+                Ok(rx) => {
+                    state.running = true;
+                    let pump = self.clone();
+                    std::thread::spawn(move || pump.pump(rx));
+                    let (w, h) = state.source.dimensions().unwrap_or((0, 0));
+                    println!("Capture started: {} {}x{} {:?}", state.source.name(), w, h, state.source.pixel_format());
+                }
+                Err(e) => {
+                    //the subscriber sees the stream as already ended, the next subscriber tries again
+                    eprintln!("Failed to start {} capture: {e}", state.source.name());
+                    state.subscribers.retain(|(sub_id, _)| *sub_id != id);
+                    queue.close();
+                }
+            }
+        }
+
+        FrameSubscription { id, queue, capture: self.clone() }
+    }
+
+    fn unsubscribe(&self, id: u64) {
+        self.state.lock().unwrap().subscribers.retain(|(sub_id, _)| *sub_id != id);
+    }
+
+    //forward frames from the capture stream to every subscriber until the stream ends or nobody is left
+    fn pump(&self, rx: Receiver<Frame>) {
+        loop {
+            let frame = match rx.recv_timeout(IDLE_CHECK_INTERVAL) {
+                Ok(frame) => Some(Arc::new(frame)),
+                Err(RecvTimeoutError::Timeout) => None,
+                Err(RecvTimeoutError::Disconnected) => {
+                    eprintln!("Capture stream ended");
+                    let mut state = self.state.lock().unwrap();
+                    for (_, queue) in state.subscribers.drain(..) {
+                        queue.close();
+                    }
+                    state.source.stop();
+                    state.running = false;
+                    state.latest = None;
+                    return;
+                }
+            };
+
+            let mut state = self.state.lock().unwrap();
+            if state.subscribers.is_empty() {
+                state.source.stop();
+                state.running = false;
+                state.latest = None;
+                println!("Last viewer left, capture stopped");
+                return;
+            }
+            if let Some(frame) = frame {
+                state.latest = Some(frame.clone());
+                for (_, queue) in &state.subscribers {
+                    queue.push(frame.clone());
+                }
+            }
+        }
+    }
+}
+
+//a client's handle on the shared capture, unsubscribes when dropped
+pub struct FrameSubscription {
+    id: u64,
+    queue: Arc<FrameQueue>,
+    capture: Arc<SharedCapture>,
+}
+
+impl FrameSubscription {
+    //wait for a frame and return the newest one, skipping older frames still queued
+    //None once the capture stream has ended
+    pub fn recv_latest(&self) -> Option<SharedFrame> {
+        let mut state = self.queue.state.lock().unwrap();
+        while state.frames.is_empty() && !state.closed {
+            state = self.queue.ready.wait(state).unwrap();
+        }
+        let latest = state.frames.pop_back();
+        let skipped = state.frames.len() as u64;
+        state.frames.clear();
+        state.dropped += skipped;
+        latest
+    }
+
+    //frames this subscriber never saw because it fell behind
+    pub fn dropped(&self) -> u64 {
+        self.queue.state.lock().unwrap().dropped
+    }
+}
+
+impl Drop for FrameSubscription {
+    fn drop(&mut self) {
+        self.capture.unsubscribe(self.id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{ error::Error, sync::atomic::{ AtomicUsize, Ordering } };
+    use super::*;
+    use crate::capture::{ PixelFormat, SyntheticPattern, SyntheticSource };
+
+    //synthetic source that counts how often it is started and stopped
+    struct Counted {
+        inner: SyntheticSource,
+        starts: Arc<AtomicUsize>,
+        stops: Arc<AtomicUsize>,
+    }
+
+    impl CaptureSource for Counted {
+        fn name(&self) -> &str {
+            self.inner.name()
+        }
+
+        fn start(&mut self) -> Result<Receiver<Frame>, Box<dyn Error>> {
+            self.starts.fetch_add(1, Ordering::SeqCst);
+            self.inner.start()
+        }
+
+        fn stop(&mut self) {
+            self.stops.fetch_add(1, Ordering::SeqCst);
+            self.inner.stop();
+        }
+
+        fn dimensions(&self) -> Option<(usize, usize)> {
+            self.inner.dimensions()
+        }
+
+        fn pixel_format(&self) -> PixelFormat {
+            self.inner.pixel_format()
+        }
+    }
+
+    fn counted() -> (Arc<SharedCapture>, Arc<AtomicUsize>, Arc<AtomicUsize>) {
+        let starts = Arc::new(AtomicUsize::new(0));
+        let stops = Arc::new(AtomicUsize::new(0));
+        let source = Counted {
+            inner: SyntheticSource::new(SyntheticPattern::TestCard, 32, 24, 200),
+            starts: starts.clone(),
+            stops: stops.clone(),
+        };
+        (SharedCapture::new(Box::new(source)), starts, stops)
+    }
+
+    fn wait_for(what: &str, done: impl Fn() -> bool) {
+        let deadline = std::time::Instant::now() + Duration::from_secs(5);
+        while !done() {
+            assert!(std::time::Instant::now() < deadline, "timed out waiting for {what}");
+            std::thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    #[test]
+    fn every_subscriber_gets_frames_from_one_capture_stream() {
+        let (capture, starts, _) = counted();
+        let first = capture.subscribe();
+        let second = capture.subscribe();
+        for subscription in [&first, &second] {
+            let frame = subscription.recv_latest().unwrap();
+            assert_eq!((frame.width, frame.height, frame.format), (32, 24, PixelFormat::Rgba8));
+        }
+        assert_eq!(starts.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn capture_stops_after_the_last_subscriber_leaves_and_restarts_for_the_next() {
+        let (capture, starts, stops) = counted();
+        let first = capture.subscribe();
+        let second = capture.subscribe();
+        first.recv_latest().unwrap();
+        drop(first);
+        second.recv_latest().unwrap();
+        assert_eq!(stops.load(Ordering::SeqCst), 0);
+        drop(second);
+        wait_for("the capture to stop", || stops.load(Ordering::SeqCst) == 1);
+
+        let third = capture.subscribe();
+        assert!(third.recv_latest().is_some());
+        assert_eq!(starts.load(Ordering::SeqCst), 2);
+    }
+}
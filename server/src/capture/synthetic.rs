@@ -0,0 +1,336 @@
+use std::{
+    error::Error,
+    sync::{
+        mpsc::{ channel, Receiver },
+        atomic::{ AtomicBool, Ordering },
+        Arc,
+    },
+    time::{ Duration, Instant },
+};
+use super::{ CaptureSource, Frame, PixelFormat };
+
+//generated images for running without a display, frame n always renders the same pixels
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum SyntheticPattern {
+    //color gradients sliding across the screen, every pixel changes every frame
+    Gradient,
+    //lines of text scrolling upwards, lots of sharp edges like a terminal or document
+    ScrollingText,
+    //color bars and a gray ramp with a moving box and frame counter, mostly static
+    TestCard,
+}
+
+//capture source that renders a test pattern at a fixed size and frame rate
+pub struct SyntheticSource {
+    pattern: SyntheticPattern,
+    width: usize,
+    height: usize,
+    fps: u32,
+    //set to tell the running render thread to exit
+    stop: Option<Arc<AtomicBool>>,
+}
+
+impl SyntheticSource {
+    pub fn new(pattern: SyntheticPattern, width: usize, height: usize, fps: u32) -> Self {
+        SyntheticSource { pattern, width, height, fps: fps.max(1), stop: None }
+    }
+
+    //render frame number n as RGBA
+    pub fn render(&self, n: u64) -> Vec<u8> {
+        render_pattern(self.pattern, self.width, self.height, n)
+    }
+}
+
+impl CaptureSource for SyntheticSource {
+    fn name(&self) -> &str {
+        match self.pattern {
+            SyntheticPattern::Gradient => "synthetic gradient",
+            SyntheticPattern::ScrollingText => "synthetic text",
+            SyntheticPattern::TestCard => "synthetic test card",
+        }
+    }
+
+    fn start(&mut self) -> Result<Receiver<Frame>, Box<dyn Error>> {
+        if self.width == 0 || self.height == 0 {
+            return Err(format!("Invalid synthetic capture size {}x{}", self.width, self.height).into());
+        }
+
+        let (tx, rx) = channel();
+        let stop = Arc::new(AtomicBool::new(false));
+        self.stop = Some(stop.clone());
+
+        let (pattern, width, height) = (self.pattern, self.width, self.height);
+        let frame_interval = Duration::from_secs(1) / self.fps;
+
+        std::thread::spawn(move || {
+            let started = Instant::now();
+            let mut n: u64 = 0;
+
+            while !stop.load(Ordering::Relaxed) {
+                //timestamps come from the frame number, not the clock, so runs are repeatable
+                let timestamp = frame_interval * n as u32;
+                let data = render_pattern(pattern, width, height, n);
+                let frame = Frame { width, height, format: PixelFormat::Rgba8, timestamp, data };
+                if tx.send(frame).is_err() {
+                    break;
+                }
+
+                //pace to the frame rate, skipping the sleep if rendering fell behind
+                n += 1;
+                let next = started + frame_interval * n as u32;
+                let now = Instant::now();
+                if next > now {
+                    std::thread::sleep(next - now);
+                }
+            }
+        });
+
+        Ok(rx)
+    }
+
+    fn stop(&mut self) {
+        if let Some(stop) = self.stop.take() {
+            stop.store(true, Ordering::Relaxed);
+        }
+    }
+
+    fn dimensions(&self) -> Option<(usize, usize)> {
+        Some((self.width, self.height))
+    }
+
+    fn pixel_format(&self) -> PixelFormat {
+        PixelFormat::Rgba8
+    }
+}
+
+fn render_pattern(pattern: SyntheticPattern, width: usize, height: usize, n: u64) -> Vec<u8> {
+    let mut rgba = vec![0u8; width * height * 4];
+    match pattern {
+        SyntheticPattern::Gradient => draw_gradient(&mut rgba, width, height, n),
+        SyntheticPattern::ScrollingText => draw_scrolling_text(&mut rgba, width, height, n),
+        SyntheticPattern::TestCard => draw_test_card(&mut rgba, width, height, n),
+    }
+    rgba
+}
+
+fn draw_gradient(rgba: &mut [u8], width: usize, height: usize, n: u64) {
+    let shift = n as usize;
+    for y in 0..height {
+        for x in 0..width {
+            let i = (y * width + x) * 4;
+            rgba[i] = ((x * 255 / width + shift * 3) % 256) as u8;
+            rgba[i + 1] = ((y * 255 / height + shift * 2) % 256) as u8;
+            rgba[i + 2] = (((x + y) / 4 + shift) % 256) as u8;
+            rgba[i + 3] = 255;
+        }
+    }
+}
+
+fn draw_scrolling_text(rgba: &mut [u8], width: usize, height: usize, n: u64) {
+    //dark background
+    fill_rect(rgba, width, height, 0, 0, width, height, [16, 16, 24]);
+
+    let scale = 2;
+    let line_height = (GLYPH_HEIGHT + 3) * scale;
+    //scroll 2 pixels per frame
+    let scroll = n as usize * 2;
+    let first_line = scroll / line_height;
+    let offset = scroll % line_height;
+
+    for row in 0..=height / line_height + 1 {
+        let line = first_line + row;
+        let text = format!("{:06}  THE QUICK BROWN FOX JUMPS OVER THE LAZY DOG - 0123456789", line);
+        let y = (row * line_height) as isize - offset as isize;
+        //alternate line colors so motion is easy to follow
+        let color = if line.is_multiple_of(2) { [220, 220, 220] } else { [120, 200, 120] };
+        draw_text(rgba, width, height, 8, y, &text, scale, color);
+    }
+}
+
+fn draw_test_card(rgba: &mut [u8], width: usize, height: usize, n: u64) {
+    //75% color bars over the top two thirds
+    const BARS: [[u8; 3]; 7] = [
+        [191, 191, 191], [191, 191, 0], [0, 191, 191], [0, 191, 0],
+        [191, 0, 191], [191, 0, 0], [0, 0, 191],
+    ];
+    let bars_height = height * 2 / 3;
+    for (i, color) in BARS.iter().enumerate() {
+        let x0 = width * i / BARS.len();
+        let x1 = width * (i + 1) / BARS.len();
+        fill_rect(rgba, width, height, x0, 0, x1 - x0, bars_height, *color);
+    }
+
+    //gray ramp across the bottom third
+    for y in bars_height..height {
+        for x in 0..width {
+            let v = (x * 255 / (width - 1).max(1)).min(255) as u8;
+            let i = (y * width + x) * 4;
+            rgba[i..i + 4].copy_from_slice(&[v, v, v, 255]);
+        }
+    }
+
+    //white box sliding across the bars so there is some motion to encode
+    let box_size = (height / 8).max(1);
+    let travel = width.saturating_sub(box_size).max(1);
+    let box_x = (n as usize * 8) % travel;
+    fill_rect(rgba, width, height, box_x, (bars_height / 2).saturating_sub(box_size / 2), box_size, box_size, [255, 255, 255]);
+
+    //frame counter in the top left corner
+    let label = format!("FRAME {:08}", n);
+    let scale = 3;
+    fill_rect(rgba, width, height, 0, 0, (label.len() * (GLYPH_WIDTH + 1) + 2) * scale, (GLYPH_HEIGHT + 2) * scale, [0, 0, 0]);
+    draw_text(rgba, width, height, scale as isize, scale as isize, &label, scale, [255, 255, 255]);
+}
+
+#[allow(clippy::too_many_arguments)]
+fn fill_rect(rgba: &mut [u8], width: usize, height: usize, x: usize, y: usize, w: usize, h: usize, color: [u8; 3]) {
+    for py in y..(y + h).min(height) {
+        for px in x..(x + w).min(width) {
+            let i = (py * width + px) * 4;
+            rgba[i..i + 4].copy_from_slice(&[color[0], color[1], color[2], 255]);
+        }
+    }
+}
+
+const GLYPH_WIDTH: usize = 5;
+const GLYPH_HEIGHT: usize = 7;
+
+//draw text with the built in 5x7 font, pixels outside the frame are clipped
+#[allow(clippy::too_many_arguments)]
+fn draw_text(rgba: &mut [u8], width: usize, height: usize, x: isize, y: isize, text: &str, scale: usize, color: [u8; 3]) {
+    let advance = ((GLYPH_WIDTH + 1) * scale) as isize;
+    for (ci, c) in text.chars().enumerate() {
+        let rows = glyph(c);
+        let gx = x + ci as isize * advance;
+        if gx >= width as isize {
+            break;
+        }
+        for (row, bits) in rows.iter().enumerate() {
+            for col in 0..GLYPH_WIDTH {
+                if bits & (1 << (GLYPH_WIDTH - 1 - col)) == 0 {
+                    continue;
+                }
+                for sy in 0..scale {
+                    for sx in 0..scale {
+                        let px = gx + (col * scale + sx) as isize;
+                        let py = y + (row * scale + sy) as isize;
+                        if px < 0 || py < 0 || px >= width as isize || py >= height as isize {
+                            continue;
+                        }
+                        let i = (py as usize * width + px as usize) * 4;
+                        rgba[i..i + 4].copy_from_slice(&[color[0], color[1], color[2], 255]);
+                    }
+                }
+            }
+        }
+    }
+}
+
+//5x7 bitmap rows for each supported character, top row first, unknown characters are blank
+fn glyph(c: char) -> [u8; GLYPH_HEIGHT] {
+    match c.to_ascii_uppercase() {
+        '0' => [0x0E, 0x11, 0x13, 0x15, 0x19, 0x11, 0x0E],
+        '1' => [0x04, 0x0C, 0x04, 0x04, 0x04, 0x04, 0x0E],
+        '2' => [0x0E, 0x11, 0x01, 0x02, 0x04, 0x08, 0x1F],
+        '3' => [0x1F, 0x02, 0x04, 0x02, 0x01, 0x11, 0x0E],
+        '4' => [0x02, 0x06, 0x0A, 0x12, 0x1F, 0x02, 0x02],
+        '5' => [0x1F, 0x10, 0x1E, 0x01, 0x01, 0x11, 0x0E],
+        '6' => [0x06, 0x08, 0x10, 0x1E, 0x11, 0x11, 0x0E],
+        '7' => [0x1F, 0x01, 0x02, 0x04, 0x08, 0x08, 0x08],
+        '8' => [0x0E, 0x11, 0x11, 0x0E, 0x11, 0x11, 0x0E],
+        '9' => [0x0E, 0x11, 0x11, 0x0F, 0x01, 0x02, 0x0C],
+        'A' => [0x0E, 0x11, 0x11, 0x11, 0x1F, 0x11, 0x11],
+        'B' => [0x1E, 0x11, 0x11, 0x1E, 0x11, 0x11, 0x1E],
+        'C' => [0x0E, 0x11, 0x10, 0x10, 0x10, 0x11, 0x0E],
+        'D' => [0x1C, 0x12, 0x11, 0x11, 0x11, 0x12, 0x1C],
+        'E' => [0x1F, 0x10, 0x10, 0x1E, 0x10, 0x10, 0x1F],
+        'F' => [0x1F, 0x10, 0x10, 0x1E, 0x10, 0x10, 0x10],
+        'G' => [0x0E, 0x11, 0x10, 0x17, 0x11, 0x11, 0x0F],
+        'H' => [0x11, 0x11, 0x11, 0x1F, 0x11, 0x11, 0x11],
+        'I' => [0x0E, 0x04, 0x04, 0x04, 0x04, 0x04, 0x0E],
+        'J' => [0x07, 0x02, 0x02, 0x02, 0x02, 0x12, 0x0C],
+        'K' => [0x11, 0x12, 0x14, 0x18, 0x14, 0x12, 0x11],
+        'L' => [0x10, 0x10, 0x10, 0x10, 0x10, 0x10, 0x1F],
+        'M' => [0x11, 0x1B, 0x15, 0x15, 0x11, 0x11, 0x11],
+        'N' => [0x11, 0x11, 0x19, 0x15, 0x13, 0x11, 0x11],
+        'O' => [0x0E, 0x11, 0x11, 0x11, 0x11, 0x11, 0x0E],
+        'P' => [0x1E, 0x11, 0x11, 0x1E, 0x10, 0x10, 0x10],
+        'Q' => [0x0E, 0x11, 0x11, 0x11, 0x15, 0x12, 0x0D],
+        'R' => [0x1E, 0x11, 0x11, 0x1E, 0x14, 0x12, 0x11],
+        'S' => [0x0F, 0x10, 0x10, 0x0E, 0x01, 0x01, 0x1E],
+        'T' => [0x1F, 0x04, 0x04, 0x04, 0x04, 0x04, 0x04],
+        'U' => [0x11, 0x11, 0x11, 0x11, 0x11, 0x11, 0x0E],
+        'V' => [0x11, 0x11, 0x11, 0x11, 0x11, 0x0A, 0x04],
+        'W' => [0x11, 0x11, 0x11, 0x15, 0x15, 0x15, 0x0A],
+        'X' => [0x11, 0x11, 0x0A, 0x04, 0x0A, 0x11, 0x11],
+        'Y' => [0x11, 0x11, 0x11, 0x0A, 0x04, 0x04, 0x04],
+        'Z' => [0x1F, 0x01, 0x02, 0x04, 0x08, 0x10, 0x1F],
+        ':' => [0x00, 0x0C, 0x0C, 0x00, 0x0C, 0x0C, 0x00],
+        '-' => [0x00, 0x00, 0x00, 0x1F, 0x00, 0x00, 0x00],
+        '.' => [0x00, 0x00, 0x00, 0x00, 0x00, 0x0C, 0x0C],
+        _ => [0x00; GLYPH_HEIGHT],
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PATTERNS: [SyntheticPattern; 3] = [SyntheticPattern::Gradient, SyntheticPattern::ScrollingText, SyntheticPattern::TestCard];
+
+    #[test]
+    fn frame_n_always_renders_the_same_pixels() {
+        for pattern in PATTERNS {
+            let source = SyntheticSource::new(pattern, 320, 240, 30);
+            let again = SyntheticSource::new(pattern, 320, 240, 30);
+            for n in [0, 1, 17, 1000] {
+                assert_eq!(source.render(n), again.render(n), "{pattern:?} frame {n}");
+            }
+            //the patterns move, so consecutive frames differ
+            assert_ne!(source.render(0), source.render(1), "{pattern:?}");
+        }
+    }
+
+    #[test]
+    fn streamed_frames_match_the_reported_size_and_format() {
+        for pattern in PATTERNS {
+            let mut source = SyntheticSource::new(pattern, 40, 30, 1000);
+            let rx = source.start().unwrap();
+            for n in 0..3 {
+                let frame = rx.recv_timeout(Duration::from_secs(5)).unwrap();
+                assert_eq!(Some((frame.width, frame.height)), source.dimensions());
+                assert_eq!(frame.format, source.pixel_format());
+                assert_eq!(frame.data.len(), frame.width * frame.height * 4);
+                assert_eq!(frame.data, source.render(n));
+                assert_eq!(frame.timestamp, Duration::from_millis(n));
+            }
+            source.stop();
+        }
+    }
+
+    #[test]
+    fn stop_ends_the_render_thread() {
+        let mut source = SyntheticSource::new(SyntheticPattern::Gradient, 16, 16, 1000);
+        for _ in 0..2 {
+            let rx = source.start().unwrap();
+            rx.recv_timeout(Duration::from_secs(5)).unwrap();
+            source.stop();
+            //the thread drops its sender when it exits, which ends the stream once the queued frames are read
+            let deadline = Instant::now() + Duration::from_secs(5);
+            loop {
+                match rx.recv_timeout(deadline.saturating_duration_since(Instant::now())) {
+                    Ok(_) => continue,
+                    Err(e) => {
+                        assert_eq!(e, std::sync::mpsc::RecvTimeoutError::Disconnected);
+                        break;
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn an_empty_size_fails_to_start() {
+        assert!(SyntheticSource::new(SyntheticPattern::TestCard, 0, 10, 30).start().is_err());
+    }
+}
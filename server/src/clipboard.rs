@@ -0,0 +1,231 @@
+//one watcher on the server clipboard shared by every session, the same way one capture stream feeds every viewer
+//it starts with the first session that syncs the clipboard and stops after the last one leaves
+use std::{
+    error::Error,
+    sync::{
+        mpsc::{ self, Receiver, RecvTimeoutError, Sender },
+        Arc, Mutex,
+    },
+    thread,
+    time::Duration,
+};
+use common::{ clipboard::ClipboardContent, clipboard_sync::ClipboardSync };
+
+//how long the pump waits for a session's write before checking the watcher for local changes
+const PUMP_INTERVAL: Duration = Duration::from_millis(100);
+
+pub struct SharedClipboard {
+    max_len: usize,
+    state: Mutex<ClipboardState>,
+}
+
+struct ClipboardState {
+    subscribers: Subscribers,
+    //where sessions send what their client copied, only while the pump thread is running
+    writes: Option<Sender<(u64, ClipboardContent)>>,
+}
+
+struct Subscriber {
+    id: u64,
+    //whether this session's client is sent the server clipboard
+    read: bool,
+    changes: Sender<ClipboardContent>,
+}
+
+//the sessions using the clipboard and where their changes go, no clipboard of its own
+#[derive(Default)]
+struct Subscribers {
+    list: Vec<Subscriber>,
+    next_id: u64,
+}
+
+impl Subscribers {
+    //the new session's id and where it is sent changes
+    fn add(&mut self, read: bool) -> (u64, Receiver<ClipboardContent>) {
+        let id = self.next_id;
+        self.next_id += 1;
+        let (changes_transmitter, changes) = mpsc::channel();
+        self.list.push(Subscriber { id, read, changes: changes_transmitter });
+        (id, changes)
+    }
+
+    fn remove(&mut self, id: u64) {
+        self.list.retain(|subscriber| subscriber.id != id);
+    }
+
+    fn is_empty(&self) -> bool {
+        self.list.is_empty()
+    }
+
+    //send content to every session that may read the clipboard, apart from the one it came from
+    fn forward(&self, from: Option<u64>, content: &ClipboardContent) {
+        for subscriber in self.list.iter().filter(|subscriber| subscriber.read && Some(subscriber.id) != from) {
+            let _ = subscriber.changes.send(content.clone());
+        }
+    }
+}
+
+impl SharedClipboard {
+    //clipboards over max_len bytes are neither sent nor applied
+    pub fn new(max_len: usize) -> Arc<SharedClipboard> {
+        Arc::new(SharedClipboard {
+            max_len,
+            state: Mutex::new(ClipboardState { subscribers: Subscribers::default(), writes: None }),
+        })
+    }
+
+    //read: the session is sent changes to the server clipboard, write: it may set the server clipboard
+    //starts the watcher if no other session is using it, which fails if the server has no clipboard to open
+    pub fn subscribe(self: &Arc<Self>, read: bool, write: bool) -> Result<ClipboardSubscription, Box<dyn Error>> {
+        let mut state = self.state.lock().unwrap();
+        let writes = match &state.writes {
+            Some(writes) => writes.clone(),
+            None => {
+                let sync = ClipboardSync::start(true, true, self.max_len)?;
+                let (writes, write_receiver) = mpsc::channel();
+                let pump = self.clone();
+                thread::Builder::new()
+                    .name("clipboard pump".to_string())
+                    .spawn(move || pump.pump(sync, write_receiver))?;
+                state.writes = Some(writes.clone());
+                writes
+            }
+        };
+
+        let (id, changes) = state.subscribers.add(read);
+        Ok(ClipboardSubscription { id, write, writes, changes, clipboard: self.clone() })
+    }
+
+    fn unsubscribe(&self, id: u64) {
+        self.state.lock().unwrap().subscribers.remove(id);
+    }
+
+    //a session's write goes to the others too, unless it is too big for the watcher to apply
+    fn written(&self, from: u64, content: &ClipboardContent) {
+        if content.len() <= self.max_len {
+            self.forward(Some(from), content);
+        }
+    }
+
+    fn forward(&self, from: Option<u64>, content: &ClipboardContent) {
+        self.state.lock().unwrap().subscribers.forward(from, content);
+    }
+
+    //apply sessions' writes and hand out local changes until nobody is left, the watcher stops when sync is dropped
+    fn pump(&self, sync: ClipboardSync, writes: Receiver<(u64, ClipboardContent)>) {
+        loop {
+            match writes.recv_timeout(PUMP_INTERVAL) {
+                //the watcher doesn't report what it was given as a change, so the other sessions are told here
+                Ok((from, content)) => {
+                    self.written(from, &content);
+                    sync.apply(content);
+                }
+                Err(RecvTimeoutError::Timeout) => {}
+                Err(RecvTimeoutError::Disconnected) => return,
+            }
+            while let Some(change) = sync.next_change() {
+                self.forward(None, &change);
+            }
+
+            let mut state = self.state.lock().unwrap();
+            if state.subscribers.is_empty() {
+                state.writes = None;
+                println!("Last clipboard session left, clipboard sync stopped");
+                return;
+            }
+        }
+    }
+}
+
+//a session's handle on the shared clipboard, unsubscribes when dropped
+pub struct ClipboardSubscription {
+    id: u64,
+    write: bool,
+    writes: Sender<(u64, ClipboardContent)>,
+    changes: Receiver<ClipboardContent>,
+    clipboard: Arc<SharedClipboard>,
+}
+
+impl ClipboardSubscription {
+    //put the client's clipboard on the server and pass it on to the other sessions
+    pub fn apply(&self, content: ClipboardContent) {
+        if !self.write {
+            println!("Ignoring clipboard from client, it may not write the server clipboard");
+            return;
+        }
+        let _ = self.writes.send((self.id, content));
+    }
+
+    //next clipboard to send to this session's client, None if nothing changed
+    pub fn next_change(&self) -> Option<ClipboardContent> {
+        self.changes.try_recv().ok()
+    }
+}
+
+impl Drop for ClipboardSubscription {
+    fn drop(&mut self) {
+        self.clipboard.unsubscribe(self.id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn received(changes: &Receiver<ClipboardContent>) -> Vec<ClipboardContent> {
+        changes.try_iter().collect()
+    }
+
+    #[test]
+    fn changes_skip_the_session_they_came_from_and_sessions_that_may_not_read() {
+        let clipboard = SharedClipboard::new(1024);
+        let (writer, writer_changes, reader_changes, blind_changes) = {
+            let mut state = clipboard.state.lock().unwrap();
+            let (writer, writer_changes) = state.subscribers.add(true);
+            let (_, reader_changes) = state.subscribers.add(true);
+            let (_, blind_changes) = state.subscribers.add(false);
+            (writer, writer_changes, reader_changes, blind_changes)
+        };
+
+        let copied = ClipboardContent::text("from a client");
+        clipboard.written(writer, &copied);
+        assert!(received(&writer_changes).is_empty());
+        assert_eq!(received(&reader_changes), vec![copied]);
+        assert!(received(&blind_changes).is_empty());
+
+        //a change on the server itself goes to everyone who may read
+        let local = ClipboardContent::text("from the server");
+        clipboard.forward(None, &local);
+        assert_eq!(received(&writer_changes), vec![local.clone()]);
+        assert_eq!(received(&reader_changes), vec![local]);
+        assert!(received(&blind_changes).is_empty());
+    }
+
+    #[test]
+    fn writes_over_the_limit_are_not_passed_on() {
+        let clipboard = SharedClipboard::new(8);
+        let (writer, reader_changes) = {
+            let mut state = clipboard.state.lock().unwrap();
+            let (writer, _) = state.subscribers.add(true);
+            (writer, state.subscribers.add(true).1)
+        };
+        clipboard.written(writer, &ClipboardContent::text("nine byte"));
+        assert!(received(&reader_changes).is_empty());
+        clipboard.written(writer, &ClipboardContent::text("eight by"));
+        assert_eq!(received(&reader_changes).len(), 1);
+    }
+
+    #[test]
+    fn removed_sessions_are_sent_nothing() {
+        let mut subscribers = Subscribers::default();
+        let (first, first_changes) = subscribers.add(true);
+        let (second, second_changes) = subscribers.add(true);
+        assert_ne!(first, second);
+        subscribers.remove(first);
+        subscribers.forward(None, &ClipboardContent::text("after"));
+        assert!(received(&first_changes).is_empty());
+        assert_eq!(received(&second_changes).len(), 1);
+        subscribers.remove(second);
+        assert!(subscribers.is_empty());
+    }
+}
@@ -0,0 +1,654 @@
+//server settings, read from a TOML file with command line flags taking priority over it
+use std::{
+    collections::HashMap,
+    error::Error,
+    fmt,
+    fs,
+    net::SocketAddr,
+    path::{ Path, PathBuf },
+};
+use clap::{ Parser, Subcommand, ValueEnum };
+use serde::Deserialize;
+use common::{
+    handshake::Codec,
+    clipboard::ClipboardPolicy,
+    fingerprint::parse_fingerprint,
+};
+use crate::{
+    approval::ApprovalPolicy,
+    auth,
+    capture::SyntheticPattern,
+    gen_certs::CertRequest,
+    permissions::Permissions,
+    scale::Resampling,
+    tls::ClientIdentity,
+};
+
+//file read when --config isn't given, it is fine for it not to exist
+const DEFAULT_CONFIG_PATH: &str = "server.toml";
+
+const DEFAULT_BIND: &str = "0.0.0.0:7878";
+const DEFAULT_CERT_PATH: &str = "../certs/server.crt";
+const DEFAULT_KEY_PATH: &str = "../certs/server.key";
+const DEFAULT_BITRATE_KBPS: u32 = 10_000;
+const DEFAULT_FPS: u32 = 30;
+const DEFAULT_SCALE: u32 = 2;
+const DEFAULT_MAX_CLIENTS: usize = 4;
+//a bit over two years, about as long as clients accept for a server certificate
+const DEFAULT_CERT_DAYS: u32 = 825;
+const DEFAULT_APPROVAL_TIMEOUT_SECS: u32 = 60;
+const DEFAULT_AUDIT_LOG_MAX_MB: u32 = 10;
+const DEFAULT_AUDIT_LOG_KEEP: u32 = 5;
+const DEFAULT_MIN_BITRATE_KBPS: u32 = 500;
+const DEFAULT_MIN_FPS: u32 = 5;
+
+//limits checked when the config is loaded so a typo fails at startup instead of mid session
+const MAX_FPS: u32 = 240;
+const MAX_SCALE: u32 = 8;
+const MIN_BITRATE_KBPS: u32 = 100;
+const MAX_BITRATE_KBPS: u32 = 1_000_000;
+const MAX_APPROVAL_TIMEOUT_SECS: u32 = 3600;
+const MAX_AUDIT_LOG_MAX_MB: u32 = 1024;
+const MAX_AUDIT_LOG_KEEP: u32 = 100;
+
+//every flag can also be set in the config file under the same name with - replaced by _
+#[derive(Parser, Debug)]
+#[command(name = "server", version, about = "Remote desktop server")]
+struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+    #[arg(short, long, env = "SERVER_CONFIG", help = "TOML config file, server.toml in the working directory is used if it exists")]
+    config: Option<PathBuf>,
+    #[arg(long, env = "SERVER_BIND", help = "address and port to listen on")]
+    bind: Option<String>,
+    #[arg(long, help = "PEM certificate chain presented to clients")]
+    cert: Option<PathBuf>,
+    #[arg(long, help = "PEM PKCS#8 private key for the certificate")]
+    key: Option<PathBuf>,
+    #[arg(long, help = "PEM CA bundle client certificates must chain to, clients need a certificate when this is set")]
+    client_ca: Option<PathBuf>,
+    #[arg(long = "allow-client", value_name = "CLIENT", help = "client certificate let in, sha256:<fingerprint> or a subject common name, can be repeated, any certificate from client_ca when unset")]
+    allowed_clients: Vec<String>,
+    #[arg(long, env = "SERVER_PASSWORD_HASH", help = "argon2 hash of the password clients must give, made with the hash-password command")]
+    password_hash: Option<String>,
+    #[arg(long, value_delimiter = ',', help = "what clients may do unless client_permissions or their invite says otherwise: all, or any of view, pointer, keyboard, clipboard-read and clipboard-write")]
+    permissions: Vec<String>,
+    #[arg(long, value_enum, env = "SERVER_APPROVAL", help = "what happens to a client once it is authenticated: auto lets it in, deny turns it away, ask asks on the server's terminal")]
+    approval: Option<ApprovalPolicy>,
+    #[arg(long, help = "seconds to wait for an answer when approval is ask before the client is turned away")]
+    approval_timeout_secs: Option<u32>,
+    #[arg(long, env = "SERVER_AUDIT_LOG", help = "file to append a JSON line to for every session, clipboard transfer and refused action")]
+    audit_log: Option<PathBuf>,
+    #[arg(long, help = "size in megabytes the audit log grows to before it is rotated")]
+    audit_log_max_mb: Option<u32>,
+    #[arg(long, help = "rotated audit logs kept as audit_log.1, .2 and so on")]
+    audit_log_keep: Option<u32>,
+    #[arg(long, value_enum, help = "video codec to stream with, only h264 for now")]
+    codec: Option<VideoCodec>,
+    #[arg(long, help = "target video bitrate in kilobits per second")]
+    bitrate_kbps: Option<u32>,
+    #[arg(long, help = "most frames per second sent to a client")]
+    fps: Option<u32>,
+    #[arg(long, help = "divide the captured width and height by this before encoding for clients that don't send their window size, 1 streams at full size")]
+    scale: Option<u32>,
+    #[arg(long, value_enum, help = "how the stream is fitted to a client's window: quality resamples to any size, fast only divides by whole numbers")]
+    resampling: Option<Resampling>,
+    #[arg(long, help = "lower bitrate, frame rate and then resolution for clients whose connection can't keep up, true or false, off unless set so a stream stays at the configured quality")]
+    adaptive: Option<bool>,
+    #[arg(long, help = "lowest bitrate adaptation goes down to, in kilobits per second")]
+    min_bitrate_kbps: Option<u32>,
+    #[arg(long, help = "lowest frame rate adaptation goes down to")]
+    min_fps: Option<u32>,
+    #[arg(long, help = "largest scale adaptation goes up to, twice scale by default")]
+    max_scale: Option<u32>,
+    #[arg(long, help = "monitor to capture, counting from 0")]
+    monitor: Option<usize>,
+    #[arg(long, value_enum, env = "SERVER_CAPTURE", help = "the screen, or a test pattern to stream instead")]
+    capture: Option<Capture>,
+    #[arg(long, value_enum, help = "which client input is let through")]
+    allow_input: Option<AllowedInput>,
+    #[arg(long, value_enum, env = "SERVER_INPUT", help = "how input is injected, picked automatically if unset, record drops it")]
+    input_backend: Option<InputBackend>,
+    #[arg(long, value_enum, env = "SERVER_CLIPBOARD", help = "clipboard sync direction")]
+    clipboard: Option<ClipboardPolicy>,
+    #[arg(long, env = "SERVER_MAX_CLIENTS", help = "most clients connected at once")]
+    max_clients: Option<usize>,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    #[command(about = "Read a password or token and print the password_hash to put in the config")]
+    HashPassword,
+    #[command(about = "Create a local CA and a server certificate signed by it")]
+    GenCerts {
+        #[arg(required = true, help = "host names and IP addresses clients connect to, the first is also the certificate's common name")]
+        names: Vec<String>,
+        #[arg(long, default_value = "../certs", help = "directory to write ca.crt, ca.key, server.crt and server.key to")]
+        out_dir: PathBuf,
+        #[arg(long, default_value_t = DEFAULT_CERT_DAYS, help = "days the server certificate is valid, the CA is valid ten times as long")]
+        days: u32,
+        #[arg(long, help = "replace certificates that already exist in out-dir")]
+        force: bool,
+    },
+    #[command(about = "Make a token that lets someone connect with limited permissions and print the config to add for it")]
+    Invite {
+        #[arg(long, default_value = "invite", help = "name the invite is logged under")]
+        name: String,
+        #[arg(long, value_delimiter = ',', default_value = "view", help = "what the invite allows, view on its own makes a view-only invite")]
+        permissions: Vec<String>,
+    },
+}
+
+//the config file, every key is optional and unknown keys are an error so typos don't go unnoticed
+#[derive(Deserialize, Debug, Default)]
+#[serde(deny_unknown_fields)]
+struct FileConfig {
+    bind: Option<String>,
+    cert: Option<PathBuf>,
+    key: Option<PathBuf>,
+    client_ca: Option<PathBuf>,
+    allowed_clients: Option<Vec<String>>,
+    password_hash: Option<String>,
+    permissions: Option<Vec<String>>,
+    //keys are entries like in allowed_clients
+    client_permissions: Option<HashMap<String, Vec<String>>>,
+    invites: Option<Vec<InviteEntry>>,
+    approval: Option<ApprovalPolicy>,
+    approval_timeout_secs: Option<u32>,
+    audit_log: Option<PathBuf>,
+    audit_log_max_mb: Option<u32>,
+    audit_log_keep: Option<u32>,
+    codec: Option<VideoCodec>,
+    bitrate_kbps: Option<u32>,
+    fps: Option<u32>,
+    scale: Option<u32>,
+    resampling: Option<Resampling>,
+    adaptive: Option<bool>,
+    min_bitrate_kbps: Option<u32>,
+    min_fps: Option<u32>,
+    max_scale: Option<u32>,
+    monitor: Option<usize>,
+    capture: Option<Capture>,
+    allow_input: Option<AllowedInput>,
+    input_backend: Option<InputBackend>,
+    clipboard: Option<ClipboardPolicy>,
+    max_clients: Option<usize>,
+}
+
+//one [[invites]] table, as printed by the invite command
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+struct InviteEntry {
+    name: Option<String>,
+    token_hash: String,
+    permissions: Option<Vec<String>>,
+}
+
+//a token handed out with the invite command, whoever gives it in place of the password gets at most its permissions
+#[derive(Debug, Clone)]
+pub struct Invite {
+    //shown in the log instead of the token
+    pub name: String,
+    pub token_hash: String,
+    pub permissions: Permissions,
+}
+
+//what the invite command was asked to make
+#[derive(Debug, Clone)]
+pub struct InviteRequest {
+    pub name: String,
+    pub permissions: Permissions,
+}
+
+//the enums below are named the same on the command line and in the config file
+
+//video codec to stream with
+#[derive(ValueEnum, Deserialize, Debug, Copy, Clone, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum VideoCodec {
+    H264,
+}
+
+impl VideoCodec {
+    pub fn codec(&self) -> Codec {
+        match self {
+            VideoCodec::H264 => Codec::H264,
+        }
+    }
+}
+
+//what is streamed, the screen or a test pattern for running without a display
+#[derive(ValueEnum, Deserialize, Debug, Copy, Clone, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Capture {
+    Screen,
+    Gradient,
+    Text,
+    Testcard,
+}
+
+impl Capture {
+    //None for the screen
+    pub fn pattern(&self) -> Option<SyntheticPattern> {
+        match self {
+            Capture::Screen => None,
+            Capture::Gradient => Some(SyntheticPattern::Gradient),
+            Capture::Text => Some(SyntheticPattern::ScrollingText),
+            Capture::Testcard => Some(SyntheticPattern::TestCard),
+        }
+    }
+}
+
+//how client input is injected
+#[derive(ValueEnum, Deserialize, Debug, Copy, Clone, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum InputBackend {
+    Xtest,
+    Uinput,
+    Ydotool,
+    //only kept in memory, nothing reaches the server's desktop
+    Record,
+}
+
+impl fmt::Display for InputBackend {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.to_possible_value() {
+            Some(value) => write!(f, "{}", value.get_name()),
+            None => Ok(()),
+        }
+    }
+}
+
+//which kinds of client input reach the server
+#[derive(ValueEnum, Deserialize, Debug, Copy, Clone, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum AllowedInput {
+    All,
+    Keyboard,
+    Pointer,
+    None,
+}
+
+impl AllowedInput {
+    pub fn keyboard(&self) -> bool {
+        matches!(self, AllowedInput::All | AllowedInput::Keyboard)
+    }
+
+    pub fn pointer(&self) -> bool {
+        matches!(self, AllowedInput::All | AllowedInput::Pointer)
+    }
+}
+
+//one entry of the client certificate allow-list
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AllowedClient {
+    //sha256 of the DER encoded certificate
+    Fingerprint([u8; 32]),
+    //common name in the certificate subject
+    CommonName(String),
+}
+
+impl AllowedClient {
+    //sha256:AB:CD:... as printed by openssl x509 -fingerprint -sha256, colons optional, anything else is a common name
+    pub fn parse(entry: &str) -> Option<Self> {
+        let Some(hex) = entry.strip_prefix("sha256:") else {
+            return (!entry.is_empty()).then(|| AllowedClient::CommonName(entry.to_string()));
+        };
+        parse_fingerprint(hex).map(AllowedClient::Fingerprint)
+    }
+}
+
+//validated settings the server runs with
+#[derive(Debug, Clone)]
+pub struct Config {
+    pub bind: SocketAddr,
+    pub cert: PathBuf,
+    pub key: PathBuf,
+    //None lets clients in without a certificate
+    pub client_ca: Option<PathBuf>,
+    //empty lets in any certificate that chains to client_ca
+    pub allowed_clients: Vec<AllowedClient>,
+    //clients have to send the password matching this before they get a session, None asks for none
+    pub password_hash: Option<String>,
+    //what a client may do when neither client_permissions nor an invite says otherwise
+    pub permissions: Permissions,
+    //per client certificate, fingerprints come first since they are more specific than a name
+    pub client_permissions: Vec<(AllowedClient, Permissions)>,
+    //tokens accepted in place of password_hash
+    pub invites: Vec<Invite>,
+    pub approval: ApprovalPolicy,
+    //how long a client waits for an answer when approval is ask
+    pub approval_timeout_secs: u32,
+    //None keeps no audit log
+    pub audit_log: Option<PathBuf>,
+    pub audit_log_max_bytes: u64,
+    pub audit_log_keep: u32,
+    pub codec: Codec,
+    pub bitrate_bps: u32,
+    pub fps: u32,
+    pub scale: u32,
+    pub resampling: Resampling,
+    //bitrate, fps and scale above are where a session starts, with adaptive it may go as far as these
+    pub adaptive: bool,
+    pub min_bitrate_bps: u32,
+    pub min_fps: u32,
+    pub max_scale: u32,
+    pub monitor: usize,
+    //None captures the screen
+    pub capture: Option<SyntheticPattern>,
+    pub allow_input: AllowedInput,
+    //None picks the platform default
+    pub input_backend: Option<InputBackend>,
+    pub clipboard: ClipboardPolicy,
+    pub max_clients: usize,
+}
+
+//what the command line asked for
+pub enum Action {
+    Serve(Box<Config>),
+    HashPassword,
+    GenCerts(CertRequest),
+    Invite(InviteRequest),
+}
+
+//parse the command line and the config file it points to, exits with usage on bad flags
+pub fn load() -> Result<Action, Box<dyn Error>> {
+    let cli = Cli::parse();
+    match cli.command {
+        Some(Command::HashPassword) => return Ok(Action::HashPassword),
+        Some(Command::GenCerts { names, out_dir, days, force }) => {
+            if days == 0 {
+                return Err("Invalid days 0, the certificate has to be valid for at least a day".into());
+            }
+            return Ok(Action::GenCerts(CertRequest { dir: out_dir, names, days, force }));
+        }
+        Some(Command::Invite { name, permissions }) => {
+            let permissions = Permissions::from_names(&permissions).map_err(|e| format!("Invalid permissions: {e}"))?;
+            return Ok(Action::Invite(InviteRequest { name, permissions }));
+        }
+        None => {}
+    }
+    let file = match &cli.config {
+        Some(path) => read_file(path)?,
+        None if Path::new(DEFAULT_CONFIG_PATH).exists() => read_file(Path::new(DEFAULT_CONFIG_PATH))?,
+        None => FileConfig::default(),
+    };
+    Ok(Action::Serve(Box::new(Config::merge(cli, file)?)))
+}
+
+impl Config {
+    //whether clients have to send a password or an invite token before they get a session
+    pub fn requires_auth(&self) -> bool {
+        self.password_hash.is_some() || !self.invites.is_empty()
+    }
+
+    //the entry for the client's certificate or else the default, cut down to what its invite grants,
+    //and never more than allow_input and clipboard let anyone do
+    pub fn permissions_for(&self, identity: Option<&ClientIdentity>, invite: Option<&Invite>) -> Permissions {
+        let mut permissions = identity
+            .and_then(|identity| self.client_permissions.iter().find(|(client, _)| identity.matches(client)))
+            .map(|(_, permissions)| *permissions)
+            .unwrap_or(self.permissions);
+        if let Some(invite) = invite {
+            permissions = permissions.intersect(invite.permissions);
+        }
+        let mut allowed = Permissions::VIEW;
+        if self.allow_input.keyboard() {
+            allowed = allowed.union(Permissions::KEYBOARD);
+        }
+        if self.allow_input.pointer() {
+            allowed = allowed.union(Permissions::POINTER);
+        }
+        if self.clipboard.server_sends() {
+            allowed = allowed.union(Permissions::CLIPBOARD_READ);
+        }
+        if self.clipboard.client_sends() {
+            allowed = allowed.union(Permissions::CLIPBOARD_WRITE);
+        }
+        permissions.intersect(allowed)
+    }
+
+    //flags win over the file, the file wins over the defaults
+    fn merge(cli: Cli, file: FileConfig) -> Result<Self, Box<dyn Error>> {
+        let bind = cli.bind.or(file.bind).unwrap_or_else(|| DEFAULT_BIND.to_string());
+        let bind = bind.parse::<SocketAddr>()
+            .map_err(|e| format!("Invalid bind {bind:?}: {e}, expected an address and port like {DEFAULT_BIND}"))?;
+
+        let client_ca = cli.client_ca.or(file.client_ca);
+        let allowed_clients = if cli.allowed_clients.is_empty() {
+            file.allowed_clients.unwrap_or_default()
+        } else {
+            cli.allowed_clients
+        };
+        let allowed_clients = allowed_clients.iter()
+            .map(|entry| AllowedClient::parse(entry).ok_or_else(|| {
+                format!("Invalid allowed_clients entry {entry:?}, expected sha256:<64 hex digits> or a common name")
+            }))
+            .collect::<Result<Vec<_>, _>>()?;
+        if !allowed_clients.is_empty() && client_ca.is_none() {
+            return Err("allowed_clients needs client_ca, client certificates are only asked for when client_ca is set".into());
+        }
+
+        let password_hash = cli.password_hash.or(file.password_hash);
+        if let Some(hash) = &password_hash {
+            auth::check_hash(hash).map_err(|e| format!("Invalid password_hash: {e}, make one with the hash-password command"))?;
+        }
+
+        let permissions = if cli.permissions.is_empty() {
+            file.permissions
+        } else {
+            Some(cli.permissions)
+        };
+        let permissions = match permissions {
+            None => Permissions::ALL,
+            Some(names) => Permissions::from_names(&names).map_err(|e| format!("Invalid permissions: {e}"))?,
+        };
+
+        let mut client_permissions = file.client_permissions.unwrap_or_default().into_iter()
+            .map(|(entry, names)| {
+                let client = AllowedClient::parse(&entry).ok_or_else(|| {
+                    format!("Invalid client_permissions entry {entry:?}, expected sha256:<64 hex digits> or a common name")
+                })?;
+                let permissions = Permissions::from_names(&names)
+                    .map_err(|e| format!("Invalid client_permissions for {entry:?}: {e}"))?;
+                Ok((client, permissions))
+            })
+            .collect::<Result<Vec<_>, Box<dyn Error>>>()?;
+        client_permissions.sort_by_key(|(client, _)| matches!(client, AllowedClient::CommonName(_)));
+        if !client_permissions.is_empty() && client_ca.is_none() {
+            return Err("client_permissions needs client_ca, client certificates are only asked for when client_ca is set".into());
+        }
+
+        let invites = file.invites.unwrap_or_default().into_iter()
+            .map(|entry| {
+                let name = entry.name.unwrap_or_else(|| "invite".to_string());
+                auth::check_hash(&entry.token_hash)
+                    .map_err(|e| format!("Invalid token_hash for invite {name:?}: {e}, make one with the invite command"))?;
+                let permissions = match entry.permissions {
+                    None => Permissions::VIEW,
+                    Some(names) => Permissions::from_names(&names)
+                        .map_err(|e| format!("Invalid permissions for invite {name:?}: {e}"))?,
+                };
+                Ok(Invite { name, token_hash: entry.token_hash, permissions })
+            })
+            .collect::<Result<Vec<_>, Box<dyn Error>>>()?;
+
+        let approval = cli.approval.or(file.approval).unwrap_or(ApprovalPolicy::Auto);
+
+        let approval_timeout_secs = cli.approval_timeout_secs.or(file.approval_timeout_secs).unwrap_or(DEFAULT_APPROVAL_TIMEOUT_SECS);
+        if !(1..=MAX_APPROVAL_TIMEOUT_SECS).contains(&approval_timeout_secs) {
+            return Err(format!("Invalid approval_timeout_secs {approval_timeout_secs}, expected 1 to {MAX_APPROVAL_TIMEOUT_SECS}").into());
+        }
+
+        let audit_log_max_mb = cli.audit_log_max_mb.or(file.audit_log_max_mb).unwrap_or(DEFAULT_AUDIT_LOG_MAX_MB);
+        if !(1..=MAX_AUDIT_LOG_MAX_MB).contains(&audit_log_max_mb) {
+            return Err(format!("Invalid audit_log_max_mb {audit_log_max_mb}, expected 1 to {MAX_AUDIT_LOG_MAX_MB}").into());
+        }
+        let audit_log_keep = cli.audit_log_keep.or(file.audit_log_keep).unwrap_or(DEFAULT_AUDIT_LOG_KEEP);
+        if audit_log_keep > MAX_AUDIT_LOG_KEEP {
+            return Err(format!("Invalid audit_log_keep {audit_log_keep}, expected 0 to {MAX_AUDIT_LOG_KEEP}").into());
+        }
+
+        let codec = cli.codec.or(file.codec).unwrap_or(VideoCodec::H264).codec();
+
+        let bitrate_kbps = cli.bitrate_kbps.or(file.bitrate_kbps).unwrap_or(DEFAULT_BITRATE_KBPS);
+        if !(MIN_BITRATE_KBPS..=MAX_BITRATE_KBPS).contains(&bitrate_kbps) {
+            return Err(format!("Invalid bitrate_kbps {bitrate_kbps}, expected {MIN_BITRATE_KBPS} to {MAX_BITRATE_KBPS}").into());
+        }
+
+        let fps = cli.fps.or(file.fps).unwrap_or(DEFAULT_FPS);
+        if !(1..=MAX_FPS).contains(&fps) {
+            return Err(format!("Invalid fps {fps}, expected 1 to {MAX_FPS}").into());
+        }
+
+        let scale = cli.scale.or(file.scale).unwrap_or(DEFAULT_SCALE);
+        if !(1..=MAX_SCALE).contains(&scale) {
+            return Err(format!("Invalid scale {scale}, expected 1 to {MAX_SCALE}").into());
+        }
+
+        let resampling = cli.resampling.or(file.resampling).unwrap_or(Resampling::Quality);
+
+        let adaptive = cli.adaptive.or(file.adaptive).unwrap_or(false);
+        let min_bitrate_kbps = cli.min_bitrate_kbps.or(file.min_bitrate_kbps).unwrap_or(DEFAULT_MIN_BITRATE_KBPS.min(bitrate_kbps));
+        if !(MIN_BITRATE_KBPS..=bitrate_kbps).contains(&min_bitrate_kbps) {
+            return Err(format!("Invalid min_bitrate_kbps {min_bitrate_kbps}, expected {MIN_BITRATE_KBPS} to bitrate_kbps ({bitrate_kbps})").into());
+        }
+        let min_fps = cli.min_fps.or(file.min_fps).unwrap_or(DEFAULT_MIN_FPS.min(fps));
+        if !(1..=fps).contains(&min_fps) {
+            return Err(format!("Invalid min_fps {min_fps}, expected 1 to fps ({fps})").into());
+        }
+        let max_scale = cli.max_scale.or(file.max_scale).unwrap_or((scale * 2).min(MAX_SCALE));
+        if !(scale..=MAX_SCALE).contains(&max_scale) {
+            return Err(format!("Invalid max_scale {max_scale}, expected scale ({scale}) to {MAX_SCALE}").into());
+        }
+
+        let capture = cli.capture.or(file.capture).and_then(|capture| capture.pattern());
+        let allow_input = cli.allow_input.or(file.allow_input).unwrap_or(AllowedInput::All);
+        let input_backend = cli.input_backend.or(file.input_backend);
+        let clipboard = cli.clipboard.or(file.clipboard).unwrap_or(ClipboardPolicy::Both);
+
+        let max_clients = cli.max_clients.or(file.max_clients).unwrap_or(DEFAULT_MAX_CLIENTS);
+        if max_clients == 0 {
+            return Err("Invalid max_clients 0, at least one client has to be allowed".into());
+        }
+
+        Ok(Config {
+            bind,
+            cert: cli.cert.or(file.cert).unwrap_or_else(|| PathBuf::from(DEFAULT_CERT_PATH)),
+            key: cli.key.or(file.key).unwrap_or_else(|| PathBuf::from(DEFAULT_KEY_PATH)),
+            client_ca,
+            allowed_clients,
+            password_hash,
+            permissions,
+            client_permissions,
+            invites,
+            approval,
+            approval_timeout_secs,
+            audit_log: cli.audit_log.or(file.audit_log),
+            audit_log_max_bytes: audit_log_max_mb as u64 * 1024 * 1024,
+            audit_log_keep,
+            codec,
+            bitrate_bps: bitrate_kbps * 1000,
+            fps,
+            scale,
+            resampling,
+            adaptive,
+            min_bitrate_bps: min_bitrate_kbps * 1000,
+            min_fps,
+            max_scale,
+            monitor: cli.monitor.or(file.monitor).unwrap_or(0),
+            capture,
+            allow_input,
+            input_backend,
+            clipboard,
+            max_clients,
+        })
+    }
+}
+
+fn read_file(path: &Path) -> Result<FileConfig, Box<dyn Error>> {
+    let text = fs::read_to_string(path)
+        .map_err(|e| format!("Failed to read config file {}: {e}", path.display()))?;
+    let file = toml::from_str(&text)
+        .map_err(|e| format!("Invalid config file {}: {e}", path.display()))?;
+    println!("Loaded config from {}", path.display());
+    Ok(file)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FILE: &str = r#"
+        bind = "127.0.0.1:9000"
+        fps = 20
+        scale = 3
+        approval = "ask"
+        clipboard = "server-to-client"
+        capture = "testcard"
+    "#;
+
+    fn merge(args: &[&str], file: &str) -> Result<Config, Box<dyn Error>> {
+        let cli = Cli::try_parse_from(["server"].iter().chain(args))?;
+        Config::merge(cli, toml::from_str(file)?)
+    }
+
+    fn error(args: &[&str], file: &str) -> String {
+        merge(args, file).unwrap_err().to_string()
+    }
+
+    #[test]
+    fn the_file_wins_over_the_defaults() {
+        let config = merge(&[], FILE).unwrap();
+        assert_eq!(config.bind, "127.0.0.1:9000".parse().unwrap());
+        assert_eq!((config.fps, config.scale), (20, 3));
+        assert_eq!(config.approval, ApprovalPolicy::Ask);
+        assert_eq!(config.clipboard, ClipboardPolicy::ServerToClient);
+        assert_eq!(config.capture, Some(SyntheticPattern::TestCard));
+
+        //and what neither sets is the default
+        assert_eq!(config.cert, PathBuf::from(DEFAULT_CERT_PATH));
+        assert_eq!(config.bitrate_bps, DEFAULT_BITRATE_KBPS * 1000);
+        assert_eq!(config.max_scale, 6);
+        assert_eq!(config.allow_input, AllowedInput::All);
+        assert_eq!(config.permissions, Permissions::ALL);
+        assert!(!config.adaptive);
+    }
+
+    #[test]
+    fn flags_win_over_the_file() {
+        let config = merge(&["--bind", "[::1]:7000", "--fps", "60", "--approval", "auto", "--clipboard", "off", "--capture", "screen"], FILE).unwrap();
+        assert_eq!(config.bind, "[::1]:7000".parse().unwrap());
+        assert_eq!((config.fps, config.scale), (60, 3));
+        assert_eq!(config.approval, ApprovalPolicy::Auto);
+        assert_eq!(config.clipboard, ClipboardPolicy::Off);
+        assert_eq!(config.capture, None);
+    }
+
+    #[test]
+    fn the_example_config_is_valid() {
+        let config = merge(&[], include_str!("../server.example.toml")).unwrap();
+        assert_eq!(config.bind, DEFAULT_BIND.parse().unwrap());
+    }
+
+    #[test]
+    fn invalid_settings_are_refused() {
+        assert!(error(&["--bind", "localhost"], "").contains("Invalid bind \"localhost\""));
+        assert!(error(&["--fps", "0"], "").contains("Invalid fps 0"));
+        assert!(error(&["--min-fps", "40"], FILE).contains("Invalid min_fps 40, expected 1 to fps (20)"));
+        assert!(error(&[], "scale = 4\nmax_scale = 2").contains("Invalid max_scale 2, expected scale (4)"));
+        assert!(error(&["--allow-client", "laptop"], "").contains("allowed_clients needs client_ca"));
+        assert!(error(&["--allow-client", "sha256:12:AB", "--client-ca", "ca.crt"], "").contains("Invalid allowed_clients entry"));
+        assert!(error(&["--permissions", "view,files"], "").contains("unknown permission \"files\""));
+    }
+
+    #[test]
+    fn unknown_keys_and_values_are_refused_with_what_is_accepted() {
+        let e = error(&[], "frame_rate = 30");
+        assert!(e.contains("unknown field `frame_rate`") && e.contains("`fps`"), "{e}");
+        let e = error(&[], "approval = \"maybe\"");
+        assert!(e.contains("unknown variant `maybe`, expected one of `auto`, `deny`, `ask`"), "{e}");
+        let e = error(&["--input-backend", "evdev"], "");
+        assert!(e.contains("evdev") && e.contains("xtest, uinput, ydotool, record"), "{e}");
+    }
+}
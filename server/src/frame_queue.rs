@@ -0,0 +1,356 @@
+//encoded frames on their way from a session's encode loop to its dispatcher
+//the queue is bounded by age and length, when a slow client lets it back up the queued frames are dropped
+//and everything up to the next keyframe with them, as delta frames can't be decoded without the ones before
+//the client can ask for a keyframe through the queue too, when its decoder fails, and for the stream to fit its window
+//the few messages the encode loop has for the client besides frames go through it as well, in order with the frames,
+//and those are never dropped
+use std::{
+    collections::VecDeque,
+    sync::{ Arc, Mutex },
+    time::{ Duration, Instant },
+};
+use common::protocol::Message;
+use crate::scale::Viewer;
+
+//a frame waiting longer than this is stale, the client would see it late enough to notice
+pub const MAX_FRAME_DELAY: Duration = Duration::from_millis(250);
+//and a queue this long is dropped whatever its age, it only gets this long with a very high frame rate
+pub const MAX_QUEUED_FRAMES: usize = 16;
+//a client that keeps asking gets a keyframe at most this often, the rest of its requests wait
+pub const KEYFRAME_REQUEST_INTERVAL: Duration = Duration::from_secs(2);
+//a new size restarts the stream with a keyframe, so a client dragging its window is followed at most this often
+pub const VIEWER_REQUEST_INTERVAL: Duration = Duration::from_millis(500);
+
+#[derive(Debug, Copy, Clone, Default)]
+pub struct QueueStats {
+    //frames waiting right now
+    pub depth: usize,
+    pub max_depth: usize,
+    pub sent: u64,
+    pub dropped: u64,
+    //times the queue was dropped and had to wait for a keyframe
+    pub resets: u64,
+    //keyframes the client asked for, and how many of those were made
+    pub keyframe_requests: u64,
+    pub keyframes_forced: u64,
+}
+
+//what the dispatcher takes off the queue, in the order it was queued
+#[derive(Debug, PartialEq)]
+pub enum Outgoing {
+    Frame(Vec<u8>),
+    Message(Message),
+}
+
+struct Queued {
+    at: Instant,
+    item: Outgoing,
+}
+
+struct Inner {
+    //frames and messages in the order they go out
+    queue: VecDeque<Queued>,
+    //frames in the queue, messages don't count towards its length
+    frames: usize,
+    //frames were dropped, deltas are thrown away until a keyframe comes
+    needs_keyframe: bool,
+    //the client asked for a keyframe that hasn't been let through yet
+    keyframe_requested: bool,
+    last_forced: Option<Instant>,
+    //the client's latest window size that hasn't been taken yet
+    viewer_requested: Option<Viewer>,
+    last_viewer_change: Option<Instant>,
+    //the receiver is gone
+    closed: bool,
+    //the sender is gone, the encode loop stopped and nothing more will be queued
+    finished: bool,
+    stats: QueueStats,
+}
+
+impl Inner {
+    //drop every queued frame, messages stay where they are, returns how many were dropped
+    fn drop_frames(&mut self) -> u64 {
+        self.queue.retain(|queued| matches!(queued.item, Outgoing::Message(_)));
+        std::mem::replace(&mut self.frames, 0) as u64
+    }
+}
+
+//returned by send once the dispatcher is gone, the client disconnected
+#[derive(Debug)]
+pub struct Disconnected;
+
+pub fn bounded() -> (FrameSender, FrameReceiver) {
+    let inner = Arc::new(Mutex::new(Inner {
+        queue: VecDeque::new(),
+        frames: 0,
+        needs_keyframe: false,
+        keyframe_requested: false,
+        last_forced: None,
+        viewer_requested: None,
+        last_viewer_change: None,
+        closed: false,
+        finished: false,
+        stats: QueueStats::default(),
+    }));
+    (FrameSender { inner: inner.clone() }, FrameReceiver { inner })
+}
+
+pub struct FrameSender {
+    inner: Arc<Mutex<Inner>>,
+}
+
+impl FrameSender {
+    //queue one encoded frame, a stale queue is dropped first
+    pub fn send(&self, data: Vec<u8>, keyframe: bool) -> Result<(), Disconnected> {
+        let mut inner = self.inner.lock().unwrap();
+        if inner.closed {
+            return Err(Disconnected);
+        }
+        let oldest = inner.queue.iter().find(|queued| matches!(queued.item, Outgoing::Frame(_)));
+        let stale = inner.frames >= MAX_QUEUED_FRAMES
+            || oldest.is_some_and(|frame| frame.at.elapsed() > MAX_FRAME_DELAY);
+
+        if keyframe {
+            //a keyframe stands on its own, nothing queued before it is needed any more once the queue is stale
+            if stale {
+                inner.stats.dropped += inner.drop_frames();
+            }
+            inner.needs_keyframe = false;
+        } else if inner.needs_keyframe {
+            inner.stats.dropped += 1;
+            return Ok(());
+        } else if stale {
+            inner.stats.dropped += inner.drop_frames() + 1;
+            inner.stats.resets += 1;
+            inner.needs_keyframe = true;
+            return Ok(());
+        }
+
+        inner.queue.push_back(Queued { at: Instant::now(), item: Outgoing::Frame(data) });
+        inner.frames += 1;
+        inner.stats.max_depth = inner.stats.max_depth.max(inner.frames);
+        Ok(())
+    }
+
+    //queue a message that isn't a frame, it goes out after the frames queued before it
+    pub fn send_message(&self, message: Message) -> Result<(), Disconnected> {
+        let mut inner = self.inner.lock().unwrap();
+        if inner.closed {
+            return Err(Disconnected);
+        }
+        inner.queue.push_back(Queued { at: Instant::now(), item: Outgoing::Message(message) });
+        Ok(())
+    }
+
+    //take the client's keyframe request if it is due, from then on needs_keyframe is true
+    pub fn take_keyframe_request(&self) -> bool {
+        let mut inner = self.inner.lock().unwrap();
+        if !inner.keyframe_requested || inner.last_forced.is_some_and(|at| at.elapsed() < KEYFRAME_REQUEST_INTERVAL) {
+            return false;
+        }
+        inner.keyframe_requested = false;
+        inner.last_forced = Some(Instant::now());
+        inner.stats.keyframes_forced += 1;
+        //deltas the client can't decode aren't worth sending
+        inner.needs_keyframe = true;
+        true
+    }
+
+    //take the client's latest window size if it is due
+    pub fn take_viewer_request(&self) -> Option<Viewer> {
+        let mut inner = self.inner.lock().unwrap();
+        if inner.last_viewer_change.is_some_and(|at| at.elapsed() < VIEWER_REQUEST_INTERVAL) {
+            return None;
+        }
+        let viewer = inner.viewer_requested.take()?;
+        inner.last_viewer_change = Some(Instant::now());
+        Some(viewer)
+    }
+
+    //true from when frames are dropped until a keyframe is sent, the encoder should make the next frame one
+    pub fn needs_keyframe(&self) -> bool {
+        self.inner.lock().unwrap().needs_keyframe
+    }
+
+    pub fn stats(&self) -> QueueStats {
+        let inner = self.inner.lock().unwrap();
+        QueueStats { depth: inner.frames, ..inner.stats }
+    }
+}
+
+pub struct FrameReceiver {
+    inner: Arc<Mutex<Inner>>,
+}
+
+impl FrameReceiver {
+    //the oldest queued frame or message, None if there is nothing to send
+    pub fn try_recv(&self) -> Option<Outgoing> {
+        let mut inner = self.inner.lock().unwrap();
+        let queued = inner.queue.pop_front()?;
+        if let Outgoing::Frame(_) = queued.item {
+            inner.frames -= 1;
+            inner.stats.sent += 1;
+        }
+        Some(queued.item)
+    }
+
+    //the client's decoder failed, requests that come in while one is waiting count as one
+    pub fn request_keyframe(&self) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.keyframe_requested = true;
+        inner.stats.keyframe_requests += 1;
+    }
+
+    //true once the encode loop has stopped and everything it queued has been taken
+    pub fn finished(&self) -> bool {
+        let inner = self.inner.lock().unwrap();
+        inner.finished && inner.queue.is_empty()
+    }
+
+    //the client's window changed size, only the latest size is kept
+    pub fn request_viewer(&self, viewer: Viewer) {
+        self.inner.lock().unwrap().viewer_requested = Some(viewer);
+    }
+}
+
+impl Drop for FrameSender {
+    //the dispatcher checks for this each time round its loop, once it has sent what is left it ends the session
+    fn drop(&mut self) {
+        self.inner.lock().unwrap().finished = true;
+    }
+}
+
+impl Drop for FrameReceiver {
+    fn drop(&mut self) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.closed = true;
+        inner.queue.clear();
+        inner.frames = 0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn receiver_finishes_once_the_sender_is_gone_and_the_queue_is_drained() {
+        let (sender, receiver) = bounded();
+        sender.send(vec![1], true).unwrap();
+        assert!(!receiver.finished());
+        drop(sender);
+        assert!(!receiver.finished());
+        assert_eq!(receiver.try_recv(), Some(Outgoing::Frame(vec![1])));
+        assert!(receiver.finished());
+    }
+
+    #[test]
+    fn sender_sees_the_receiver_go() {
+        let (sender, receiver) = bounded();
+        drop(receiver);
+        assert!(sender.send(vec![1], true).is_err());
+        assert!(sender.send_message(Message::FrameEnd).is_err());
+    }
+
+    #[test]
+    fn messages_keep_their_place_between_frames() {
+        let (sender, receiver) = bounded();
+        sender.send(vec![1], true).unwrap();
+        sender.send_message(Message::Resize { w: 640, h: 480 }).unwrap();
+        sender.send(vec![2], true).unwrap();
+        assert_eq!(receiver.try_recv(), Some(Outgoing::Frame(vec![1])));
+        assert_eq!(receiver.try_recv(), Some(Outgoing::Message(Message::Resize { w: 640, h: 480 })));
+        assert_eq!(receiver.try_recv(), Some(Outgoing::Frame(vec![2])));
+        assert_eq!(receiver.try_recv(), None);
+    }
+
+    #[test]
+    fn a_full_queue_drops_frames_but_not_messages() {
+        let (sender, receiver) = bounded();
+        sender.send(vec![0], true).unwrap();
+        sender.send_message(Message::Resize { w: 640, h: 480 }).unwrap();
+        for i in 1..MAX_QUEUED_FRAMES as u8 {
+            sender.send(vec![i], false).unwrap();
+        }
+        //this delta finds the queue full, everything up to the next keyframe goes
+        sender.send(vec![100], false).unwrap();
+        sender.send(vec![101], false).unwrap();
+        sender.send(vec![102], true).unwrap();
+        let stats = sender.stats();
+        assert_eq!(stats.resets, 1);
+        assert_eq!(stats.dropped, MAX_QUEUED_FRAMES as u64 + 2);
+        assert_eq!(stats.depth, 1);
+        assert_eq!(receiver.try_recv(), Some(Outgoing::Message(Message::Resize { w: 640, h: 480 })));
+        assert_eq!(receiver.try_recv(), Some(Outgoing::Frame(vec![102])));
+        assert_eq!(receiver.try_recv(), None);
+    }
+
+    //pretend the last forced keyframe and size change happened long enough ago, instead of sleeping through the intervals
+    fn let_intervals_pass(sender: &FrameSender) {
+        let mut inner = sender.inner.lock().unwrap();
+        let past = |at: Option<Instant>, interval| at.map(|at| at - interval);
+        inner.last_forced = past(inner.last_forced, KEYFRAME_REQUEST_INTERVAL);
+        inner.last_viewer_change = past(inner.last_viewer_change, VIEWER_REQUEST_INTERVAL);
+    }
+
+    #[test]
+    fn repeated_keyframe_requests_are_let_through_once_per_interval() {
+        let (sender, receiver) = bounded();
+        assert!(!sender.take_keyframe_request());
+
+        receiver.request_keyframe();
+        receiver.request_keyframe();
+        assert!(sender.take_keyframe_request());
+        //the two requests count as one
+        assert!(!sender.take_keyframe_request());
+
+        //another one this soon waits
+        receiver.request_keyframe();
+        assert!(!sender.take_keyframe_request());
+        let_intervals_pass(&sender);
+        assert!(sender.take_keyframe_request());
+        assert!(!sender.take_keyframe_request());
+
+        let stats = sender.stats();
+        assert_eq!((stats.keyframe_requests, stats.keyframes_forced), (3, 2));
+    }
+
+    #[test]
+    fn deltas_are_dropped_after_a_keyframe_request_until_the_keyframe() {
+        let (sender, receiver) = bounded();
+        sender.send(vec![0], true).unwrap();
+        receiver.request_keyframe();
+        assert!(sender.take_keyframe_request());
+        assert!(sender.needs_keyframe());
+
+        sender.send(vec![1], false).unwrap();
+        sender.send(vec![2], false).unwrap();
+        sender.send(vec![3], true).unwrap();
+        assert!(!sender.needs_keyframe());
+        sender.send(vec![4], false).unwrap();
+
+        assert_eq!(sender.stats().dropped, 2);
+        assert_eq!(receiver.try_recv(), Some(Outgoing::Frame(vec![0])));
+        assert_eq!(receiver.try_recv(), Some(Outgoing::Frame(vec![3])));
+        assert_eq!(receiver.try_recv(), Some(Outgoing::Frame(vec![4])));
+        assert_eq!(receiver.try_recv(), None);
+    }
+
+    #[test]
+    fn only_the_latest_window_size_is_taken_once_per_interval() {
+        let (sender, receiver) = bounded();
+        assert_eq!(sender.take_viewer_request(), None);
+
+        receiver.request_viewer(Viewer::from_resize(800, 600));
+        assert_eq!(sender.take_viewer_request(), Some(Viewer::from_resize(800, 600)));
+
+        //a window being dragged sends sizes faster than the stream restarts, they wait and only the last counts
+        receiver.request_viewer(Viewer::from_resize(900, 600));
+        receiver.request_viewer(Viewer::from_resize(1000, 700));
+        assert_eq!(sender.take_viewer_request(), None);
+        let_intervals_pass(&sender);
+        assert_eq!(sender.take_viewer_request(), Some(Viewer::from_resize(1000, 700)));
+        let_intervals_pass(&sender);
+        assert_eq!(sender.take_viewer_request(), None);
+    }
+}
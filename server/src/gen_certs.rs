@@ -0,0 +1,200 @@
+//gen-certs: a local CA and a server certificate signed by it, so the server can start without an openssl session
+use std::{
+    error::Error,
+    fs::{ self, OpenOptions },
+    io::Write,
+    net::IpAddr,
+    path::{ Path, PathBuf },
+};
+use rcgen::{
+    BasicConstraints,
+    CertificateParams,
+    DnType,
+    ExtendedKeyUsagePurpose,
+    IsCa,
+    KeyPair,
+    KeyUsagePurpose,
+    SanType,
+};
+use sha2::{ Digest, Sha256 };
+use time::{ Duration, OffsetDateTime };
+use common::fingerprint::fingerprint_hex;
+
+//what gen-certs was asked to make
+#[derive(Debug, Clone)]
+pub struct CertRequest {
+    pub dir: PathBuf,
+    //host names and IP addresses clients connect to, each one goes in the certificate
+    pub names: Vec<String>,
+    //how long the server certificate is valid, the CA lasts ten times as long
+    pub days: u32,
+    //replace certificates that are already in dir
+    pub force: bool,
+}
+
+const CA_CERT: &str = "ca.crt";
+const CA_KEY: &str = "ca.key";
+const SERVER_CERT: &str = "server.crt";
+const SERVER_KEY: &str = "server.key";
+
+//write ca.crt, ca.key, server.crt and server.key to request.dir and print what clients need to know
+pub fn generate(request: &CertRequest) -> Result<(), Box<dyn Error>> {
+    //the first name is the common name, so there has to be one
+    let Some(common_name) = request.names.first() else {
+        return Err("No host names or IP addresses given, the certificate needs at least one".into());
+    };
+    let files = [CA_CERT, CA_KEY, SERVER_CERT, SERVER_KEY].map(|name| request.dir.join(name));
+    if !request.force && let Some(existing) = files.iter().find(|path| path.exists()) {
+        return Err(format!("{} already exists, pass --force to replace the certificates in {}", existing.display(), request.dir.display()).into());
+    }
+
+    let now = OffsetDateTime::now_utc();
+    //a little slack for clients whose clocks run behind
+    let not_before = now - Duration::days(1);
+
+    let ca_key = KeyPair::generate()?;
+    let mut ca_params = CertificateParams::default();
+    ca_params.distinguished_name.push(DnType::CommonName, "Remote desktop local CA");
+    ca_params.is_ca = IsCa::Ca(BasicConstraints::Constrained(0));
+    ca_params.key_usages = vec![KeyUsagePurpose::KeyCertSign, KeyUsagePurpose::CrlSign];
+    ca_params.not_before = not_before;
+    ca_params.not_after = now + Duration::days(request.days as i64 * 10);
+    let ca_cert = ca_params.self_signed(&ca_key)?;
+
+    let server_key = KeyPair::generate()?;
+    let mut server_params = CertificateParams::default();
+    server_params.distinguished_name.push(DnType::CommonName, common_name.clone());
+    server_params.subject_alt_names = request.names.iter()
+        .map(|name| match name.parse::<IpAddr>() {
+            Ok(ip) => Ok(SanType::IpAddress(ip)),
+            Err(_) if valid_host_name(name) => Ok(SanType::DnsName(name.clone().try_into()?)),
+            Err(_) => Err(format!("Invalid host name {name:?}, expected a name like desktop.lan or an IP address").into()),
+        })
+        .collect::<Result<_, Box<dyn Error>>>()?;
+    server_params.key_usages = vec![KeyUsagePurpose::DigitalSignature];
+    server_params.extended_key_usages = vec![ExtendedKeyUsagePurpose::ServerAuth];
+    server_params.not_before = not_before;
+    server_params.not_after = now + Duration::days(request.days as i64);
+    let server_cert = server_params.signed_by(&server_key, &ca_cert, &ca_key)?;
+
+    fs::create_dir_all(&request.dir)
+        .map_err(|e| format!("Failed to create {}: {e}", request.dir.display()))?;
+    write_file(&files[0], &ca_cert.pem(), false)?;
+    write_file(&files[1], &ca_key.serialize_pem(), true)?;
+    //the chain a client sees is the server certificate followed by the CA
+    write_file(&files[2], &format!("{}{}", server_cert.pem(), ca_cert.pem()), false)?;
+    write_file(&files[3], &server_key.serialize_pem(), true)?;
+
+    let ca_fingerprint: [u8; 32] = Sha256::digest(ca_cert.der()).into();
+    let server_fingerprint: [u8; 32] = Sha256::digest(server_cert.der()).into();
+    println!("Wrote {}, {}, {} and {} to {}", CA_CERT, CA_KEY, SERVER_CERT, SERVER_KEY, request.dir.display());
+    println!("Server certificate for {}, valid for {} days", request.names.join(", "), request.days);
+    println!("  SHA256 {}", fingerprint_hex(&server_fingerprint));
+    println!("Give clients {} for --ca, or check the server fingerprint above when using --verify tofu", files[0].display());
+    println!("CA fingerprint");
+    println!("  SHA256 {}", fingerprint_hex(&ca_fingerprint));
+    println!("Keep {} private, anyone with it can make certificates clients will trust", files[1].display());
+    Ok(())
+}
+
+//letters, digits and hyphens in dot separated labels, a leading *. makes a wildcard
+fn valid_host_name(name: &str) -> bool {
+    let name = name.strip_prefix("*.").unwrap_or(name);
+    !name.is_empty() && name.len() <= 253 && name.split('.').all(|label| {
+        !label.is_empty()
+            && label.len() <= 63
+            && !label.starts_with('-')
+            && !label.ends_with('-')
+            && label.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+    })
+}
+
+//private keys are only readable by the owner, also when --force replaces a file that wasn't
+fn write_file(path: &Path, contents: &str, private: bool) -> Result<(), Box<dyn Error>> {
+    let mut options = OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    if private {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    let mut file = options.open(path).map_err(|e| format!("Failed to write {}: {e}", path.display()))?;
+    #[cfg(unix)]
+    if private {
+        use std::os::unix::fs::PermissionsExt;
+        file.set_permissions(fs::Permissions::from_mode(0o600))?;
+    }
+    #[cfg(not(unix))]
+    let _ = private;
+    file.write_all(contents.as_bytes())?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{ fs::File, io::BufReader, net::Ipv4Addr };
+    use x509_parser::{ extensions::GeneralName, prelude::{ FromDer, X509Certificate } };
+    use crate::tls;
+
+    fn request(dir: &Path, names: &[&str], force: bool) -> CertRequest {
+        CertRequest { dir: dir.to_path_buf(), names: names.iter().map(|name| name.to_string()).collect(), days: 30, force }
+    }
+
+    //DNS and IP subject alt names of the first certificate in a PEM file
+    fn alt_names(path: &Path) -> (Vec<String>, Vec<Vec<u8>>) {
+        let der = rustls_pemfile::certs(&mut BufReader::new(File::open(path).unwrap())).next().unwrap().unwrap();
+        let (_, cert) = X509Certificate::from_der(&der).unwrap();
+        let (mut dns, mut ips) = (Vec::new(), Vec::new());
+        for name in &cert.subject_alternative_name().unwrap().unwrap().value.general_names {
+            match name {
+                GeneralName::DNSName(name) => dns.push(name.to_string()),
+                GeneralName::IPAddress(ip) => ips.push(ip.to_vec()),
+                _ => {}
+            }
+        }
+        (dns, ips)
+    }
+
+    #[test]
+    fn the_certificates_load_and_name_every_host() {
+        let dir = tempfile::tempdir().unwrap();
+        generate(&request(dir.path(), &["desktop.lan", "192.168.1.20", "*.home.arpa"], false)).unwrap();
+
+        let path = |name| dir.path().join(name);
+        tls::load_server_config(&path(SERVER_CERT), &path(SERVER_KEY), Some(&path(CA_CERT))).unwrap();
+        let (dns, ips) = alt_names(&path(SERVER_CERT));
+        assert_eq!(dns, ["desktop.lan", "*.home.arpa"]);
+        assert_eq!(ips, [Ipv4Addr::new(192, 168, 1, 20).octets().to_vec()]);
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = |name| fs::metadata(path(name)).unwrap().permissions().mode() & 0o777;
+            assert_eq!(mode(CA_KEY), 0o600);
+            assert_eq!(mode(SERVER_KEY), 0o600);
+        }
+    }
+
+    #[test]
+    fn existing_certificates_are_only_replaced_with_force() {
+        let dir = tempfile::tempdir().unwrap();
+        generate(&request(dir.path(), &["desktop.lan"], false)).unwrap();
+        let before = fs::read(dir.path().join(SERVER_CERT)).unwrap();
+
+        let e = generate(&request(dir.path(), &["desktop.lan"], false)).unwrap_err().to_string();
+        assert!(e.contains("pass --force"), "{e}");
+        assert_eq!(fs::read(dir.path().join(SERVER_CERT)).unwrap(), before);
+
+        generate(&request(dir.path(), &["desktop.lan"], true)).unwrap();
+        assert_ne!(fs::read(dir.path().join(SERVER_CERT)).unwrap(), before);
+    }
+
+    #[test]
+    fn bad_names_are_refused_before_anything_is_written() {
+        let dir = tempfile::tempdir().unwrap();
+        assert!(generate(&request(dir.path(), &[], false)).unwrap_err().to_string().contains("No host names"));
+        assert!(generate(&request(dir.path(), &["desktop.lan", "not a host"], false)).unwrap_err().to_string().contains("Invalid host name"));
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 0);
+    }
+}
@@ -0,0 +1,72 @@
+use common::input::Key;
+
+//linux evdev key code (linux/input-event-codes.h) for a protocol key, None if linux has no such key
+pub fn evdev_code(key: Key) -> Option<u16> {
+    let code = match key {
+        Key::Escape => 1,
+        Key::Digit1 => 2, Key::Digit2 => 3, Key::Digit3 => 4, Key::Digit4 => 5, Key::Digit5 => 6,
+        Key::Digit6 => 7, Key::Digit7 => 8, Key::Digit8 => 9, Key::Digit9 => 10, Key::Digit0 => 11,
+        Key::Minus => 12, Key::Equal => 13, Key::Backspace => 14, Key::Tab => 15,
+        Key::Q => 16, Key::W => 17, Key::E => 18, Key::R => 19, Key::T => 20,
+        Key::Y => 21, Key::U => 22, Key::I => 23, Key::O => 24, Key::P => 25,
+        Key::LeftBracket => 26, Key::RightBracket => 27, Key::Enter => 28, Key::LeftCtrl => 29,
+        Key::A => 30, Key::S => 31, Key::D => 32, Key::F => 33, Key::G => 34,
+        Key::H => 35, Key::J => 36, Key::K => 37, Key::L => 38,
+        Key::Semicolon => 39, Key::Quote => 40, Key::Grave => 41, Key::LeftShift => 42, Key::Backslash => 43,
+        Key::Z => 44, Key::X => 45, Key::C => 46, Key::V => 47, Key::B => 48, Key::N => 49, Key::M => 50,
+        Key::Comma => 51, Key::Period => 52, Key::Slash => 53, Key::RightShift => 54,
+        Key::KpMultiply => 55, Key::LeftAlt => 56, Key::Space => 57, Key::CapsLock => 58,
+        Key::F1 => 59, Key::F2 => 60, Key::F3 => 61, Key::F4 => 62, Key::F5 => 63,
+        Key::F6 => 64, Key::F7 => 65, Key::F8 => 66, Key::F9 => 67, Key::F10 => 68,
+        Key::NumLock => 69, Key::ScrollLock => 70,
+        Key::Kp7 => 71, Key::Kp8 => 72, Key::Kp9 => 73, Key::KpMinus => 74,
+        Key::Kp4 => 75, Key::Kp5 => 76, Key::Kp6 => 77, Key::KpPlus => 78,
+        Key::Kp1 => 79, Key::Kp2 => 80, Key::Kp3 => 81, Key::Kp0 => 82, Key::KpDecimal => 83,
+        Key::IntlBackslash => 86, Key::F11 => 87, Key::F12 => 88,
+        Key::KpEnter => 96, Key::RightCtrl => 97, Key::KpDivide => 98, Key::PrintScreen => 99,
+        Key::RightAlt => 100, Key::Home => 102, Key::Up => 103, Key::PageUp => 104,
+        Key::Left => 105, Key::Right => 106, Key::End => 107, Key::Down => 108, Key::PageDown => 109,
+        Key::Insert => 110, Key::Delete => 111,
+        Key::Mute => 113, Key::VolumeDown => 114, Key::VolumeUp => 115, Key::KpEqual => 117,
+        Key::Pause => 119, Key::LeftMeta => 125, Key::RightMeta => 126, Key::Menu => 127,
+        Key::Unknown(_) => return None,
+    };
+    Some(code)
+}
+
+//key and whether shift is needed to type a character on a US layout, None for characters with no key
+pub fn char_to_key(c: char) -> Option<(Key, bool)> {
+    //letters are in alphabetical order starting at the code for A
+    if c.is_ascii_alphabetic() {
+        let key = Key::from_u16(Key::A.to_u16() + (c.to_ascii_lowercase() as u16 - 'a' as u16));
+        return Some((key, c.is_ascii_uppercase()));
+    }
+    let key = match c {
+        '1' => (Key::Digit1, false), '!' => (Key::Digit1, true),
+        '2' => (Key::Digit2, false), '@' => (Key::Digit2, true),
+        '3' => (Key::Digit3, false), '#' => (Key::Digit3, true),
+        '4' => (Key::Digit4, false), '$' => (Key::Digit4, true),
+        '5' => (Key::Digit5, false), '%' => (Key::Digit5, true),
+        '6' => (Key::Digit6, false), '^' => (Key::Digit6, true),
+        '7' => (Key::Digit7, false), '&' => (Key::Digit7, true),
+        '8' => (Key::Digit8, false), '*' => (Key::Digit8, true),
+        '9' => (Key::Digit9, false), '(' => (Key::Digit9, true),
+        '0' => (Key::Digit0, false), ')' => (Key::Digit0, true),
+        '-' => (Key::Minus, false), '_' => (Key::Minus, true),
+        '=' => (Key::Equal, false), '+' => (Key::Equal, true),
+        '[' => (Key::LeftBracket, false), '{' => (Key::LeftBracket, true),
+        ']' => (Key::RightBracket, false), '}' => (Key::RightBracket, true),
+        '\\' => (Key::Backslash, false), '|' => (Key::Backslash, true),
+        ';' => (Key::Semicolon, false), ':' => (Key::Semicolon, true),
+        '\'' => (Key::Quote, false), '"' => (Key::Quote, true),
+        '`' => (Key::Grave, false), '~' => (Key::Grave, true),
+        ',' => (Key::Comma, false), '<' => (Key::Comma, true),
+        '.' => (Key::Period, false), '>' => (Key::Period, true),
+        '/' => (Key::Slash, false), '?' => (Key::Slash, true),
+        ' ' => (Key::Space, false),
+        '\t' => (Key::Tab, false),
+        '\n' => (Key::Enter, false),
+        _ => return None,
+    };
+    Some(key)
+}
@@ -0,0 +1,63 @@
+use std::error::Error;
+use core_graphics::event::{ CGEvent, CGEventTapLocation, CGEventType, CGMouseButton };
+use core_graphics::event_source::{ CGEventSource, CGEventSourceStateID };
+use core_graphics::geometry::CGPoint;
+use common::input::{ Key, MouseButton };
+use super::InputInjector;
+
+//injects pointer movement through Core Graphics events, other input is only logged for now
+pub struct CoreGraphicsInjector;
+
+impl CoreGraphicsInjector {
+    pub fn new() -> Self {
+        CoreGraphicsInjector
+    }
+}
+
+impl Default for CoreGraphicsInjector {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl InputInjector for CoreGraphicsInjector {
+    fn name(&self) -> &str {
+        "Core Graphics"
+    }
+
+    //which key isn't logged, keystrokes may be a password
+    fn key(&mut self, _key: Key, _pressed: bool) -> Result<(), Box<dyn Error>> {
+        println!("Key not injected on macos");
+        Ok(())
+    }
+
+    fn text(&mut self, text: &str) -> Result<(), Box<dyn Error>> {
+        println!("Text of {} characters not injected on macos", text.chars().count());
+        Ok(())
+    }
+
+    fn button(&mut self, button: MouseButton, pressed: bool) -> Result<(), Box<dyn Error>> {
+        println!("Mouse {:?} {} not injected on macos", button, if pressed { "down" } else { "up" });
+        Ok(())
+    }
+
+    fn move_to(&mut self, x: u32, y: u32) -> Result<(), Box<dyn Error>> {
+        let src = CGEventSource::new(CGEventSourceStateID::HIDSystemState)
+            .map_err(|_| "Failed to create CGEventSource")?;
+
+        //create event
+        let pos = CGPoint::new(x as f64, y as f64);
+        let move_event = CGEvent::new_mouse_event(src, CGEventType::MouseMoved, pos, CGMouseButton::Left)
+            .map_err(|_| "Failed to create CGEvent")?;
+
+        //post it to the system
+        move_event.post(CGEventTapLocation::HID);
+
+        Ok(())
+    }
+
+    fn scroll(&mut self, x: i32, y: i32) -> Result<(), Box<dyn Error>> {
+        println!("Scroll x={x} y={y} not injected on macos");
+        Ok(())
+    }
+}
@@ -0,0 +1,358 @@
+use std::{
+    env,
+    error::Error,
+    sync::{ Arc, Mutex },
+};
+use common::input::{ Key, Modifiers, MouseButton, ScrollDelta, SCROLL_STEPS_PER_LINE };
+
+#[cfg(target_os = "linux")]
+mod keymap;
+#[cfg(target_os = "linux")]
+mod uinput;
+#[cfg(target_os = "linux")]
+pub use uinput::UinputInjector;
+#[cfg(target_os = "linux")]
+mod ydotool;
+#[cfg(target_os = "linux")]
+pub use ydotool::YdotoolInjector;
+#[cfg(target_os = "linux")]
+mod xtest;
+#[cfg(target_os = "linux")]
+pub use xtest::XTestInjector;
+
+#[cfg(target_os = "macos")]
+mod mac;
+#[cfg(target_os = "macos")]
+pub use mac::CoreGraphicsInjector;
+
+mod recording;
+pub use recording::{ RecordingInjector, InjectedEvent };
+
+//anything that can turn client input into input on the server, a real device or an in-memory log
+pub trait InputInjector: Send {
+    //short name used in logs
+    fn name(&self) -> &str;
+
+    fn key(&mut self, key: Key, pressed: bool) -> Result<(), Box<dyn Error>>;
+
+    //type text that has no key of its own
+    fn text(&mut self, text: &str) -> Result<(), Box<dyn Error>>;
+
+    fn button(&mut self, button: MouseButton, pressed: bool) -> Result<(), Box<dyn Error>>;
+
+    //move the pointer to an absolute position in screen pixels
+    fn move_to(&mut self, x: u32, y: u32) -> Result<(), Box<dyn Error>>;
+
+    //the captured screen changed size, move_to positions are in the new size from now on
+    //only backends that can't ask the system for the screen size need to know
+    fn set_screen_size(&mut self, _width: u32, _height: u32) {}
+
+    //scroll in SCROLL_STEPS_PER_LINE steps per line, positive y scrolls up and positive x scrolls right
+    fn scroll(&mut self, x: i32, y: i32) -> Result<(), Box<dyn Error>>;
+}
+
+//one injector shared by every session, the virtual devices are created once for the whole server
+pub type SharedInjector = Arc<Mutex<Box<dyn InputInjector>>>;
+
+pub fn shared(injector: Box<dyn InputInjector>) -> SharedInjector {
+    Arc::new(Mutex::new(injector))
+}
+
+//input backend for this platform, screen is the size of the captured screen in pixels
+//an error if none of them can be used, clients aren't offered input that would go nowhere
+//recording input instead has to be asked for with input_backend = "record"
+#[cfg(target_os = "linux")]
+pub fn platform_injector(screen: (usize, usize)) -> Result<Box<dyn InputInjector>, Box<dyn Error>> {
+    //XTest needs no privileges but only reaches X11 programs, so it is only the first choice on a plain X11 session
+    if env::var_os("DISPLAY").is_some() && env::var_os("WAYLAND_DISPLAY").is_none() {
+        match XTestInjector::connect(None) {
+            Ok(injector) => return Ok(Box::new(injector)),
+            Err(e) => eprintln!("Failed to use XTest ({e}), trying uinput"),
+        }
+    }
+    let uinput_error = match UinputInjector::new(screen.0 as u32, screen.1 as u32) {
+        Ok(injector) => return Ok(Box::new(injector)),
+        Err(e) => e,
+    };
+    if YdotoolInjector::installed() {
+        eprintln!("Failed to open /dev/uinput ({uinput_error}), using ydotool, ydotoold has to be running");
+        return Ok(Box::new(YdotoolInjector::new()));
+    }
+    Err(format!(
+        "No input backend available: failed to open /dev/uinput ({uinput_error}) and ydotool isn't installed, \
+        set input_backend to record to run without input or allow_input to none"
+    ).into())
+}
+
+#[cfg(target_os = "macos")]
+pub fn platform_injector(_screen: (usize, usize)) -> Result<Box<dyn InputInjector>, Box<dyn Error>> {
+    Ok(Box::new(CoreGraphicsInjector::new()))
+}
+
+//one session's view of the server keyboard, remembers what it pressed so nothing is left held down
+//every key still pressed is released when it is dropped at the end of the session
+pub struct Keyboard {
+    injector: SharedInjector,
+    pressed: Vec<Key>,
+}
+
+impl Keyboard {
+    pub fn new(injector: SharedInjector) -> Self {
+        Keyboard { injector, pressed: Vec::new() }
+    }
+
+    pub fn key_down(&mut self, key: Key, modifiers: Modifiers) -> Result<(), Box<dyn Error>> {
+        //the client says which modifiers are held, release any we are holding that it let go of
+        //this covers key ups lost when the client window loses focus
+        let stale: Vec<Key> = self.pressed.iter()
+            .copied()
+            .filter(|held| *held != key && held.modifier().is_some_and(|m| !modifiers.contains(m)))
+            .collect();
+        for held in stale {
+            self.key_up(held)?;
+        }
+
+        self.injector.lock().unwrap().key(key, true)?;
+        //auto repeat sends more key downs, the key only needs releasing once
+        if !self.pressed.contains(&key) {
+            self.pressed.push(key);
+        }
+        Ok(())
+    }
+
+    pub fn key_up(&mut self, key: Key) -> Result<(), Box<dyn Error>> {
+        //only release keys this session pressed
+        let Some(i) = self.pressed.iter().position(|held| *held == key) else {
+            return Ok(());
+        };
+        self.pressed.remove(i);
+        self.injector.lock().unwrap().key(key, false)
+    }
+
+    pub fn type_text(&mut self, text: &str) -> Result<(), Box<dyn Error>> {
+        self.injector.lock().unwrap().text(text)
+    }
+
+    //release everything still held, newest first so modifiers go after the keys they modify
+    pub fn release_all(&mut self) {
+        let mut injector = self.injector.lock().unwrap();
+        while let Some(key) = self.pressed.pop() {
+            if let Err(e) = injector.key(key, false) {
+                eprintln!("Failed to release {:?}: {e}", key);
+            }
+        }
+    }
+}
+
+impl Drop for Keyboard {
+    fn drop(&mut self) {
+        if !self.pressed.is_empty() {
+            println!("Releasing {} keys still held at end of session", self.pressed.len());
+        }
+        self.release_all();
+    }
+}
+
+//how many pixels of touchpad scrolling count as one line of wheel scrolling
+const PIXELS_PER_LINE: i32 = 20;
+
+//one session's view of the server pointer, buttons are released the same way as Keyboard
+pub struct Mouse {
+    injector: SharedInjector,
+    pressed: Vec<MouseButton>,
+}
+
+impl Mouse {
+    pub fn new(injector: SharedInjector) -> Self {
+        Mouse { injector, pressed: Vec::new() }
+    }
+
+    pub fn move_to(&mut self, x: u32, y: u32) -> Result<(), Box<dyn Error>> {
+        self.injector.lock().unwrap().move_to(x, y)
+    }
+
+    pub fn button_down(&mut self, button: MouseButton) -> Result<(), Box<dyn Error>> {
+        self.injector.lock().unwrap().button(button, true)?;
+        if !self.pressed.contains(&button) {
+            self.pressed.push(button);
+        }
+        Ok(())
+    }
+
+    pub fn button_up(&mut self, button: MouseButton) -> Result<(), Box<dyn Error>> {
+        //only release buttons this session pressed
+        let Some(i) = self.pressed.iter().position(|held| *held == button) else {
+            return Ok(());
+        };
+        self.pressed.remove(i);
+        self.injector.lock().unwrap().button(button, false)
+    }
+
+    pub fn scroll(&mut self, delta: ScrollDelta) -> Result<(), Box<dyn Error>> {
+        let (x, y) = match delta {
+            ScrollDelta::Lines { x, y } => (x, y),
+            ScrollDelta::Pixels { x, y } => (
+                x.saturating_mul(SCROLL_STEPS_PER_LINE) / PIXELS_PER_LINE,
+                y.saturating_mul(SCROLL_STEPS_PER_LINE) / PIXELS_PER_LINE,
+            ),
+        };
+        if x == 0 && y == 0 {
+            return Ok(());
+        }
+        self.injector.lock().unwrap().scroll(x, y)
+    }
+
+    pub fn release_all(&mut self) {
+        let mut injector = self.injector.lock().unwrap();
+        while let Some(button) = self.pressed.pop() {
+            if let Err(e) = injector.button(button, false) {
+                eprintln!("Failed to release {:?}: {e}", button);
+            }
+        }
+    }
+}
+
+impl Drop for Mouse {
+    fn drop(&mut self) {
+        if !self.pressed.is_empty() {
+            println!("Releasing {} mouse buttons still held at end of session", self.pressed.len());
+        }
+        self.release_all();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::VecDeque;
+    use super::*;
+
+    fn recorded() -> (SharedInjector, Arc<Mutex<VecDeque<InjectedEvent>>>) {
+        let injector = RecordingInjector::new();
+        let events = injector.events();
+        (shared(Box::new(injector)), events)
+    }
+
+    fn take(events: &Mutex<VecDeque<InjectedEvent>>) -> Vec<InjectedEvent> {
+        events.lock().unwrap().drain(..).collect()
+    }
+
+    fn key(key: Key, pressed: bool) -> InjectedEvent {
+        InjectedEvent::Key { key, pressed }
+    }
+
+    fn button(button: MouseButton, pressed: bool) -> InjectedEvent {
+        InjectedEvent::Button { button, pressed }
+    }
+
+    #[test]
+    fn held_keys_are_released_newest_first_on_drop() {
+        let (injector, events) = recorded();
+        let mut keyboard = Keyboard::new(injector);
+        keyboard.key_down(Key::LeftCtrl, Modifiers::CTRL).unwrap();
+        keyboard.key_down(Key::LeftShift, Modifiers::CTRL.union(Modifiers::SHIFT)).unwrap();
+        keyboard.key_down(Key::A, Modifiers::CTRL.union(Modifiers::SHIFT)).unwrap();
+        keyboard.key_down(Key::B, Modifiers::CTRL.union(Modifiers::SHIFT)).unwrap();
+        keyboard.key_up(Key::B).unwrap();
+        take(&events);
+
+        drop(keyboard);
+        assert_eq!(take(&events), vec![key(Key::A, false), key(Key::LeftShift, false), key(Key::LeftCtrl, false)]);
+    }
+
+    #[test]
+    fn auto_repeat_is_released_once_and_unpressed_keys_not_at_all() {
+        let (injector, events) = recorded();
+        let mut keyboard = Keyboard::new(injector);
+        keyboard.key_down(Key::A, Modifiers::NONE).unwrap();
+        keyboard.key_down(Key::A, Modifiers::NONE).unwrap();
+        keyboard.key_up(Key::A).unwrap();
+        keyboard.key_up(Key::A).unwrap();
+        keyboard.key_up(Key::B).unwrap();
+        drop(keyboard);
+        assert_eq!(take(&events), vec![key(Key::A, true), key(Key::A, true), key(Key::A, false)]);
+    }
+
+    #[test]
+    fn modifiers_the_client_let_go_of_are_released_before_the_next_key() {
+        let (injector, events) = recorded();
+        let mut keyboard = Keyboard::new(injector);
+        keyboard.key_down(Key::LeftShift, Modifiers::SHIFT).unwrap();
+        keyboard.key_down(Key::LeftCtrl, Modifiers::SHIFT.union(Modifiers::CTRL)).unwrap();
+        take(&events);
+
+        //the shift key up was lost, the next key says only ctrl is held
+        keyboard.key_down(Key::C, Modifiers::CTRL).unwrap();
+        assert_eq!(take(&events), vec![key(Key::LeftShift, false), key(Key::C, true)]);
+
+        //a modifier's own key down doesn't release it, even before the client counts it as held
+        keyboard.key_down(Key::LeftShift, Modifiers::CTRL).unwrap();
+        assert_eq!(take(&events), vec![key(Key::LeftShift, true)]);
+
+        drop(keyboard);
+        assert_eq!(take(&events), vec![key(Key::LeftShift, false), key(Key::C, false), key(Key::LeftCtrl, false)]);
+    }
+
+    #[test]
+    fn text_is_typed_without_holding_anything() {
+        let (injector, events) = recorded();
+        let mut keyboard = Keyboard::new(injector);
+        keyboard.type_text("hi there").unwrap();
+        drop(keyboard);
+        assert_eq!(take(&events), vec![InjectedEvent::Text("hi there".to_string())]);
+    }
+
+    #[test]
+    fn held_buttons_are_released_on_drop() {
+        let (injector, events) = recorded();
+        let mut mouse = Mouse::new(injector);
+        mouse.move_to(10, 20).unwrap();
+        mouse.button_down(MouseButton::Left).unwrap();
+        mouse.button_down(MouseButton::Right).unwrap();
+        mouse.button_down(MouseButton::Right).unwrap();
+        mouse.button_up(MouseButton::Middle).unwrap();
+        mouse.button_down(MouseButton::Back).unwrap();
+        mouse.button_up(MouseButton::Back).unwrap();
+        assert_eq!(take(&events), vec![
+            InjectedEvent::MoveTo { x: 10, y: 20 },
+            button(MouseButton::Left, true),
+            button(MouseButton::Right, true),
+            button(MouseButton::Right, true),
+            button(MouseButton::Back, true),
+            button(MouseButton::Back, false),
+        ]);
+
+        drop(mouse);
+        assert_eq!(take(&events), vec![button(MouseButton::Right, false), button(MouseButton::Left, false)]);
+    }
+
+    #[test]
+    fn pixel_scrolling_is_turned_into_wheel_steps() {
+        let (injector, events) = recorded();
+        let mut mouse = Mouse::new(injector);
+        mouse.scroll(ScrollDelta::Lines { x: 0, y: -SCROLL_STEPS_PER_LINE }).unwrap();
+        mouse.scroll(ScrollDelta::Pixels { x: PIXELS_PER_LINE, y: PIXELS_PER_LINE / 2 }).unwrap();
+        //too little to move anything isn't sent at all
+        mouse.scroll(ScrollDelta::Pixels { x: 0, y: 0 }).unwrap();
+        mouse.scroll(ScrollDelta::Lines { x: 0, y: 0 }).unwrap();
+        assert_eq!(take(&events), vec![
+            InjectedEvent::Scroll { x: 0, y: -SCROLL_STEPS_PER_LINE },
+            InjectedEvent::Scroll { x: SCROLL_STEPS_PER_LINE, y: SCROLL_STEPS_PER_LINE / 2 },
+        ]);
+    }
+
+    #[test]
+    fn two_sessions_only_release_what_they_pressed() {
+        let (injector, events) = recorded();
+        let mut first = Keyboard::new(injector.clone());
+        let mut second = Keyboard::new(injector);
+        first.key_down(Key::A, Modifiers::NONE).unwrap();
+        second.key_down(Key::B, Modifiers::NONE).unwrap();
+        second.key_up(Key::A).unwrap();
+        take(&events);
+
+        drop(first);
+        assert_eq!(take(&events), vec![key(Key::A, false)]);
+        drop(second);
+        assert_eq!(take(&events), vec![key(Key::B, false)]);
+    }
+}
@@ -0,0 +1,77 @@
+use std::{
+    collections::VecDeque,
+    error::Error,
+    sync::{ Arc, Mutex },
+};
+use common::input::{ Key, MouseButton };
+use super::InputInjector;
+
+//one call made on an injector
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum InjectedEvent {
+    Key { key: Key, pressed: bool },
+    Text(String),
+    Button { button: MouseButton, pressed: bool },
+    MoveTo { x: u32, y: u32 },
+    Scroll { x: i32, y: i32 },
+}
+
+//oldest events are forgotten past this so a server left running with input_backend = "record" doesn't grow forever
+const MAX_RECORDED_EVENTS: usize = 10_000;
+
+//injector that only remembers what it was asked to do, for tests and for servers that shouldn't touch real input
+pub struct RecordingInjector {
+    events: Arc<Mutex<VecDeque<InjectedEvent>>>,
+}
+
+impl RecordingInjector {
+    pub fn new() -> Self {
+        RecordingInjector { events: Arc::new(Mutex::new(VecDeque::new())) }
+    }
+
+    //handle on the recorded events that stays valid after the injector is boxed and shared
+    pub fn events(&self) -> Arc<Mutex<VecDeque<InjectedEvent>>> {
+        self.events.clone()
+    }
+
+    fn record(&self, event: InjectedEvent) -> Result<(), Box<dyn Error>> {
+        let mut events = self.events.lock().unwrap();
+        if events.len() >= MAX_RECORDED_EVENTS {
+            events.pop_front();
+        }
+        events.push_back(event);
+        Ok(())
+    }
+}
+
+impl Default for RecordingInjector {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl InputInjector for RecordingInjector {
+    fn name(&self) -> &str {
+        "recording"
+    }
+
+    fn key(&mut self, key: Key, pressed: bool) -> Result<(), Box<dyn Error>> {
+        self.record(InjectedEvent::Key { key, pressed })
+    }
+
+    fn text(&mut self, text: &str) -> Result<(), Box<dyn Error>> {
+        self.record(InjectedEvent::Text(text.to_string()))
+    }
+
+    fn button(&mut self, button: MouseButton, pressed: bool) -> Result<(), Box<dyn Error>> {
+        self.record(InjectedEvent::Button { button, pressed })
+    }
+
+    fn move_to(&mut self, x: u32, y: u32) -> Result<(), Box<dyn Error>> {
+        self.record(InjectedEvent::MoveTo { x, y })
+    }
+
+    fn scroll(&mut self, x: i32, y: i32) -> Result<(), Box<dyn Error>> {
+        self.record(InjectedEvent::Scroll { x, y })
+    }
+}
@@ -0,0 +1,196 @@
+use std::error::Error;
+use evdev::{
+    uinput::{ VirtualDevice, VirtualDeviceBuilder },
+    AttributeSet, AbsInfo, AbsoluteAxisType, EventType, InputEvent, RelativeAxisType, UinputAbsSetup,
+};
+use common::input::{ Key, MouseButton, SCROLL_STEPS_PER_LINE };
+use super::{ InputInjector, keymap::{ evdev_code, char_to_key } };
+
+//the pointer's absolute axes always run 0..=AXIS_MAX, positions are scaled to the screen size they were given in
+//so the devices don't have to be created again when the screen changes size
+const AXIS_MAX: i32 = 65535;
+
+//injects input through a virtual keyboard and a virtual absolute pointer created with /dev/uinput
+//needs write access to /dev/uinput, usually root or membership of the input group
+pub struct UinputInjector {
+    keyboard: VirtualDevice,
+    pointer: VirtualDevice,
+    //size of the captured screen, pointer positions are clamped to it
+    width: u32,
+    height: u32,
+    //high resolution scrolling not yet added up to a whole notch, for programs that only read the plain wheel
+    scroll_x: i32,
+    scroll_y: i32,
+}
+
+impl UinputInjector {
+    //create the virtual devices for a width x height screen, set_screen_size changes it later
+    pub fn new(width: u32, height: u32) -> Result<Self, Box<dyn Error>> {
+        if width == 0 || height == 0 {
+            return Err(format!("Invalid screen size {width}x{height} for absolute pointer").into());
+        }
+
+        //every key the protocol can name, protocol codes are HID usage ids which fit in a byte
+        let mut keys = AttributeSet::<evdev::Key>::new();
+        for hid in 0..=0xFFu16 {
+            if let Some(code) = evdev_code(Key::from_u16(hid)) {
+                keys.insert(evdev::Key::new(code));
+            }
+        }
+        let keyboard = VirtualDeviceBuilder::new()?
+            .name("remote desktop keyboard")
+            .with_keys(&keys)?
+            .build()?;
+
+        let mut buttons = AttributeSet::<evdev::Key>::new();
+        for button in [evdev::Key::BTN_LEFT, evdev::Key::BTN_RIGHT, evdev::Key::BTN_MIDDLE, evdev::Key::BTN_SIDE, evdev::Key::BTN_EXTRA] {
+            buttons.insert(button);
+        }
+        let mut wheels = AttributeSet::<RelativeAxisType>::new();
+        wheels.insert(RelativeAxisType::REL_WHEEL);
+        wheels.insert(RelativeAxisType::REL_HWHEEL);
+        wheels.insert(RelativeAxisType::REL_WHEEL_HI_RES);
+        wheels.insert(RelativeAxisType::REL_HWHEEL_HI_RES);
+        let abs_x = UinputAbsSetup::new(AbsoluteAxisType::ABS_X, AbsInfo::new(0, 0, AXIS_MAX, 0, 0, 0));
+        let abs_y = UinputAbsSetup::new(AbsoluteAxisType::ABS_Y, AbsInfo::new(0, 0, AXIS_MAX, 0, 0, 0));
+        let pointer = VirtualDeviceBuilder::new()?
+            .name("remote desktop pointer")
+            .with_keys(&buttons)?
+            .with_absolute_axis(&abs_x)?
+            .with_absolute_axis(&abs_y)?
+            .with_relative_axes(&wheels)?
+            .build()?;
+
+        println!("Created uinput keyboard and {}x{} pointer", width, height);
+        Ok(UinputInjector { keyboard, pointer, width, height, scroll_x: 0, scroll_y: 0 })
+    }
+
+    fn emit_key(&mut self, code: u16, pressed: bool) -> Result<(), Box<dyn Error>> {
+        self.keyboard.emit(&[InputEvent::new(EventType::KEY, code, pressed as i32)])?;
+        Ok(())
+    }
+}
+
+impl InputInjector for UinputInjector {
+    fn name(&self) -> &str {
+        "uinput"
+    }
+
+    fn key(&mut self, key: Key, pressed: bool) -> Result<(), Box<dyn Error>> {
+        let Some(code) = evdev_code(key) else {
+            println!("No linux key code for {:?}, skipping", key);
+            return Ok(());
+        };
+        self.emit_key(code, pressed)
+    }
+
+    //typed as key presses on a US layout, characters with no key are skipped
+    fn text(&mut self, text: &str) -> Result<(), Box<dyn Error>> {
+        for c in text.chars() {
+            let Some((key, shift)) = char_to_key(c) else {
+                println!("No key for a typed character, skipping it");
+                continue;
+            };
+            let Some(code) = evdev_code(key) else {
+                continue;
+            };
+            if shift {
+                self.emit_key(evdev::Key::KEY_LEFTSHIFT.code(), true)?;
+            }
+            self.emit_key(code, true)?;
+            self.emit_key(code, false)?;
+            if shift {
+                self.emit_key(evdev::Key::KEY_LEFTSHIFT.code(), false)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn button(&mut self, button: MouseButton, pressed: bool) -> Result<(), Box<dyn Error>> {
+        //BTN_SIDE and BTN_EXTRA are what browsers treat as back and forward
+        let code = match button {
+            MouseButton::Left => evdev::Key::BTN_LEFT,
+            MouseButton::Right => evdev::Key::BTN_RIGHT,
+            MouseButton::Middle => evdev::Key::BTN_MIDDLE,
+            MouseButton::Back => evdev::Key::BTN_SIDE,
+            MouseButton::Forward => evdev::Key::BTN_EXTRA,
+            MouseButton::Unknown(_) => {
+                println!("No linux button for {:?}, skipping", button);
+                return Ok(());
+            }
+        };
+        self.pointer.emit(&[InputEvent::new(EventType::KEY, code.code(), pressed as i32)])?;
+        Ok(())
+    }
+
+    fn move_to(&mut self, x: u32, y: u32) -> Result<(), Box<dyn Error>> {
+        let x = axis_value(x, self.width);
+        let y = axis_value(y, self.height);
+        self.pointer.emit(&[
+            InputEvent::new(EventType::ABSOLUTE, AbsoluteAxisType::ABS_X.0, x),
+            InputEvent::new(EventType::ABSOLUTE, AbsoluteAxisType::ABS_Y.0, y),
+        ])?;
+        Ok(())
+    }
+
+    fn set_screen_size(&mut self, width: u32, height: u32) {
+        if width > 0 && height > 0 && (width, height) != (self.width, self.height) {
+            println!("uinput pointer now maps to {}x{}", width, height);
+            (self.width, self.height) = (width, height);
+        }
+    }
+
+    fn scroll(&mut self, x: i32, y: i32) -> Result<(), Box<dyn Error>> {
+        //the high resolution axes use 120 per notch, the same units as the protocol
+        let mut events = vec![
+            InputEvent::new(EventType::RELATIVE, RelativeAxisType::REL_WHEEL_HI_RES.0, y),
+            InputEvent::new(EventType::RELATIVE, RelativeAxisType::REL_HWHEEL_HI_RES.0, x),
+        ];
+
+        //the plain wheel axes only move in whole notches, like a real high resolution mouse
+        self.scroll_x = self.scroll_x.saturating_add(x);
+        self.scroll_y = self.scroll_y.saturating_add(y);
+        let notches_x = self.scroll_x / SCROLL_STEPS_PER_LINE;
+        let notches_y = self.scroll_y / SCROLL_STEPS_PER_LINE;
+        if notches_y != 0 {
+            events.push(InputEvent::new(EventType::RELATIVE, RelativeAxisType::REL_WHEEL.0, notches_y));
+            self.scroll_y -= notches_y * SCROLL_STEPS_PER_LINE;
+        }
+        if notches_x != 0 {
+            events.push(InputEvent::new(EventType::RELATIVE, RelativeAxisType::REL_HWHEEL.0, notches_x));
+            self.scroll_x -= notches_x * SCROLL_STEPS_PER_LINE;
+        }
+
+        self.pointer.emit(&events)?;
+        Ok(())
+    }
+}
+
+//where a pixel position on a screen of size pixels lands on an axis running 0..=AXIS_MAX
+fn axis_value(position: u32, size: u32) -> i32 {
+    let last = size.saturating_sub(1) as u64;
+    if last == 0 {
+        return 0;
+    }
+    (position.min(last as u32) as u64 * AXIS_MAX as u64 / last) as i32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn positions_span_the_whole_axis_at_any_screen_size() {
+        for size in [640, 1920, 3840] {
+            assert_eq!(axis_value(0, size), 0);
+            assert_eq!(axis_value(size / 2, size), (AXIS_MAX as u64 * (size / 2) as u64 / (size - 1) as u64) as i32);
+            assert_eq!(axis_value(size - 1, size), AXIS_MAX);
+            //past the edge is held at the edge
+            assert_eq!(axis_value(size + 100, size), AXIS_MAX);
+        }
+        //the same pixel lands in a different place once the screen is bigger
+        assert!(axis_value(639, 1280) < axis_value(639, 640));
+        assert_eq!(axis_value(5, 1), 0);
+        assert_eq!(axis_value(5, 0), 0);
+    }
+}
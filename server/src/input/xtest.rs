@@ -0,0 +1,350 @@
+use std::error::Error;
+use x11rb::{
+    connection::{ Connection, RequestConnection },
+    protocol::{
+        xproto::{
+            self, ConnectionExt as _,
+            KEY_PRESS_EVENT, KEY_RELEASE_EVENT, BUTTON_PRESS_EVENT, BUTTON_RELEASE_EVENT, MOTION_NOTIFY_EVENT,
+        },
+        xtest::{ self, ConnectionExt as _ },
+    },
+    rust_connection::RustConnection,
+};
+use common::input::{ Key, MouseButton, SCROLL_STEPS_PER_LINE };
+use super::{ InputInjector, keymap::evdev_code };
+
+//X servers using the evdev keymap (Xorg, Xvfb, Xwayland) number keys as the linux key code plus 8
+const EVDEV_KEYCODE_OFFSET: u16 = 8;
+//X pointer buttons, 4 to 7 are the scroll wheel
+const BUTTON_LEFT: u8 = 1;
+const BUTTON_MIDDLE: u8 = 2;
+const BUTTON_RIGHT: u8 = 3;
+const BUTTON_SCROLL_UP: u8 = 4;
+const BUTTON_SCROLL_DOWN: u8 = 5;
+const BUTTON_SCROLL_LEFT: u8 = 6;
+const BUTTON_SCROLL_RIGHT: u8 = 7;
+const BUTTON_BACK: u8 = 8;
+const BUTTON_FORWARD: u8 = 9;
+//keysyms from X11/keysymdef.h
+const XK_SHIFT_L: u32 = 0xFFE1;
+const XK_TAB: u32 = 0xFF09;
+const XK_RETURN: u32 = 0xFF0D;
+
+//injects input through the XTest extension of an X server, needs no special privileges
+//only programs on that X server see the input, so on wayland it only reaches Xwayland windows
+pub struct XTestInjector {
+    conn: RustConnection,
+    root: xproto::Window,
+    //keysyms of every keycode, used to find the key that types a character
+    min_keycode: u8,
+    keysyms_per_keycode: u8,
+    keysyms: Vec<u32>,
+    //scrolling smaller than one wheel notch, kept until it adds up since X only scrolls in whole button clicks
+    scroll_x: i32,
+    scroll_y: i32,
+}
+
+impl XTestInjector {
+    //connect to the named display, or to $DISPLAY when None
+    pub fn connect(display: Option<&str>) -> Result<Self, Box<dyn Error>> {
+        let (conn, screen) = x11rb::connect(display)?;
+        if conn.extension_information(xtest::X11_EXTENSION_NAME)?.is_none() {
+            return Err("X server has no XTest extension".into());
+        }
+        let root = conn.setup().roots[screen].root;
+
+        let min_keycode = conn.setup().min_keycode;
+        let max_keycode = conn.setup().max_keycode;
+        let mapping = conn.get_keyboard_mapping(min_keycode, max_keycode - min_keycode + 1)?.reply()?;
+
+        Ok(XTestInjector {
+            conn,
+            root,
+            min_keycode,
+            keysyms_per_keycode: mapping.keysyms_per_keycode,
+            keysyms: mapping.keysyms,
+            scroll_x: 0,
+            scroll_y: 0,
+        })
+    }
+
+    fn fake(&self, event_type: u8, detail: u8, x: i16, y: i16) -> Result<(), Box<dyn Error>> {
+        //device 0 sends through the core keyboard and pointer
+        self.conn.xtest_fake_input(event_type, detail, x11rb::CURRENT_TIME, self.root, x, y, 0)?;
+        Ok(())
+    }
+
+    fn click(&self, button: u8, times: i32) -> Result<(), Box<dyn Error>> {
+        for _ in 0..times {
+            self.fake(BUTTON_PRESS_EVENT, button, 0, 0)?;
+            self.fake(BUTTON_RELEASE_EVENT, button, 0, 0)?;
+        }
+        Ok(())
+    }
+
+    //keycode that types the keysym and whether shift is needed, from the server's current keymap
+    fn keycode_for(&self, keysym: u32) -> Option<(u8, bool)> {
+        keycode_in(&self.keysyms, self.keysyms_per_keycode, self.min_keycode, keysym)
+    }
+}
+
+//search a keymap of keysyms_per_keycode keysyms for every keycode from min_keycode up
+//the first keysym of a keycode is typed on its own and the second with shift
+fn keycode_in(keysyms: &[u32], keysyms_per_keycode: u8, min_keycode: u8, keysym: u32) -> Option<(u8, bool)> {
+    let per = keysyms_per_keycode as usize;
+    if per == 0 {
+        return None;
+    }
+    for (i, syms) in keysyms.chunks(per).enumerate() {
+        let keycode = u8::try_from(min_keycode as usize + i).ok()?;
+        if syms.first() == Some(&keysym) {
+            return Some((keycode, false));
+        }
+        if syms.get(1) == Some(&keysym) {
+            return Some((keycode, true));
+        }
+    }
+    None
+}
+
+//whole wheel notches in the pending scroll steps, what is left of a notch stays pending
+fn take_notches(pending: &mut i32) -> i32 {
+    let notches = *pending / SCROLL_STEPS_PER_LINE;
+    *pending -= notches * SCROLL_STEPS_PER_LINE;
+    notches
+}
+
+//keysym for a character, latin-1 keysyms are the code point and the rest of unicode is offset by 0x01000000
+fn char_keysym(c: char) -> u32 {
+    match c {
+        '\n' => XK_RETURN,
+        '\t' => XK_TAB,
+        ' '..='~' | '\u{A0}'..='\u{FF}' => c as u32,
+        _ => 0x0100_0000 | c as u32,
+    }
+}
+
+impl InputInjector for XTestInjector {
+    fn name(&self) -> &str {
+        "XTest"
+    }
+
+    fn key(&mut self, key: Key, pressed: bool) -> Result<(), Box<dyn Error>> {
+        let Some(code) = evdev_code(key) else {
+            println!("No X key code for {:?}, skipping", key);
+            return Ok(());
+        };
+        let event_type = if pressed { KEY_PRESS_EVENT } else { KEY_RELEASE_EVENT };
+        self.fake(event_type, (code + EVDEV_KEYCODE_OFFSET) as u8, 0, 0)?;
+        self.conn.flush()?;
+        Ok(())
+    }
+
+    //typed with whatever keys the server's keymap has for each character, characters with no key are skipped
+    fn text(&mut self, text: &str) -> Result<(), Box<dyn Error>> {
+        let shift = self.keycode_for(XK_SHIFT_L).map(|(code, _)| code);
+        for c in text.chars() {
+            let Some((keycode, needs_shift)) = self.keycode_for(char_keysym(c)) else {
+                println!("No key for a typed character in the X keymap, skipping it");
+                continue;
+            };
+            let shift = if needs_shift { shift } else { None };
+            if let Some(shift) = shift {
+                self.fake(KEY_PRESS_EVENT, shift, 0, 0)?;
+            }
+            self.fake(KEY_PRESS_EVENT, keycode, 0, 0)?;
+            self.fake(KEY_RELEASE_EVENT, keycode, 0, 0)?;
+            if let Some(shift) = shift {
+                self.fake(KEY_RELEASE_EVENT, shift, 0, 0)?;
+            }
+        }
+        self.conn.flush()?;
+        Ok(())
+    }
+
+    fn button(&mut self, button: MouseButton, pressed: bool) -> Result<(), Box<dyn Error>> {
+        let detail = match button {
+            MouseButton::Left => BUTTON_LEFT,
+            MouseButton::Middle => BUTTON_MIDDLE,
+            MouseButton::Right => BUTTON_RIGHT,
+            MouseButton::Back => BUTTON_BACK,
+            MouseButton::Forward => BUTTON_FORWARD,
+            MouseButton::Unknown(_) => {
+                println!("No X button for {:?}, skipping", button);
+                return Ok(());
+            }
+        };
+        let event_type = if pressed { BUTTON_PRESS_EVENT } else { BUTTON_RELEASE_EVENT };
+        self.fake(event_type, detail, 0, 0)?;
+        self.conn.flush()?;
+        Ok(())
+    }
+
+    fn move_to(&mut self, x: u32, y: u32) -> Result<(), Box<dyn Error>> {
+        //detail 0 makes the motion absolute, relative to the root window
+        let x = x.min(i16::MAX as u32) as i16;
+        let y = y.min(i16::MAX as u32) as i16;
+        self.fake(MOTION_NOTIFY_EVENT, 0, x, y)?;
+        self.conn.flush()?;
+        Ok(())
+    }
+
+    fn scroll(&mut self, x: i32, y: i32) -> Result<(), Box<dyn Error>> {
+        self.scroll_x = self.scroll_x.saturating_add(x);
+        self.scroll_y = self.scroll_y.saturating_add(y);
+
+        //click the wheel buttons once per whole notch and keep the rest for next time
+        let notches_x = take_notches(&mut self.scroll_x);
+        let notches_y = take_notches(&mut self.scroll_y);
+
+        let vertical = if notches_y > 0 { BUTTON_SCROLL_UP } else { BUTTON_SCROLL_DOWN };
+        self.click(vertical, notches_y.abs())?;
+        let horizontal = if notches_x > 0 { BUTTON_SCROLL_RIGHT } else { BUTTON_SCROLL_LEFT };
+        self.click(horizontal, notches_x.abs())?;
+        self.conn.flush()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use x11rb::protocol::{
+        Event,
+        xproto::{ CreateWindowAux, EventMask, InputFocus, KeyButMask, WindowClass },
+    };
+    use super::*;
+
+    #[test]
+    fn characters_map_to_their_keysyms() {
+        assert_eq!(char_keysym('a'), 0x61);
+        assert_eq!(char_keysym('Z'), 0x5A);
+        assert_eq!(char_keysym(' '), 0x20);
+        assert_eq!(char_keysym('\n'), XK_RETURN);
+        assert_eq!(char_keysym('\t'), XK_TAB);
+        //latin-1 is the code point, everything past it is offset into the unicode keysyms
+        assert_eq!(char_keysym('é'), 0xE9);
+        assert_eq!(char_keysym('€'), 0x0100_20AC);
+    }
+
+    #[test]
+    fn keycodes_are_found_with_or_without_shift() {
+        //keycodes 8, 9 and 10 with two keysyms each
+        let keysyms = [0, 0, 0x61, 0x41, 0x31, 0x21];
+        assert_eq!(keycode_in(&keysyms, 2, 8, 0x61), Some((9, false)));
+        assert_eq!(keycode_in(&keysyms, 2, 8, 0x41), Some((9, true)));
+        assert_eq!(keycode_in(&keysyms, 2, 8, 0x21), Some((10, true)));
+        assert_eq!(keycode_in(&keysyms, 2, 8, 0x62), None);
+        //only the first two keysyms of a keycode are typed
+        assert_eq!(keycode_in(&[0, 0, 0, 0x61, 0x41, 0x31], 3, 8, 0x31), None);
+        assert_eq!(keycode_in(&keysyms, 0, 8, 0x61), None);
+        //a keymap that runs past the last keycode stops there
+        assert_eq!(keycode_in(&[0x61], 1, 255, 0x61), Some((255, false)));
+        assert_eq!(keycode_in(&[0, 0x61], 1, 255, 0x61), None);
+    }
+
+    #[test]
+    fn scrolling_clicks_once_per_whole_notch_and_keeps_the_rest() {
+        let half = SCROLL_STEPS_PER_LINE / 2;
+        let mut pending = half;
+        assert_eq!(take_notches(&mut pending), 0);
+        assert_eq!(pending, half);
+        pending += half;
+        assert_eq!(take_notches(&mut pending), 1);
+        assert_eq!(pending, 0);
+
+        pending = 2 * SCROLL_STEPS_PER_LINE + 10;
+        assert_eq!(take_notches(&mut pending), 2);
+        assert_eq!(pending, 10);
+        pending -= SCROLL_STEPS_PER_LINE + 20;
+        assert_eq!(take_notches(&mut pending), -1);
+        assert_eq!(pending, -10);
+    }
+
+    //wait until everything the injector sent has reached the X server and every event it caused has reached watcher
+    fn settle(injector: &XTestInjector, watcher: &RustConnection) -> Vec<Event> {
+        injector.conn.get_input_focus().unwrap().reply().unwrap();
+        watcher.get_input_focus().unwrap().reply().unwrap();
+        let mut events = Vec::new();
+        while let Some(event) = watcher.poll_for_event().unwrap() {
+            events.push(event);
+        }
+        events
+    }
+
+    fn button_presses(events: &[Event]) -> Vec<u8> {
+        events.iter().filter_map(|event| match event {
+            Event::ButtonPress(press) => Some(press.detail),
+            _ => None,
+        }).collect()
+    }
+
+    fn key_presses(events: &[Event]) -> Vec<u8> {
+        events.iter().filter_map(|event| match event {
+            Event::KeyPress(press) => Some(press.detail),
+            _ => None,
+        }).collect()
+    }
+
+    fn key_is_down(watcher: &RustConnection, keycode: u8) -> bool {
+        let keys = watcher.query_keymap().unwrap().reply().unwrap().keys;
+        keys[keycode as usize / 8] & (1 << (keycode % 8)) != 0
+    }
+
+    //needs an X server with XTest and nothing else using it, for example
+    //Xvfb :99 & DISPLAY=:99 cargo test -p server xtest -- --ignored
+    #[test]
+    #[ignore = "needs an Xvfb display in $DISPLAY"]
+    fn injects_into_an_xvfb_display() {
+        let mut injector = XTestInjector::connect(None).unwrap();
+        let (watcher, screen) = x11rb::connect(None).unwrap();
+        let root = watcher.setup().roots[screen].root;
+
+        //a window under the pointer with the keyboard focus sees every button and key the injector sends
+        let window = watcher.generate_id().unwrap();
+        let events = EventMask::BUTTON_PRESS | EventMask::KEY_PRESS;
+        watcher.create_window(
+            x11rb::COPY_DEPTH_FROM_PARENT, window, root, 0, 0, 200, 200, 0,
+            WindowClass::INPUT_OUTPUT, x11rb::COPY_FROM_PARENT, &CreateWindowAux::new().event_mask(events),
+        ).unwrap();
+        watcher.map_window(window).unwrap();
+        watcher.set_input_focus(InputFocus::POINTER_ROOT, window, x11rb::CURRENT_TIME).unwrap();
+        settle(&injector, &watcher);
+
+        injector.move_to(50, 60).unwrap();
+        settle(&injector, &watcher);
+        let pointer = watcher.query_pointer(root).unwrap().reply().unwrap();
+        assert_eq!((pointer.root_x, pointer.root_y, pointer.child), (50, 60, window));
+
+        injector.button(MouseButton::Left, true).unwrap();
+        assert_eq!(button_presses(&settle(&injector, &watcher)), vec![BUTTON_LEFT]);
+        assert!(watcher.query_pointer(root).unwrap().reply().unwrap().mask.contains(KeyButMask::BUTTON1));
+        injector.button(MouseButton::Left, false).unwrap();
+        settle(&injector, &watcher);
+        assert!(!watcher.query_pointer(root).unwrap().reply().unwrap().mask.contains(KeyButMask::BUTTON1));
+
+        //half a notch does nothing until the other half arrives
+        injector.scroll(0, SCROLL_STEPS_PER_LINE / 2).unwrap();
+        assert!(button_presses(&settle(&injector, &watcher)).is_empty());
+        injector.scroll(0, SCROLL_STEPS_PER_LINE / 2).unwrap();
+        assert_eq!(button_presses(&settle(&injector, &watcher)), vec![BUTTON_SCROLL_UP]);
+        injector.scroll(-SCROLL_STEPS_PER_LINE, -2 * SCROLL_STEPS_PER_LINE).unwrap();
+        assert_eq!(
+            button_presses(&settle(&injector, &watcher)),
+            vec![BUTTON_SCROLL_DOWN, BUTTON_SCROLL_DOWN, BUTTON_SCROLL_LEFT],
+        );
+
+        let a = (evdev_code(Key::A).unwrap() + EVDEV_KEYCODE_OFFSET) as u8;
+        injector.key(Key::A, true).unwrap();
+        assert_eq!(key_presses(&settle(&injector, &watcher)), vec![a]);
+        assert!(key_is_down(&watcher, a));
+        injector.key(Key::A, false).unwrap();
+        settle(&injector, &watcher);
+        assert!(!key_is_down(&watcher, a));
+
+        //text goes through the keymap, an upper case letter is typed with shift
+        let shift = injector.keycode_for(XK_SHIFT_L).unwrap().0;
+        injector.text("aA").unwrap();
+        assert_eq!(key_presses(&settle(&injector, &watcher)), vec![a, shift, a]);
+        assert!(!key_is_down(&watcher, a) && !key_is_down(&watcher, shift));
+    }
+}
@@ -0,0 +1,99 @@
+use std::{
+    env,
+    error::Error,
+    process::Command,
+};
+use common::input::{ Key, MouseButton, SCROLL_STEPS_PER_LINE };
+use super::{ InputInjector, keymap::evdev_code };
+
+//injects input by running the ydotool command for every event, needs ydotoold running (see tcp_server.rs)
+//slower than uinput since it forks a process per event, but works where the server can't open /dev/uinput itself
+pub struct YdotoolInjector {
+    //scrolling smaller than one wheel notch, kept until it adds up since ydotool only scrolls whole notches
+    scroll_x: i32,
+    scroll_y: i32,
+}
+
+impl YdotoolInjector {
+    pub fn new() -> Self {
+        YdotoolInjector { scroll_x: 0, scroll_y: 0 }
+    }
+
+    //whether the ydotool command can be found, ydotoold still has to be running for it to work
+    pub fn installed() -> bool {
+        env::var_os("PATH").is_some_and(|path| env::split_paths(&path).any(|dir| dir.join("ydotool").is_file()))
+    }
+}
+
+impl Default for YdotoolInjector {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn ydotool(args: &[&str]) -> Result<(), Box<dyn Error>> {
+    let status = Command::new("ydotool").args(args).status()?;
+    if !status.success() {
+        return Err(format!("ydotool {} failed: {status}", args[0]).into());
+    }
+    Ok(())
+}
+
+impl InputInjector for YdotoolInjector {
+    fn name(&self) -> &str {
+        "ydotool"
+    }
+
+    fn key(&mut self, key: Key, pressed: bool) -> Result<(), Box<dyn Error>> {
+        let Some(code) = evdev_code(key) else {
+            println!("No linux key code for {:?}, skipping", key);
+            return Ok(());
+        };
+
+        //ydotool takes <evdev code>:<1 for press, 0 for release>
+        ydotool(&["key", &format!("{}:{}", code, pressed as u8)])
+    }
+
+    //ydotool works out the key presses for each character
+    fn text(&mut self, text: &str) -> Result<(), Box<dyn Error>> {
+        ydotool(&["type", "--", text])
+    }
+
+    fn button(&mut self, button: MouseButton, pressed: bool) -> Result<(), Box<dyn Error>> {
+        //ydotool button numbers, BTN_SIDE and BTN_EXTRA are what browsers treat as back and forward
+        let code: u8 = match button {
+            MouseButton::Left => 0x00,
+            MouseButton::Right => 0x01,
+            MouseButton::Middle => 0x02,
+            MouseButton::Back => 0x03,
+            MouseButton::Forward => 0x04,
+            MouseButton::Unknown(_) => {
+                println!("No linux button for {:?}, skipping", button);
+                return Ok(());
+            }
+        };
+
+        //0x40 flag presses the button, 0x80 releases it
+        let flag: u8 = if pressed { 0x40 } else { 0x80 };
+        ydotool(&["click", &format!("{:#04x}", code | flag)])
+    }
+
+    fn move_to(&mut self, x: u32, y: u32) -> Result<(), Box<dyn Error>> {
+        ydotool(&["mousemove", "--absolute", "-x", &x.to_string(), "-y", &y.to_string()])
+    }
+
+    fn scroll(&mut self, x: i32, y: i32) -> Result<(), Box<dyn Error>> {
+        self.scroll_x = self.scroll_x.saturating_add(x);
+        self.scroll_y = self.scroll_y.saturating_add(y);
+
+        //send whole notches and keep the rest for next time
+        let notches_x = self.scroll_x / SCROLL_STEPS_PER_LINE;
+        let notches_y = self.scroll_y / SCROLL_STEPS_PER_LINE;
+        if notches_x == 0 && notches_y == 0 {
+            return Ok(());
+        }
+        self.scroll_x -= notches_x * SCROLL_STEPS_PER_LINE;
+        self.scroll_y -= notches_y * SCROLL_STEPS_PER_LINE;
+        ydotool(&["mousemove", "--wheel", "-x", &notches_x.to_string(), "-y", &notches_y.to_string()])
+    }
+}
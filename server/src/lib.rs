@@ -1,13 +1,69 @@
 //everything that brings code together to be run by main
-use std::error::Error;
+use std::{
+    error::Error,
+    io::{ self, IsTerminal },
+};
 
+mod adapt;
+mod approval;
+mod audit;
+mod auth;
 mod capture;
-pub use capture::start_sck_stream;
+pub use capture::{ CaptureSource, Frame, PixelFormat, SyntheticSource, SyntheticPattern };
+mod clipboard;
+mod config;
+mod frame_queue;
+mod gen_certs;
+mod input;
+pub use input::{ InputInjector, RecordingInjector, InjectedEvent };
 mod message_type_handlers;
+mod permissions;
+mod scale;
 mod tcp_server;
 mod tls;
-//load tls config and call tcp_server run
+//read settings, then run a setup command or load tls config and call tcp_server run
 pub fn run() -> Result<(), Box<dyn Error>> {
-    let cfg = tls::load_server_config()?;
-    tcp_server::run(cfg)
+    match config::load()? {
+        config::Action::HashPassword => {
+            let password = read_password()?;
+            println!("password_hash = \"{}\"", auth::hash_password(&password)?);
+            Ok(())
+        }
+        config::Action::GenCerts(request) => gen_certs::generate(&request),
+        config::Action::Invite(request) => {
+            let token = auth::generate_token();
+            println!("Invite token, it is given in place of the password when connecting:");
+            println!("  {token}");
+            println!("Add this to the server config and restart the server, remove it again to revoke the invite:");
+            println!();
+            println!("[[invites]]");
+            println!("name = {:?}", request.name);
+            println!("token_hash = \"{}\"", auth::hash_password(&token)?);
+            println!("permissions = [{}]", request.permissions.names().iter().map(|name| format!("{name:?}")).collect::<Vec<_>>().join(", "));
+            Ok(())
+        }
+        config::Action::Serve(config) => {
+            let cfg = tls::load_server_config(&config.cert, &config.key, config.client_ca.as_deref())?;
+            tcp_server::run(cfg, *config)
+        }
+    }
+}
+
+//prompt twice on a terminal, otherwise take the first line of stdin so it can be scripted
+fn read_password() -> Result<String, Box<dyn Error>> {
+    let password = if io::stdin().is_terminal() {
+        let password = rpassword::prompt_password("Password: ")?;
+        if rpassword::prompt_password("Repeat password: ")? != password {
+            return Err("Passwords don't match".into());
+        }
+        password
+    } else {
+        let mut line = String::new();
+        io::stdin().read_line(&mut line)?;
+        line.trim_end_matches(['\r', '\n']).to_string()
+    };
+    if password.is_empty() {
+        return Err("Password is empty".into());
+    }
+    Ok(password)
 }
\ No newline at end of file
@@ -27,6 +27,9 @@ typedef void (*sck_frame_cb)(const uint8_t *data,
 // Called from Rust to start capture
 void sck_start_capture(sck_frame_cb cb);
 
+// Called from Rust to stop capture, sck_start_capture can be called again afterwards
+void sck_stop_capture(void);
+
 #ifdef __cplusplus
 }
 #endif
\ No newline at end of file
@@ -66,6 +66,8 @@ didOutputSampleBuffer:(CMSampleBufferRef)sampleBuffer
 // Global references (to keep the stream alive)
 static SCStream *globalStream = nil;
 static SCKBridge *globalBridge = nil;
+// Run loop of the capture thread so it can be stopped
+static CFRunLoopRef globalRunLoop = NULL;
 
 void sck_start_capture(sck_frame_cb cb) {
     @autoreleasepool {
@@ -139,9 +141,33 @@ void sck_start_capture(sck_frame_cb cb) {
 
                     // 🔥 Keep run loop alive so ScreenCaptureKit can deliver frames
                     NSLog(@"[SCK] Entering CFRunLoop…");
+                    globalRunLoop = CFRunLoopGetCurrent();
                     CFRunLoopRun();
                 }];
             }
         });
     }
 }
+
+void sck_stop_capture(void) {
+    // Stop forwarding frames right away, the stream itself stops asynchronously
+    globalBridge.cb = NULL;
+
+    SCStream *stream = globalStream;
+    globalStream = nil;
+    if (stream) {
+        [stream stopCaptureWithCompletionHandler:^(NSError * _Nullable stopErr) {
+            if (stopErr) {
+                NSLog(@"stopCapture error: %@", stopErr);
+            } else {
+                NSLog(@"ScreenCaptureKit stream stopped");
+            }
+        }];
+    }
+
+    // Let the capture thread leave its run loop
+    if (globalRunLoop) {
+        CFRunLoopStop(globalRunLoop);
+        globalRunLoop = NULL;
+    }
+}
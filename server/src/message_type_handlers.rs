@@ -4,30 +4,62 @@ use std::{
     time::Instant,
     process::{ Command, },
 };
-use crate::tcp_server::send_response;
-use common::message_type::MessageType;
+use common::{
+    message_type::MessageType,
+    protocol::ErrorMessage,
+    handshake::{ self, Hello, SessionConfig, Capabilities },
+    input::{ KeyEvent, MouseButton, ScrollDelta },
+    clipboard::ClipboardContent,
+};
+use crate::input::{ Keyboard, Mouse };
+use crate::clipboard::ClipboardSubscription;
+use crate::config::{ Config, AllowedInput };
+use crate::scale::Viewer;
 use turbojpeg::{Compressor, Image, PixelFormat, Subsamp, OutputBuf};
 
-pub fn handle_text(payload: &[u8]) -> Result<(), Box<dyn Error>>  {
-    println!("Text message: {:?}", String::from_utf8_lossy(payload));
+pub fn handle_text(text: &str) -> Result<(), Box<dyn Error>>  {
+    println!("Text message: {:?}", text);
 
     Ok(())
 }
 
-pub fn handle_connect(_payload: &[u8]) -> Result<(), Box<dyn Error>>  {
-    println!("Client connected (connect message)");
+//largest frame the h.264 encoder will be asked to produce
+const MAX_ENCODE_WIDTH: u32 = 4096;
+const MAX_ENCODE_HEIGHT: u32 = 2304;
 
-    Ok(())
+//what this server announces during the hello exchange, features the config turns off are left out
+pub fn server_hello(config: &Config) -> Hello {
+    let mut capabilities = Capabilities::NONE;
+    if config.allow_input != AllowedInput::None {
+        capabilities = capabilities.union(Capabilities::INPUT);
+    }
+    if config.clipboard.enabled() {
+        capabilities = capabilities.union(Capabilities::CLIPBOARD);
+    }
+    if config.adaptive {
+        capabilities = capabilities.union(Capabilities::RECEIVE_REPORTS);
+    }
+    Hello::new(vec![config.codec], MAX_ENCODE_WIDTH, MAX_ENCODE_HEIGHT, capabilities)
 }
 
-pub fn handle_disconnect(_payload: &[u8]) -> Result<(), Box<dyn Error>>  {
+//negotiate the session from the client's hello, the error is sent back to the client as is
+pub fn handle_connect(hello: &Hello, config: &Config) -> Result<SessionConfig, ErrorMessage>  {
+    println!(
+        "Client hello: protocol {}-{}, codecs {:?}, max {}x{}, capabilities {:#x}",
+        hello.min_version, hello.max_version, hello.codecs, hello.max_width, hello.max_height, hello.capabilities.0
+    );
+
+    handshake::negotiate(hello, &server_hello(config))
+}
+
+pub fn handle_disconnect() -> Result<(), Box<dyn Error>>  {
     println!("Client requested disconnect");
 
     Ok(())
 }
 
-pub fn handle_error(payload: &[u8]) -> Result<(), Box<dyn Error>>  {
-    println!("Error message: {:?}", String::from_utf8_lossy(payload));
+pub fn handle_error(err: &ErrorMessage) -> Result<(), Box<dyn Error>>  {
+    println!("Error message: {err}");
 
     Ok(())
 }
@@ -137,56 +169,9 @@ pub fn calculate_frame_changes(prev_frame: &mut Vec<u8>, width: usize, height: u
     (frame_changes, rect_count, changed_pixels)
 }
 
-#[cfg(target_os = "macos")]
-pub fn handle_mouse_move(payload: &[u8]) -> Result<(), Box<dyn std::error::Error>> {
-    use core_graphics::event::{CGEvent, CGEventTapLocation, CGEventType, CGMouseButton};
-    use core_graphics::event_source::{CGEventSource, CGEventSourceStateID};
-    use core_graphics::geometry::CGPoint;
-
-   if payload.len() < 8 {
-        return Err("Invalid MouseMove payload".into());
-    }
-
-    // Parse x/y from big-endian u32
-    let x = u32::from_be_bytes([payload[0], payload[1], payload[2], payload[3]]);
-    let y = u32::from_be_bytes([payload[4], payload[5], payload[6], payload[7]]);
-    println!("Mouse move: x={}, y={}", x, y);
-
-    let src = CGEventSource::new(CGEventSourceStateID::HIDSystemState)
-        .map_err(|_| "Failed to create CGEventSource")?;
-
-    //create event
-    let pos = CGPoint::new(x as f64, y as f64);
-    let move_event = CGEvent::new_mouse_event(src, CGEventType::MouseMoved, pos, CGMouseButton::Left)
-        .map_err(|_| "Failed to create CGEvent")?;
-
-    //post it to the system
-    move_event.post(CGEventTapLocation::HID);
-
-    Ok(())
-}
-
-#[cfg(target_os = "linux")]
-pub fn handle_mouse_move(payload: &[u8]) -> Result<(), Box<dyn std::error::Error>> {
-    use std::process::Command;
-
-    if payload.len() < 8 {
-        return Err("Invalid MouseMove payload".into());
-    }
-
-    // Parse x/y from big-endian u32
-    let x = u32::from_be_bytes([payload[0], payload[1], payload[2], payload[3]]);
-    let y = u32::from_be_bytes([payload[4], payload[5], payload[6], payload[7]]);
-    println!("Mouse move: x={}, y={}", x, y);
-
-    //Call ydotool to actually move the cursor
-    std::process::Command::new("ydotool")
-        .arg("mousemove")
-        .arg(x.to_string())
-        .arg(y.to_string())
-        .status()?;
-
-    Ok(())
+//not logged, it arrives with every pointer movement
+pub fn handle_mouse_move(mouse: &mut Mouse, x: u32, y: u32) -> Result<(), Box<dyn Error>> {
+    mouse.move_to(x, y)
 }
 
 pub fn handle_cursor_shape(payload: &[u8]) -> Result<(), Box<dyn Error>>  {
@@ -195,62 +180,54 @@ pub fn handle_cursor_shape(payload: &[u8]) -> Result<(), Box<dyn Error>>  {
     Ok(())
 }
 
-pub fn handle_cursor_pos(payload: &[u8]) -> Result<(), Box<dyn Error>>  {
-    if payload.len() == 8 {
-        let x = u32::from_be_bytes(payload[0..4].try_into().unwrap());
-        let y = u32::from_be_bytes(payload[4..8].try_into().unwrap());
-        println!("Cursor moved to ({x}, {y})");
-    } else {
-        println!("Invalid cursor pos payload");
-    }
+pub fn handle_cursor_pos(x: u32, y: u32) -> Result<(), Box<dyn Error>>  {
+    println!("Cursor moved to ({x}, {y})");
 
     Ok(())
 }
 
-pub fn handle_resize(payload: &[u8]) -> Result<(), Box<dyn Error>>  {
-    if payload.len() == 8 {
-        let w = u32::from_be_bytes(payload[0..4].try_into().unwrap());
-        let h = u32::from_be_bytes(payload[4..8].try_into().unwrap());
-        println!("Resize request: {w}x{h}");
-    } else {
-        println!("Invalid resize payload");
-    }
-
-    Ok(())
+//the client's window changed size, or it asked for the screen 1:1 with 0x0
+pub fn handle_resize(w: u32, h: u32) -> Viewer {
+    let viewer = Viewer::from_resize(w, h);
+    println!("Client asked for {viewer}");
+    viewer
 }
 
-pub fn handle_key_down(payload: &[u8]) -> Result<(), Box<dyn Error>>  {
-    println!("Key down: {:?}", payload);
-
-    Ok(())
+//keys and text are never logged, they may be a password typed on the server
+pub fn handle_key_down(keyboard: &mut Keyboard, event: &KeyEvent) -> Result<(), Box<dyn Error>>  {
+    match event {
+        KeyEvent::Key { key, modifiers } => keyboard.key_down(*key, *modifiers),
+        KeyEvent::Text(text) => keyboard.type_text(text),
+    }
 }
 
-pub fn handle_key_up(payload: &[u8]) -> Result<(), Box<dyn Error>>  {
-    println!("Key up: {:?}", payload);
-
-    Ok(())
+pub fn handle_key_up(keyboard: &mut Keyboard, event: &KeyEvent) -> Result<(), Box<dyn Error>>  {
+    match event {
+        KeyEvent::Key { key, .. } => keyboard.key_up(*key),
+        //text is typed in full on key down
+        KeyEvent::Text(_) => Ok(()),
+    }
 }
 
-pub fn handle_mouse_down(payload: &[u8]) -> Result<(), Box<dyn Error>>  {
-    println!("Mouse down: {:?}", payload);
-
-    Ok(())
+//buttons and scrolling aren't logged either, a trackpad scrolls dozens of times a second
+pub fn handle_mouse_down(mouse: &mut Mouse, button: MouseButton) -> Result<(), Box<dyn Error>>  {
+    mouse.button_down(button)
 }
 
-pub fn handle_mouse_up(payload: &[u8]) -> Result<(), Box<dyn Error>>  {
-    println!("Mouse up: {:?}", payload);
-
-    Ok(())
+pub fn handle_mouse_up(mouse: &mut Mouse, button: MouseButton) -> Result<(), Box<dyn Error>>  {
+    mouse.button_up(button)
 }
 
-pub fn handle_mouse_scroll(payload: &[u8]) -> Result<(), Box<dyn Error>>  {
-    println!("Mouse scroll: {:?}", payload);
-
-    Ok(())
+pub fn handle_mouse_scroll(mouse: &mut Mouse, delta: ScrollDelta) -> Result<(), Box<dyn Error>>  {
+    mouse.scroll(delta)
 }
 
-pub fn handle_clipboard(payload: &[u8]) -> Result<(), Box<dyn Error>>  {
-    println!("Clipboard data: {:?}", String::from_utf8_lossy(payload));
+pub fn handle_clipboard(clipboard: Option<&ClipboardSubscription>, content: ClipboardContent) -> Result<(), Box<dyn Error>>  {
+    println!("Clipboard data: {} bytes", content.len());
 
+    //the session still works when the server has no clipboard to open, changes are just dropped
+    if let Some(clipboard) = clipboard {
+        clipboard.apply(content);
+    }
     Ok(())
 }
\ No newline at end of file
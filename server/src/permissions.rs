@@ -0,0 +1,111 @@
+//what a client may do once it has a session, set for everyone, per client certificate or per invite
+use std::fmt;
+use common::handshake::Capabilities;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Permissions(pub u32);
+
+impl Permissions {
+    pub const NONE: Permissions = Permissions(0);
+    //see the screen, a session isn't started without it
+    pub const VIEW: Permissions = Permissions(1 << 0);
+    pub const POINTER: Permissions = Permissions(1 << 1);
+    pub const KEYBOARD: Permissions = Permissions(1 << 2);
+    //receive the server's clipboard
+    pub const CLIPBOARD_READ: Permissions = Permissions(1 << 3);
+    //set the server's clipboard
+    pub const CLIPBOARD_WRITE: Permissions = Permissions(1 << 4);
+    pub const ALL: Permissions = Permissions((1 << 5) - 1);
+
+    //names used in the config file and on the command line
+    const NAMES: [(&'static str, Permissions); 5] = [
+        ("view", Permissions::VIEW),
+        ("pointer", Permissions::POINTER),
+        ("keyboard", Permissions::KEYBOARD),
+        ("clipboard-read", Permissions::CLIPBOARD_READ),
+        ("clipboard-write", Permissions::CLIPBOARD_WRITE),
+    ];
+
+    //a list of names, "all" grants every permission
+    pub fn from_names<S: AsRef<str>>(names: &[S]) -> Result<Self, String> {
+        let mut permissions = Permissions::NONE;
+        for name in names {
+            let name = name.as_ref();
+            let permission = match name {
+                "all" => Permissions::ALL,
+                //files can't be sent yet, granting it would promise something no session can do
+                "file-transfer" => return Err("file-transfer isn't supported, files can't be sent yet".to_string()),
+                _ => Permissions::NAMES.iter()
+                    .find(|(known, _)| *known == name)
+                    .map(|(_, permission)| *permission)
+                    .ok_or_else(|| format!("unknown permission {name:?}, expected all, view, pointer, keyboard, clipboard-read or clipboard-write"))?,
+            };
+            permissions = permissions.union(permission);
+        }
+        Ok(permissions)
+    }
+
+    //names of the permissions granted, in the order the config documents them
+    pub fn names(&self) -> Vec<&'static str> {
+        Permissions::NAMES.iter()
+            .filter(|(_, permission)| self.contains(*permission))
+            .map(|(name, _)| *name)
+            .collect()
+    }
+
+    pub fn contains(&self, other: Permissions) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    pub fn intersect(&self, other: Permissions) -> Permissions {
+        Permissions(self.0 & other.0)
+    }
+
+    pub fn union(&self, other: Permissions) -> Permissions {
+        Permissions(self.0 | other.0)
+    }
+
+    //session features these permissions leave in, a client that may not type or point isn't offered input at all
+    //features no permission covers are always left in
+    pub fn capabilities(&self) -> Capabilities {
+        let mut capabilities = Capabilities(u32::MAX);
+        if self.0 & (Permissions::POINTER.0 | Permissions::KEYBOARD.0) == 0 {
+            capabilities = capabilities.difference(Capabilities::INPUT);
+        }
+        if self.0 & (Permissions::CLIPBOARD_READ.0 | Permissions::CLIPBOARD_WRITE.0) == 0 {
+            capabilities = capabilities.difference(Capabilities::CLIPBOARD);
+        }
+        capabilities
+    }
+}
+
+impl fmt::Display for Permissions {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let names = self.names();
+        if names.is_empty() {
+            write!(f, "none")
+        } else {
+            write!(f, "{}", names.join(", "))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn names_round_trip_and_all_grants_each_of_them() {
+        let names = Permissions::ALL.names();
+        assert_eq!(names, ["view", "pointer", "keyboard", "clipboard-read", "clipboard-write"]);
+        assert_eq!(Permissions::from_names(&names), Ok(Permissions::ALL));
+        assert_eq!(Permissions::from_names(&["all"]), Ok(Permissions::ALL));
+    }
+
+    #[test]
+    fn file_transfer_is_refused_rather_than_granted() {
+        let e = Permissions::from_names(&["view", "file-transfer"]).unwrap_err();
+        assert!(e.contains("file-transfer isn't supported"), "{e}");
+        assert!(Permissions::from_names(&["files"]).unwrap_err().contains("unknown permission"));
+    }
+}
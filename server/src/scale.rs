@@ -0,0 +1,221 @@
+//sizing the stream to the viewer: the client says how big its window is, or that it wants the screen 1:1,
+//and every frame is scaled down to fit that before it is encoded
+//whole-number scales are averaged in boxes, anything in between is resampled, which is slower but keeps text sharp
+use std::{ error::Error, fmt };
+use clap::ValueEnum;
+use image::{ ImageBuffer, Rgba, imageops::{ self, FilterType } };
+use serde::Deserialize;
+
+//scales this close to a whole number are taken as one, a window a pixel off shouldn't cost a resample
+const WHOLE_EPSILON: f64 = 0.001;
+
+//what the client asked to see the screen at
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Viewer {
+    //the client hasn't said, the configured scale applies
+    Unknown,
+    //the screen 1:1, as far as the client's max size allows
+    Native,
+    //fit into a window this size, in physical pixels
+    Window { width: usize, height: usize },
+}
+
+impl Viewer {
+    //from a client's Resize message, 0x0 asks for native
+    pub fn from_resize(w: u32, h: u32) -> Self {
+        if w == 0 || h == 0 {
+            Viewer::Native
+        } else {
+            Viewer::Window { width: w as usize, height: h as usize }
+        }
+    }
+}
+
+impl fmt::Display for Viewer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Viewer::Unknown => write!(f, "the configured scale"),
+            Viewer::Native => write!(f, "native resolution"),
+            Viewer::Window { width, height } => write!(f, "a {width}x{height} window"),
+        }
+    }
+}
+
+//how scales that aren't whole numbers are handled
+#[derive(ValueEnum, Deserialize, Debug, Copy, Clone, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Resampling {
+    //bicubic resampling to exactly the size asked for, sharp at any scale but tens of milliseconds a frame at 1080p
+    Quality,
+    //rounded up to the next whole-number scale and box averaged, the picture can come out smaller than the window
+    Fast,
+}
+
+//what to divide the captured width and height by for this viewer, never less than 1 as the stream isn't scaled up
+//the largest frame the client accepts is a hard limit, the viewer's window only what it would like
+pub fn fit_scale(viewer: Viewer, configured: usize, capture: (usize, usize), max: (usize, usize), resampling: Resampling) -> f64 {
+    let (width, height) = (capture.0 as f64, capture.1 as f64);
+    let wanted = match viewer {
+        Viewer::Unknown => configured as f64,
+        Viewer::Native => 1.0,
+        Viewer::Window { width: window_width, height: window_height } => {
+            (width / window_width as f64).max(height / window_height as f64)
+        }
+    };
+    let scale = wanted
+        .max(width / max.0.max(1) as f64)
+        .max(height / max.1.max(1) as f64)
+        .max(1.0);
+    if scale - scale.floor() < WHOLE_EPSILON {
+        return scale.floor();
+    }
+    match resampling {
+        Resampling::Quality => scale,
+        Resampling::Fast => scale.ceil(),
+    }
+}
+
+//size of a width x height frame divided by scale
+//both sides are kept even, as the encoder's chroma planes are half the size
+pub fn scaled_size(width: usize, height: usize, scale: f64) -> (usize, usize) {
+    let even = |length: usize| (length & !1).max(2);
+    if scale.fract() == 0.0 {
+        let factor = scale as usize;
+        return (even(width / factor), even(height / factor));
+    }
+    let divide = |length: usize| (length as f64 / scale + WHOLE_EPSILON) as usize;
+    (even(divide(width)), even(divide(height)))
+}
+
+//scale a frame of width x height into dst, which has to hold the scaled size, returns that size
+//works on any 4 byte pixel format since every channel is treated the same way
+pub fn scale_frame(dst: &mut [u8], src: &[u8], width: usize, height: usize, scale: f64) -> Result<(usize, usize), Box<dyn Error>> {
+    let (scaled_width, scaled_height) = scaled_size(width, height, scale);
+    let factor = scale as usize;
+    //a frame too small to have an even size left after dividing is resampled up to the smallest one instead
+    if scale.fract() == 0.0 && scaled_width * factor <= width && scaled_height * factor <= height {
+        downscale_box(dst, src, width, factor, scaled_width, scaled_height);
+        return Ok((scaled_width, scaled_height));
+    }
+    let frame = ImageBuffer::<Rgba<u8>, &[u8]>::from_raw(width as u32, height as u32, src)
+        .ok_or_else(|| format!("frame is smaller than {width}x{height}"))?;
+    let scaled = imageops::resize(&frame, scaled_width as u32, scaled_height as u32, FilterType::CatmullRom);
+    dst[..scaled.len()].copy_from_slice(&scaled);
+    Ok((scaled_width, scaled_height))
+}
+
+//average every factor x factor block of a frame w pixels wide into one pixel of an nw x nh picture, a factor of 1 just copies
+//columns and rows past nw x nh blocks are left out
+fn downscale_box(dst: &mut [u8], src: &[u8], w: usize, factor: usize, nw: usize, nh: usize) {
+    let area = (factor * factor) as u32;
+    for y in 0..nh {
+        for x in 0..nw {
+            let mut sum = [0u32; 4];
+            for dy in 0..factor {
+                let i = ((factor*y+dy)*w + factor*x)*4;
+                for pixel in src[i..i + factor*4].chunks_exact(4) {
+                    for (s, v) in sum.iter_mut().zip(pixel) {
+                        *s += *v as u32;
+                    }
+                }
+            }
+            let o = (y*nw + x)*4;
+            for (d, s) in dst[o..o + 4].iter_mut().zip(sum) {
+                *d = (s / area) as u8;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    //a frame where each pixel is its own x and y, so a scaled pixel shows which block it came from
+    fn coordinate_frame(width: usize, height: usize) -> Vec<u8> {
+        let mut frame = Vec::with_capacity(width * height * 4);
+        for y in 0..height {
+            for x in 0..width {
+                frame.extend_from_slice(&[x as u8, y as u8, 0, 255]);
+            }
+        }
+        frame
+    }
+
+    fn assert_even((width, height): (usize, usize)) {
+        assert!(width % 2 == 0 && height % 2 == 0, "{width}x{height} isn't even");
+        assert!(width >= 2 && height >= 2, "{width}x{height} is too small");
+    }
+
+    #[test]
+    fn whole_number_scales_give_even_sizes() {
+        assert_eq!(scaled_size(2560, 1440, 3.0), (852, 480));
+        assert_eq!(scaled_size(1366, 768, 1.0), (1366, 768));
+        assert_eq!(scaled_size(1365, 767, 1.0), (1364, 766));
+        assert_eq!(scaled_size(1920, 1080, 4.0), (480, 270));
+        for scale in 1..=8 {
+            for (width, height) in [(1366, 768), (1365, 767), (2560, 1440), (1280, 1024), (7, 5)] {
+                assert_even(scaled_size(width, height, scale as f64));
+            }
+        }
+    }
+
+    #[test]
+    fn fractional_scales_give_even_sizes() {
+        assert_eq!(scaled_size(1280, 720, 1.28), (1000, 562));
+        for scale in [1.1, 1.5, 1.92, 2.5, 3.3, 7.9] {
+            for (width, height) in [(1366, 768), (1365, 767), (1920, 1080), (7, 5)] {
+                assert_even(scaled_size(width, height, scale));
+            }
+        }
+    }
+
+    #[test]
+    fn box_scaling_an_odd_frame_fills_the_even_region() {
+        let (width, height) = (13, 9);
+        let src = coordinate_frame(width, height);
+        let mut dst = vec![0u8; width * height * 4];
+        let (scaled_width, scaled_height) = scale_frame(&mut dst, &src, width, height, 3.0).unwrap();
+        assert_eq!((scaled_width, scaled_height), (4, 2));
+        //the pixel for block x, y averages columns 3x..3x+2 and rows 3y..3y+2, so it lands on the middle one
+        for y in 0..scaled_height {
+            for x in 0..scaled_width {
+                let o = (y * scaled_width + x) * 4;
+                assert_eq!(&dst[o..o + 4], &[(3 * x + 1) as u8, (3 * y + 1) as u8, 0, 255]);
+            }
+        }
+    }
+
+    #[test]
+    fn scale_one_drops_the_odd_column_and_row() {
+        let (width, height) = (5, 3);
+        let src = coordinate_frame(width, height);
+        let mut dst = vec![0u8; width * height * 4];
+        assert_eq!(scale_frame(&mut dst, &src, width, height, 1.0).unwrap(), (4, 2));
+        assert_eq!(&dst[..16], &src[..16]);
+        assert_eq!(&dst[16..32], &src[20..36]);
+    }
+
+    #[test]
+    fn frames_too_small_for_the_box_are_resampled() {
+        let (width, height) = (3, 3);
+        let src = coordinate_frame(width, height);
+        let mut dst = vec![0u8; 4 * 4];
+        assert_eq!(scale_frame(&mut dst, &src, width, height, 2.0).unwrap(), (2, 2));
+    }
+
+    #[test]
+    fn fit_scale_fits_the_window_and_the_client_limit() {
+        let capture = (2560, 1440);
+        let max = (1920, 1080);
+        let window = Viewer::Window { width: 1280, height: 720 };
+        assert_eq!(fit_scale(window, 2, capture, max, Resampling::Quality), 2.0);
+        let window = Viewer::Window { width: 1000, height: 1000 };
+        assert_eq!(fit_scale(window, 2, capture, max, Resampling::Quality), 2.56);
+        assert_eq!(fit_scale(window, 2, capture, max, Resampling::Fast), 3.0);
+        //native and a larger window are held to the client's limit, and nothing is scaled up
+        assert_eq!(fit_scale(Viewer::Native, 2, capture, max, Resampling::Quality), 2560.0 / 1920.0);
+        assert_eq!(fit_scale(Viewer::Native, 2, (800, 600), max, Resampling::Quality), 1.0);
+        assert_eq!(fit_scale(Viewer::Unknown, 3, capture, max, Resampling::Quality), 3.0);
+    }
+}
@@ -1,12 +1,10 @@
 use std::{
-    io::{ Read, Write, ErrorKind, },
+    io::{ self, Read, Write, ErrorKind, },
     error::Error,
-    sync::{ Arc },
-    env,
-    net::{ TcpListener, TcpStream, },
+    sync::{ Arc, atomic::{ AtomicUsize, Ordering } },
+    net::{ IpAddr, Shutdown, SocketAddr, TcpListener, TcpStream, },
     time::{ Instant, Duration },
     thread,
-    sync::{ mpsc, },
 };
 use rustls::{
     ServerConfig,
@@ -20,14 +18,97 @@ use turbojpeg::{ Compressor,
     Subsamp,
     OutputBuf,
 };
-use common::message_type::MessageType;
+use common::{
+    protocol::{ Message, MessageEncoder, MessageDecoder, DecodeError, ErrorMessage, ErrorCode, MAX_PAYLOAD_LEN },
+    handshake::{ SessionConfig, Capabilities },
+    clipboard::DEFAULT_MAX_CLIPBOARD_LEN,
+};
 use crate::message_type_handlers;
-use crate::capture::start_sck_stream;
+use crate::config::{ AllowedInput, Config, InputBackend, Invite };
+use crate::adapt::{ self, Adapter, Bounds, LinkStats, Quality };
+use crate::scale::{ self, Viewer };
+use crate::approval::{ ApprovalPolicy, Approver };
+use crate::frame_queue::{ self, FrameReceiver, FrameSender, Outgoing };
+use crate::audit::{ AuditLog, SessionAudit };
+use crate::tls::ClientIdentity;
+use crate::auth::{ self, AuthLimiter };
+use crate::permissions::Permissions;
+use crate::input::{ self, Keyboard, Mouse, SharedInjector, RecordingInjector, InputInjector };
+use crate::capture::{ self, SharedCapture, SyntheticSource, CaptureSource };
+use crate::clipboard::{ SharedClipboard, ClipboardSubscription };
 use openh264::{
-    encoder::{ Encoder, EncoderConfig, RateControlMode },
+    encoder::{ Encoder, EncoderConfig, FrameType, RateControlMode },
     formats::YUVBuffer,
+    Timestamp,
 };
 
+//how long a connection has to finish the TLS handshake, so idle connections don't hold a worker thread for ever
+const TLS_HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+//how long a client has after the TLS handshake to send its Connect hello
+const HELLO_TIMEOUT: Duration = Duration::from_secs(5);
+//long enough for someone to type a password at the client's prompt
+const AUTH_TIMEOUT: Duration = Duration::from_secs(120);
+//largest message read before the session is agreed, a hello or password is far smaller
+//so a client that hasn't authenticated can't make the server buffer a frame sized payload
+const HANDSHAKE_MAX_PAYLOAD: usize = 4 * 1024;
+//connections still in the TLS handshake, hello or password prompt, per client the server allows
+//each one holds a thread, so this caps what clients that never authenticate can tie up
+const PENDING_PER_CLIENT: usize = 4;
+//the client is told a little less than it really has so it doesn't give up just before the answer arrives
+const APPROVAL_MARGIN: Duration = Duration::from_secs(2);
+//size of the generated test patterns
+const SYNTHETIC_WIDTH: usize = 1280;
+const SYNTHETIC_HEIGHT: usize = 720;
+
+//one of a limited number of concurrent client sessions or pending connections, given back when dropped
+struct ClientSlot {
+    active: Arc<AtomicUsize>,
+}
+
+impl ClientSlot {
+    //take a slot if fewer than max are taken
+    fn acquire(active: &Arc<AtomicUsize>, max: usize) -> Option<ClientSlot> {
+        active.fetch_update(Ordering::SeqCst, Ordering::SeqCst, |n| if n < max { Some(n + 1) } else { None })
+            .ok()
+            .map(|_| ClientSlot { active: active.clone() })
+    }
+}
+
+impl Drop for ClientSlot {
+    fn drop(&mut self) {
+        self.active.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+//who a session is with and what it may do
+struct SessionClient {
+    //name for this client in the log
+    name: String,
+    permissions: Permissions,
+    audit: SessionAudit,
+    //shared with the encode loop, which adapts the stream to it
+    link: Arc<LinkStats>,
+}
+
+//what every session shares: one capture stream, one clipboard watcher and one set of input devices
+struct Shared {
+    capture: Arc<SharedCapture>,
+    clipboard: Arc<SharedClipboard>,
+    injector: SharedInjector,
+}
+
+//shared by every connection to decide who gets a session after the hello
+struct Admission {
+    //wrong passwords are counted per address across every connection
+    limiter: AuthLimiter,
+    //only running when approval is ask
+    approver: Option<Approver>,
+    //every client let in or turned away is recorded here
+    audit: Arc<AuditLog>,
+    //sessions running now, a client is only counted once it has authenticated
+    active_clients: Arc<AtomicUsize>,
+    max_clients: usize,
+}
 
 #[inline]
 fn rgba_to_rgb_inplace(dst_rgb: &mut [u8], src_rgba: &[u8]) {
@@ -39,47 +120,272 @@ fn rgba_to_rgb_inplace(dst_rgb: &mut [u8], src_rgba: &[u8]) {
     }
 }
 
-fn downscale_rgba_box_2x(dst: &mut [u8], src: &[u8], w: usize, h: usize) -> (usize, usize) {
-    let nw = w / 2;
-    let nh = h / 2;
-    for y in 0..nh {
-        for x in 0..nw {
-            let mut r:u32=0; let mut g:u32=0; let mut b:u32=0; let mut a:u32=0;
-            for dy in 0..2 {
-                for dx in 0..2 {
-                    let i = ((2*y+dy)*w + (2*x+dx))*4;
-                    r += src[i+0] as u32;
-                    g += src[i+1] as u32;
-                    b += src[i+2] as u32;
-                    a += src[i+3] as u32;
-                }
-            }
-            let o = (y*nw + x)*4;
-            dst[o+0]=(r/4) as u8; dst[o+1]=(g/4) as u8; dst[o+2]=(b/4) as u8; dst[o+3]=(a/4) as u8;
-        }
+#[inline]
+fn bgra_to_rgb_inplace(dst_rgb: &mut [u8], src_bgra: &[u8]) {
+    // dst_rgb must be (width * height * 3) bytes long
+    let mut di = 0;
+    for chunk in src_bgra.chunks_exact(4) {
+        dst_rgb[di] = chunk[2];
+        dst_rgb[di + 1] = chunk[1];
+        dst_rgb[di + 2] = chunk[0];
+        di += 3;
+    }
+}
+
+//drop the alpha channel, putting the color channels in RGB order whatever order the source used
+fn to_rgb_inplace(dst_rgb: &mut [u8], src: &[u8], format: capture::PixelFormat) {
+    match format {
+        capture::PixelFormat::Rgba8 => rgba_to_rgb_inplace(dst_rgb, src),
+        capture::PixelFormat::Bgra8 => bgra_to_rgb_inplace(dst_rgb, src),
     }
-    (nw, nh)
+}
+
+//frame timestamp in the form the encoder wants
+fn encode_timestamp(frame: &capture::Frame) -> Timestamp {
+    Timestamp::from_millis(frame.timestamp.as_millis() as u64)
 }
 
 //TO RUN YDOTOOLD(to allow for mouse and keyboard input) run "~/bin/ydotool_session.sh" in empty terminal window
 //run "sudo pkill -f ydotoold" to stop ydotoold
-fn handle_client(mut tcp: TcpStream, tls_config: Arc<ServerConfig>) -> Result<(), Box<dyn std::error::Error>> {
+//pending: this connection's place among those that haven't got a session yet, given back once the handshake is over
+fn handle_client(mut tcp: TcpStream, pending: ClientSlot, tls_config: Arc<ServerConfig>, shared: Arc<Shared>, config: Arc<Config>, admission: Arc<Admission>) -> Result<(), Box<dyn std::error::Error>> {
+    let peer = tcp.peer_addr()?;
     tcp.set_nodelay(true)?;
     tcp.set_read_timeout(Some(Duration::from_millis(5)))?;
     tcp.set_write_timeout(Some(Duration::from_millis(5)))?;
 
-    // --- Create TLS stream ---
-    let mut tls_conn = ServerConnection::new(tls_config.clone())?;
+    let tls_conn = match tls_handshake(&mut tcp, tls_config) {
+        Ok(tls_conn) => tls_conn,
+        Err(e) => {
+            admission.audit.rejected(peer, None, failure_code(&*e, "TlsHandshake"), &e.to_string());
+            return Err(e);
+        }
+    };
+    //the certificate already chains to client_ca if one is required, this is who it belongs to
+    let identity = ClientIdentity::from_connection(&tls_conn);
+    let mut tls = StreamOwned::new(tls_conn, tcp);
+
+    match &identity {
+        Some(identity) => println!("New client connection from {identity}"),
+        None => println!("New client connection"),
+    }
+
+    //reason to turn the client away once it says hello
+    let rejection = if let Some(identity) = &identity
+        && !identity.allowed_by(&config.allowed_clients) {
+        Some(ErrorMessage::new(ErrorCode::Unauthorized, format!("client certificate {identity} is not on the allow-list")))
+    } else if config.approval == ApprovalPolicy::Deny {
+        Some(ErrorMessage::new(ErrorCode::Denied, "server isn't accepting new clients"))
+    } else {
+        None
+    };
+
+    //no frames are sent until the client and server agree on a session
+    let mut decoder = MessageDecoder::with_max_payload(HANDSHAKE_MAX_PAYLOAD);
+    let (session, client, slot) = handshake(&mut tls, &mut decoder, rejection, &config, &admission, peer, identity.as_ref())?;
+    //the session is agreed, clipboard contents can be as large as the protocol allows from here on
+    decoder.set_max_payload(MAX_PAYLOAD_LEN);
+    drop(pending);
+    //held by the dispatcher and the encode loop both, the slot is free again once the whole session is over
+    let slot = Arc::new(slot);
+    let dispatcher_slot = slot.clone();
+    let dispatcher_shared = shared.clone();
+    println!(
+        "Session established: protocol {}, codec {:?}, max {}x{}, capabilities {:#x}, permissions {}",
+        session.version, session.codec, session.max_width, session.max_height, session.capabilities.0, client.permissions
+    );
+
+    //largest frame the client accepts, the session itself moves to the dispatcher
+    let (max_width, max_height) = (session.max_width as usize, session.max_height as usize);
+
+    let (frame_sender, frame_receiver) = frame_queue::bounded();
+    let link = client.link.clone();
+    let name = client.name.clone();
+
+    //new dispatcher thread
+    std::thread::spawn(move || {
+    // this thread owns the TLS stream
+    let mut tls = tls;
+    let reason = match dispatcher(&mut tls, frame_receiver, decoder, session, &client, &dispatcher_shared) {
+        Ok(()) => "client disconnected".to_string(),
+        Err(e) => {
+            eprintln!("Dispatcher thread error: {e}");
+            e.to_string()
+        }
+    };
+    //the client sees the session end rather than a stream that stopped moving
+    tls.conn.send_close_notify();
+    let _ = tls.flush();
+    let _ = tls.sock.shutdown(Shutdown::Both);
+    client.audit.ended(&reason);
+    drop(dispatcher_slot);
+    });
+
+    //subscribe to the shared capture stream
+    let rx = shared.capture.subscribe();
+
+    //get first image and the images width/height, the capture can change size later on
+    let first = rx.recv_latest().ok_or("Capture stream ended")?;
+    let (mut capture_width, mut capture_height) = (first.width, first.height);
+    //pointer positions from the client are relative to the captured screen
+    shared.injector.lock().unwrap().set_screen_size(capture_width as u32, capture_height as u32);
+    //the configured scale until the client says how big its window is
+    let mut viewer = Viewer::Unknown;
+    let mut scale = scale::fit_scale(viewer, config.scale as usize, (capture_width, capture_height), (max_width, max_height), config.resampling);
+    //rgb and downscaled rgba buffers sized for the first image
+    let (width, height) = scale::scaled_size(capture_width, capture_height, scale);
+    let (mut rgb_buf, mut down_rgba) = frame_buffers(width, height);
+    println!("Streaming {capture_width}x{capture_height} to {name} at {width}x{height}");
+
+    scale::scale_frame(&mut down_rgba, &first.data, capture_width, capture_height, scale)?;
+    // convert the downscaled pixels to RGB
+    to_rgb_inplace(&mut rgb_buf[0..width*height*3], &down_rgba[0..width*height*4], first.format);
+
+    //create rgb of first image
+    //rgba_to_rgb_inplace(&mut rgb_buf, &first_rgba);
+
+    //set current encoder dimensions
+    let mut current_enc_w = width;
+    let mut current_enc_h = height;
+
+    //the stream starts at the configured quality, with adaptive on it follows what the link keeps up with from there
+    let mut quality = Quality { bitrate_bps: config.bitrate_bps, fps: config.fps, scale };
+    let mut adapter = config.adaptive.then(|| Adapter::new(Bounds {
+        best: quality,
+        min_bitrate_bps: config.min_bitrate_bps,
+        min_fps: config.min_fps,
+        max_scale: config.max_scale as f64,
+    }));
+    let mut encoder = h264_encoder(width, height, quality)?;
+
+    //converts rgb image into yuv420 format
+    let yuv = YUVBuffer::with_rgb(width as usize, height as usize, &rgb_buf);
+
+    //compresses yuv image into bitstream
+    let bitstream = encoder.encode_at(&yuv, encode_timestamp(&first))?;
+    //clones bitstream into vec<u8> so it can be sent over TLS
+    let encoded_bytes = bitstream.to_vec();
+    let keyframe = bitstream.frame_type() == FrameType::IDR;
+
+    //sends info to frame_receiver, a closed queue means the dispatcher is gone and the client disconnected
+    //the client is told the size of the screen first, its pointer positions are relative to that
+    if frame_sender.send_message(Message::Resize { w: capture_width as u32, h: capture_height as u32 }).is_err()
+        || frame_sender.send(encoded_bytes, keyframe).is_err() {
+        return Ok(());
+    }
+
+    //frames are sent no faster than the configured rate, whatever is captured in between is skipped
+    let mut frame_interval = Duration::from_secs(1) / quality.fps;
+    let mut next_frame = Instant::now() + frame_interval;
+
     loop {
-        match tls_conn.complete_io(&mut tcp) {
+        let now = Instant::now();
+        if next_frame > now {
+            thread::sleep(next_frame - now);
+        }
+        next_frame = next_frame.max(now) + frame_interval;
+
+        if let Some(adaptation) = adapter.as_mut().and_then(|adapter| adapter.update(&link, frame_sender.stats())) {
+            println!("Adapting stream for {name}: {adaptation}");
+            let to = adaptation.to;
+            if to.scale != quality.scale {
+                //a new size needs new buffers and a new encoder, which starts over with a keyframe
+                scale = to.scale;
+                let (width, height) = scale::scaled_size(capture_width, capture_height, scale);
+                (rgb_buf, down_rgba) = frame_buffers(width, height);
+                encoder = h264_encoder(width, height, to)?;
+            } else {
+                adapt::set_rate(&mut encoder, to.bitrate_bps, to.fps)?;
+            }
+            frame_interval = Duration::from_secs(1) / to.fps;
+            quality = to;
+        }
+
+        //wait for the next frame, anything older that piled up while encoding is skipped
+        let latest = rx.recv_latest().ok_or("Capture stream ended")?;
+
+        //the screen's resolution changed, everything sized from the last frame starts over at the new size
+        let resized = (latest.width, latest.height) != (capture_width, capture_height);
+        if resized {
+            println!("Capture changed from {capture_width}x{capture_height} to {}x{}, restarting the stream for {name}", latest.width, latest.height);
+            (capture_width, capture_height) = (latest.width, latest.height);
+            shared.injector.lock().unwrap().set_screen_size(capture_width as u32, capture_height as u32);
+        }
+        //the client's window changed size, the stream only starts over if that changes the size it is sent at
+        let requested = frame_sender.take_viewer_request();
+        if let Some(requested) = requested {
+            viewer = requested;
+        }
+        if resized || requested.is_some() {
+            let fitted = scale::fit_scale(viewer, config.scale as usize, (capture_width, capture_height), (max_width, max_height), config.resampling);
+            match adapter.as_mut() {
+                Some(adapter) => quality = adapter.rescale(fitted),
+                None => quality.scale = fitted,
+            }
+            if resized || quality.scale != scale {
+                scale = quality.scale;
+                let (width, height) = scale::scaled_size(capture_width, capture_height, scale);
+                println!("Streaming {capture_width}x{capture_height} to {name} at {width}x{height} for {viewer}");
+                (rgb_buf, down_rgba) = frame_buffers(width, height);
+                encoder = h264_encoder(width, height, quality)?;
+            }
+            if resized && frame_sender.send_message(Message::Resize { w: capture_width as u32, h: capture_height as u32 }).is_err() {
+                return Ok(());
+            }
+        }
+
+        {
+            // Downscale
+            let (nw, nh) = scale::scale_frame(&mut down_rgba, &latest.data, capture_width, capture_height, scale)?;
+            // Convert to RGB
+            to_rgb_inplace(
+                &mut rgb_buf[0..nw * nh * 3],
+                &down_rgba[0..nw * nh * 4],
+                latest.format,
+            );
+
+            if frame_sender.take_keyframe_request() {
+                println!("Sending {name} the keyframe it asked for");
+            }
+            //after the queue dropped frames or the client's decoder failed, nothing can be decoded until the next keyframe
+            if frame_sender.needs_keyframe() {
+                force_keyframe(&mut encoder);
+            }
+
+            // Prepare YUV buffer and encode
+            let yuv = YUVBuffer::with_rgb(nw, nh, &rgb_buf[0..nw * nh * 3]);
+            let bitstream = encoder.encode_at(&yuv, encode_timestamp(&latest))?;
+            let encoded = bitstream.to_vec();
+            if encoded.is_empty() {
+                continue;
+            }
+            if frame_sender.send(encoded, bitstream.frame_type() == FrameType::IDR).is_err() {
+                println!("Client skipped {} captured frames while encoding", rx.dropped());
+                print_queue_stats(&name, &frame_sender);
+                return Ok(());
+            }
+        }
+    }
+}
+
+//run the TLS handshake on tcp, giving up after TLS_HANDSHAKE_TIMEOUT
+fn tls_handshake(tcp: &mut TcpStream, tls_config: Arc<ServerConfig>) -> Result<ServerConnection, Box<dyn Error>> {
+    let mut tls_conn = ServerConnection::new(tls_config)?;
+    let deadline = Instant::now() + TLS_HANDSHAKE_TIMEOUT;
+    loop {
+        //the short read timeout above keeps each attempt brief, this ends the whole handshake
+        if Instant::now() >= deadline {
+            return Err(Box::new(io::Error::new(ErrorKind::TimedOut, "TLS handshake timed out")));
+        }
+        match tls_conn.complete_io(tcp) {
             Ok((_rd, _wr)) => {
                 // Handshake complete when both conditions true:
                 if !tls_conn.is_handshaking() {
-                    break;
+                    return Ok(tls_conn);
                 }
             }
 
-            Err(ref e) if e.kind() == std::io::ErrorKind::WouldBlock => {
+            Err(ref e) if e.kind() == std::io::ErrorKind::WouldBlock || e.kind() == std::io::ErrorKind::TimedOut => {
                 // Socket not ready yet — wait a bit and try again
                 std::thread::sleep(Duration::from_millis(5));
                 continue;
@@ -104,175 +410,450 @@ fn handle_client(mut tcp: TcpStream, tls_config: Arc<ServerConfig>) -> Result<()
         // Optional: back off a bit if handshake is still progressing
         std::thread::sleep(Duration::from_millis(1));
     }
-    let tls = StreamOwned::new(tls_conn, tcp);
-
-    println!("New client connection");
-
-    let (frame_transmitter, frame_receiver) = std::sync::mpsc::channel::<(MessageType, Vec<u8>)>();
+}
 
-    //new dispatcher thread
-    std::thread::spawn(move || {
-    // this thread owns the TLS stream
-    let mut tls = tls;
-    if let Err(e) = dispatcher(&mut tls, frame_receiver) {
-        eprintln!("Dispatcher thread error: {e}");
+//audit code for a connection that failed before it could be told why, other_code for anything but a timeout or hang up
+fn failure_code(e: &(dyn Error + 'static), other_code: &'static str) -> &'static str {
+    match e.downcast_ref::<io::Error>().map(io::Error::kind) {
+        Some(ErrorKind::TimedOut) => "Timeout",
+        Some(ErrorKind::UnexpectedEof) => "Disconnected",
+        _ => other_code,
     }
-    });
-
-    //start ScreenCaptureKit capture
-    let rx = start_sck_stream();
-    println!("ScreenCaptureKit capture started…");
-
-    //get first image and the images width/height
-    let (init_width, init_height, first_rgba) = rx.recv()?;
-    //create an empty vec with dimensions of first image as RGB
-    let mut rgb_buf = vec![0u8; (init_width / 2) * (init_height / 2) * 3];
-    //create an empty vec with dimensions of first image downscaled
-    let mut down_rgba = vec![0u8; (init_width / 2) * (init_height / 2) * 4];
-
-    let (width, height) = downscale_rgba_box_2x(&mut down_rgba, &first_rgba, init_width, init_height);
-    // convert the downscaled RGBA to RGB
-    rgba_to_rgb_inplace(&mut rgb_buf[0..width*height*3], &down_rgba[0..width*height*4]);
-
-    //create rgb of first image
-    //rgba_to_rgb_inplace(&mut rgb_buf, &first_rgba);
+}
 
-    //set current encoder dimensions
-    let mut current_enc_w = width;
-    let mut current_enc_h = height;
+//rgb and downscaled rgba buffers for frames of width x height
+fn frame_buffers(width: usize, height: usize) -> (Vec<u8>, Vec<u8>) {
+    let pixels = width * height;
+    (vec![0u8; pixels * 3], vec![0u8; pixels * 4])
+}
 
-    //set h.264 encoder configuration then build encoder with that config
+//h.264 encoder for frames of width x height at the given bitrate and frame rate
+fn h264_encoder(width: usize, height: usize, quality: Quality) -> Result<Encoder, Box<dyn Error>> {
     let enc_cfg = EncoderConfig::new(width as u32, height as u32)
-        .max_frame_rate(30.0)
-        .set_bitrate_bps(10_000_000)
+        .max_frame_rate(quality.fps as f32)
+        .set_bitrate_bps(quality.bitrate_bps)
         .rate_control_mode(RateControlMode::Bitrate);
-    let mut encoder = Encoder::with_config(enc_cfg)?;
+    Ok(Encoder::with_config(enc_cfg)?)
+}
 
-    //converts rgb image into yuv420 format
-    let yuv = YUVBuffer::with_rgb(width as usize, height as usize, &rgb_buf);
+//make the next frame an IDR so a client that missed frames can decode again
+fn force_keyframe(encoder: &mut Encoder) {
+    //only asks the encoder to start over at its next frame, no parameters change
+    unsafe {
+        encoder.raw_api().force_intra_frame(true);
+    }
+}
 
-    //compresses yuv image into bitstream
-    let bitstream = encoder.encode(&yuv)?;
-    //clones bitstream into vec<u8> so it can be sent over TLS
-    let encoded_bytes = bitstream.to_vec();
+fn print_queue_stats(name: &str, frame_sender: &FrameSender) {
+    let stats = frame_sender.stats();
+    println!(
+        "Frame queue for {name}: {} sent, {} dropped in {} resets, at most {} queued, {} of {} keyframe requests answered",
+        stats.sent, stats.dropped, stats.resets, stats.max_depth, stats.keyframes_forced, stats.keyframe_requests
+    );
+}
 
-    //sends info to frame_receiver
-    frame_transmitter.send((MessageType::FrameDelta, encoded_bytes))?;
-    frame_transmitter.send((MessageType::FrameEnd, Vec::new()))?;
+//every setting can be given as a flag or in server.toml, see cargo run --release -p server -- --help
+//to make the certs directory cargo run --release -p server -- gen-certs <hostname> <ip>...
+//to let someone watch without control cargo run --release -p server -- invite --name <name>, then add what it prints to server.toml
+//to run on local host cargo run --release -p server -- --bind 127.0.0.1:7878
+//to stream a test pattern instead of the screen cargo run --release -p server -- --capture gradient|text|testcard
+//xtest uses $DISPLAY, to try it headless run "Xvfb :99 &" then DISPLAY=:99 cargo run --release -p server -- --input-backend xtest
+pub fn run(tls_config: Arc<ServerConfig>, config: Config) -> Result<(), Box<dyn Error>> {
+    let source: Box<dyn CaptureSource> = match config.capture {
+        None => capture::screen_source(config.monitor),
+        Some(pattern) => Box::new(SyntheticSource::new(pattern, SYNTHETIC_WIDTH, SYNTHETIC_HEIGHT, config.fps)),
+    };
+    //uinput maps its absolute pointer to the captured screen, sessions tell it when that changes size
+    let screen = source.dimensions().unwrap_or((0, 0));
+    let injector: Box<dyn InputInjector> = match config.input_backend {
+        //nothing would be injected anyway, so no device is needed
+        None if config.allow_input == AllowedInput::None => Box::new(RecordingInjector::new()),
+        None => input::platform_injector(screen)?,
+        #[cfg(target_os = "linux")]
+        Some(InputBackend::Uinput) => Box::new(input::UinputInjector::new(screen.0 as u32, screen.1 as u32)?),
+        #[cfg(target_os = "linux")]
+        Some(InputBackend::Ydotool) => Box::new(input::YdotoolInjector::new()),
+        #[cfg(target_os = "linux")]
+        Some(InputBackend::Xtest) => Box::new(input::XTestInjector::connect(None)?),
+        Some(InputBackend::Record) => Box::new(RecordingInjector::new()),
+        #[cfg(not(target_os = "linux"))]
+        Some(backend) => return Err(format!("Input backend {backend} is not available on this platform").into()),
+    };
+    println!("Injecting input with {}", injector.name());
+    //one set of virtual devices is shared by every client
+    let injector = input::shared(injector);
+    let max_clients = config.max_clients;
+    let max_pending = max_clients.saturating_mul(PENDING_PER_CLIENT);
+    let pending = Arc::new(AtomicUsize::new(0));
+    let bind_addr = config.bind;
+    let config = Arc::new(config);
+
+    //fails without a terminal, which is better found out before anyone connects
+    let approver = match config.approval {
+        ApprovalPolicy::Ask => Some(Approver::start()?),
+        ApprovalPolicy::Auto | ApprovalPolicy::Deny => None,
+    };
 
-    //initialize prev_frame with the first frame
-    let mut prev_frame = first_rgba;
+    let listener = TcpListener::bind(bind_addr).map_err(|e| format!("Failed to listen on {bind_addr}: {e}"))?;
+    println!("Tcp server listening to {bind_addr}, max {max_clients} clients");
 
-    loop {
-        let mut latest = None;
-        // drain the capture channel and keep only the latest frame
-        while let Ok(frame) = rx.try_recv() {
-            latest = Some(frame);
+    //one capture stream feeds every client, it only runs while someone is connected
+    //and one watcher on the clipboard serves every session that syncs it
+    let shared = Arc::new(Shared {
+        capture: SharedCapture::new(source),
+        clipboard: SharedClipboard::new(DEFAULT_MAX_CLIPBOARD_LEN),
+        injector,
+    });
+    let audit = match &config.audit_log {
+        Some(path) => {
+            println!("Writing audit log to {}", path.display());
+            AuditLog::open(path, config.audit_log_max_bytes, config.audit_log_keep)?
         }
+        None => AuditLog::disabled(),
+    };
+    let admission = Arc::new(Admission {
+        limiter: AuthLimiter::new(),
+        approver,
+        audit: Arc::new(audit),
+        active_clients: Arc::new(AtomicUsize::new(0)),
+        max_clients,
+    });
+    if config.password_hash.is_some() {
+        println!("Clients need a password to connect");
+    }
+    match config.approval {
+        ApprovalPolicy::Auto => {}
+        ApprovalPolicy::Deny => println!("Turning away every client, approval is deny"),
+        ApprovalPolicy::Ask => println!("Each client has to be approved here, unanswered requests are denied after {} seconds", config.approval_timeout_secs),
+    }
 
-        if let Some((_, _, rgba)) = latest {
-            // Downscale
-            let (nw, nh) = downscale_rgba_box_2x(&mut down_rgba, &rgba, init_width, init_height);
-            // Convert RGBA → RGB
-            rgba_to_rgb_inplace(
-                &mut rgb_buf[0..nw * nh * 3],
-                &down_rgba[0..nw * nh * 4],
-            );
-
-            // Prepare YUV buffer and encode
-            let yuv = YUVBuffer::with_rgb(nw, nh, &rgb_buf[0..nw * nh * 3]);
-            let bitstream = encoder.encode(&yuv)?;
-            let encoded = bitstream.to_vec();
-            if !encoded.is_empty() {
-                frame_transmitter.send((MessageType::FrameDelta, encoded))?;
-                frame_transmitter.send((MessageType::FrameEnd, Vec::new()))?;
+    //handle every client on its own thread so one slow or broken client can't stall or kill the others
+    for stream in listener.incoming() {
+        let tcp = match stream {
+            Ok(tcp) => tcp,
+            Err(e) => {
+                eprintln!("Failed to accept connection: {e}");
+                continue;
+            }
+        };
+        let peer = tcp.peer_addr().map(|a| a.to_string()).unwrap_or_else(|_| "unknown peer".to_string());
+        //too many connections haven't got through the handshake yet, this one is closed before it gets a thread
+        let Some(pending) = ClientSlot::acquire(&pending, max_pending) else {
+            eprintln!("Closing connection from {peer}, {max_pending} connections are already waiting to authenticate");
+            if let Ok(addr) = tcp.peer_addr() {
+                admission.audit.rejected(addr, None, "Overloaded", "too many connections waiting to authenticate");
             }
+            let _ = tcp.shutdown(Shutdown::Both);
+            continue;
+        };
+        //clients over the limit still get a worker so they can be told why they were turned away, their slot is taken once they authenticate
+        let tls_config = tls_config.clone();
+        let shared = shared.clone();
+        let config = config.clone();
+        let admission = admission.clone();
+
+        let spawned = thread::Builder::new()
+            .name(format!("client {peer}"))
+            .spawn(move || {
+                if let Err(e) = handle_client(tcp, pending, tls_config, shared, config, admission) {
+                    eprintln!("Client {peer} error: {e}");
+                }
+                println!("Client {peer} session ended");
+            });
+        if let Err(e) = spawned {
+            eprintln!("Failed to start client thread: {e}");
         }
     }
+    Ok(())
 }
 
-//to run on local host SERVER_BIND=127.0.0.1:7878 cargo run --release -p server
-//to run at on vm at work or at home cargo run --release -p server
-pub fn run(tls_config: Arc<ServerConfig>) -> Result<(), Box<dyn Error>> {
-    let default_addr = "0.0.0.0:7878".to_string();
-    //allow override of bind address with env var
-    let bind_addr = env::var("SERVER_BIND").unwrap_or(default_addr);
-    let listener = TcpListener::bind(&bind_addr)?;
-    println!("Tcp server listening to {bind_addr}");
-    //call handel_client on all clients that contact tcp adress
-    for stream in listener.incoming() {
-        handle_client(stream?, tls_config.clone())?;
+//wait for the client's hello and answer with the negotiated session, or a structured error if they can't be served
+//rejection: why the client can't be served even if its hello is fine, sent in place of the session
+//when a password is configured the client has to give it before the session is sent, then it may have to be approved
+//the session only offers what the client's permissions allow, a client that may not view gets no session
+//a client slot is taken once the client is known to be allowed in, before anyone is asked to approve it
+fn handshake<T: Read + Write>(tls: &mut T, decoder: &mut MessageDecoder, rejection: Option<ErrorMessage>, config: &Config, admission: &Admission, peer: SocketAddr, identity: Option<&ClientIdentity>) -> Result<(SessionConfig, SessionClient, ClientSlot), Box<dyn Error>> {
+    let mut encoder = MessageEncoder::new();
+    let result = match decoder.read_message(tls, HELLO_TIMEOUT) {
+        Ok(Message::Connect(_)) if let Some(rejection) = rejection => Err(rejection),
+        Ok(Message::Connect(hello)) => message_type_handlers::handle_connect(&hello, config),
+        Ok(other) => Err(ErrorMessage::new(
+            ErrorCode::Protocol,
+            format!("expected Connect, got {:?}", other.message_type()),
+        )),
+        Err(e) => match e.downcast::<DecodeError>() {
+            Ok(decode_err) => Err(ErrorMessage::new(ErrorCode::Protocol, decode_err.to_string())),
+            Err(e) => {
+                admission.audit.rejected(peer, identity, failure_code(&*e, "ConnectionFailed"), &e.to_string());
+                return Err(e);
+            }
+        },
+    };
+    let result = match result {
+        Ok(session) if config.requires_auth() => {
+            authenticate(tls, decoder, &mut encoder, config, &admission.limiter, peer.ip()).map(|invite| (session, invite))
+        }
+        result => result.map(|session| (session, None)),
+    };
+    let result = result.and_then(|(mut session, invite)| {
+        let permissions = config.permissions_for(identity, invite);
+        if !permissions.contains(Permissions::VIEW) {
+            return Err(ErrorMessage::new(ErrorCode::Unauthorized, "this client isn't permitted to view the screen"));
+        }
+        session.capabilities = session.capabilities.intersect(permissions.capabilities());
+        let slot = ClientSlot::acquire(&admission.active_clients, admission.max_clients)
+            .ok_or_else(|| ErrorMessage::new(ErrorCode::ServerFull, "server is at its client limit, try again later"))?;
+        Ok((session, invite, permissions, slot))
+    });
+    let result = match (result, &admission.approver) {
+        (Ok((session, invite, permissions, slot)), Some(approver)) => {
+            //the address, certificate and invite are what someone at the server has to go on
+            let mut who = peer.to_string();
+            if let Some(identity) = identity {
+                who += &format!(" {identity}");
+            }
+            if let Some(invite) = invite {
+                who += &format!(" with invite {:?}", invite.name);
+            }
+            who += &format!(" to {permissions}");
+            approve(tls, &mut encoder, approver, config, &who).map(|_| (session, invite, permissions, slot))
+        }
+        (result, _) => result,
+    };
+
+    match result {
+        Ok((session, invite, permissions, slot)) => {
+            encoder.write(tls, &Message::SessionConfig(session.clone()))?;
+            let client = SessionClient {
+                name: identity.map(ClientIdentity::to_string).unwrap_or_else(|| "Client".to_string()),
+                permissions,
+                audit: admission.audit.start_session(peer, identity, invite.map(|invite| invite.name.as_str()), permissions),
+                link: Arc::new(LinkStats::default()),
+            };
+            Ok((session, client, slot))
+        }
+        Err(err) => {
+            admission.audit.rejected(peer, identity, &format!("{:?}", err.code), &err.message);
+            encoder.write(tls, &Message::Error(err.clone()))?;
+            Err(format!("Rejected client: {err}").into())
+        }
     }
-    Ok(())
 }
 
-fn handle_incoming_message(msg_type: MessageType, payload: &[u8]) -> Result<(), Box<dyn Error>> {
-    match msg_type {
-        MessageType::Text => message_type_handlers::handle_text(payload)?,
-        MessageType::Connect => message_type_handlers::handle_connect(payload)?,
-        MessageType::Disconnect => message_type_handlers::handle_disconnect(payload)?,
-        MessageType::Error => message_type_handlers::handle_error(payload)?,
+//ask for the password and check it, a locked out address is turned away without being asked
+//an invite token is accepted in place of the password, the invite it belongs to is returned
+fn authenticate<'a, T: Read + Write>(tls: &mut T, decoder: &mut MessageDecoder, encoder: &mut MessageEncoder, config: &'a Config, limiter: &AuthLimiter, peer_ip: IpAddr) -> Result<Option<&'a Invite>, ErrorMessage> {
+    let locked_out = |wait: Duration| ErrorMessage::new(
+        ErrorCode::Unauthorized,
+        format!("too many wrong passwords, try again in {} seconds", wait.as_secs() + 1),
+    );
+    if let Some(wait) = limiter.locked_for(peer_ip) {
+        return Err(locked_out(wait));
+    }
 
-        MessageType::CursorShape => message_type_handlers::handle_cursor_shape(payload)?,
-        MessageType::CursorPos => message_type_handlers::handle_cursor_pos(payload)?,
-        MessageType::Resize => message_type_handlers::handle_resize(payload)?,
+    let io_error = |e: Box<dyn Error>| ErrorMessage::new(ErrorCode::Protocol, e.to_string());
+    encoder.write(tls, &Message::AuthRequired).map_err(|e| io_error(e.into()))?;
+    let password = match decoder.read_message(tls, AUTH_TIMEOUT).map_err(io_error)? {
+        Message::Auth(password) => password,
+        other => {
+            return Err(ErrorMessage::new(
+                ErrorCode::Protocol,
+                format!("expected Auth, got {:?}", other.message_type()),
+            ));
+        }
+    };
 
-        MessageType::KeyDown => message_type_handlers::handle_key_down(payload)?,
-        MessageType::KeyUp => message_type_handlers::handle_key_up(payload)?,
-        MessageType::MouseMove => message_type_handlers::handle_mouse_move(payload)?,
-        MessageType::MouseDown => message_type_handlers::handle_mouse_down(payload)?,
-        MessageType::MouseUp => message_type_handlers::handle_mouse_up(payload)?,
-        MessageType::MouseScroll => message_type_handlers::handle_mouse_scroll(payload)?,
+    //the attempt is counted before the password is checked, so connections in parallel can't each get a guess in
+    let attempt = limiter.begin(peer_ip).map_err(locked_out)?;
+    if config.password_hash.as_deref().is_some_and(|hash| auth::verify_password(hash, &password)) {
+        attempt.succeeded();
+        println!("Client {peer_ip} authenticated");
+        return Ok(None);
+    }
+    if let Some(invite) = config.invites.iter().find(|invite| auth::verify_password(&invite.token_hash, &password)) {
+        attempt.succeeded();
+        println!("Client {peer_ip} authenticated with invite {:?}", invite.name);
+        return Ok(Some(invite));
+    }
+    match attempt.failed() {
+        Some(lockout) => {
+            println!("Wrong password from {peer_ip}, locked out for {} seconds", lockout.as_secs());
+            Err(ErrorMessage::new(
+                ErrorCode::Unauthorized,
+                format!("wrong password, too many attempts, try again in {} seconds", lockout.as_secs()),
+            ))
+        }
+        None => {
+            println!("Wrong password from {peer_ip}");
+            Err(ErrorMessage::new(ErrorCode::Unauthorized, "wrong password"))
+        }
+    }
+}
 
-        MessageType::Clipboard => message_type_handlers::handle_clipboard(payload)?,
+//hold the client until someone at the server lets it in, it is told how long it may have to wait
+fn approve<T: Read + Write>(tls: &mut T, encoder: &mut MessageEncoder, approver: &Approver, config: &Config, who: &str) -> Result<(), ErrorMessage> {
+    let timeout = Duration::from_secs(config.approval_timeout_secs as u64);
+    let waiting = Message::AwaitingApproval { timeout_secs: config.approval_timeout_secs };
+    encoder.write(tls, &waiting).map_err(|e| ErrorMessage::new(ErrorCode::Protocol, e.to_string()))?;
+    if approver.ask(who, timeout.saturating_sub(APPROVAL_MARGIN).max(Duration::from_secs(1))) {
+        Ok(())
+    } else {
+        Err(ErrorMessage::new(ErrorCode::Denied, "the server didn't approve the connection"))
+    }
+}
+
+//handle one message from the client, returns a reply to send back if there is one
+fn handle_incoming_message(msg: Message, session: &SessionConfig, keyboard: &mut Keyboard, mouse: &mut Mouse, clipboard: Option<&ClipboardSubscription>, client: &SessionClient) -> Result<Option<Message>, Box<dyn Error>> {
+    //drop input, clipboard and report messages for features that weren't agreed on
+    let required = match msg {
+        Message::KeyDown(_) | Message::KeyUp(_) | Message::MouseMove { .. }
+        | Message::MouseDown(_) | Message::MouseUp(_) | Message::MouseScroll(_) => Capabilities::INPUT,
+        Message::Clipboard(_) => Capabilities::CLIPBOARD,
+        Message::ReceiveReport { .. } => Capabilities::RECEIVE_REPORTS,
+        _ => Capabilities::NONE,
+    };
+    if !session.capabilities.contains(required) {
+        println!("Ignoring {:?}, not enabled for this session", msg.message_type());
+        return Ok(None);
+    }
+    match msg {
+        Message::Text(text) => message_type_handlers::handle_text(&text)?,
+        Message::Connect(_) => {
+            return Ok(Some(Message::Error(ErrorMessage::new(ErrorCode::Protocol, "session already established"))));
+        }
+        Message::Disconnect => message_type_handlers::handle_disconnect()?,
+        Message::Error(err) => message_type_handlers::handle_error(&err)?,
+        Message::SessionConfig(_) | Message::AuthRequired | Message::Auth(_) | Message::AwaitingApproval { .. } => {}
+
+        Message::CursorShape(payload) => message_type_handlers::handle_cursor_shape(&payload)?,
+        Message::CursorPos { x, y } => message_type_handlers::handle_cursor_pos(x, y)?,
+        Message::ReceiveReport { frames, bytes } => client.link.client_report(frames, bytes),
+
+        Message::KeyDown(event) => message_type_handlers::handle_key_down(keyboard, &event)?,
+        Message::KeyUp(event) => message_type_handlers::handle_key_up(keyboard, &event)?,
+        Message::MouseMove { x, y } => message_type_handlers::handle_mouse_move(mouse, x, y)?,
+        Message::MouseDown(button) => message_type_handlers::handle_mouse_down(mouse, button)?,
+        Message::MouseUp(button) => message_type_handlers::handle_mouse_up(mouse, button)?,
+        Message::MouseScroll(delta) => message_type_handlers::handle_mouse_scroll(mouse, delta)?,
+
+        Message::Clipboard(content) => {
+            client.audit.clipboard("to_server", &content);
+            message_type_handlers::handle_clipboard(clipboard, content)?
+        }
 
-        MessageType::FrameFull => {}
-        MessageType::FrameDelta => {}
-        MessageType::FrameEnd => {}
+        Message::FrameFull(_) => {}
+        Message::FrameDelta(_) => {}
+        Message::FrameEnd => {}
+        Message::RequestKeyframe => {}
+        Message::Resize { .. } => {}
 
-        MessageType::Unknown(code) => {
+        Message::Unknown { code, payload } => {
             println!("Unknown message type: {code:#X}, skipping {} bytes", payload.len());
         }
     }
 
-    Ok(())
+    Ok(None)
 }
 
-fn dispatcher<T: Read + Write>(tls: &mut T, frame_receiver: mpsc::Receiver<(MessageType, Vec<u8>)>) -> Result<(), Box<dyn Error>> {
+//permission for what a client message does, NONE for messages every session may send
+fn required_permission(msg: &Message) -> Permissions {
+    match msg {
+        Message::KeyDown(_) | Message::KeyUp(_) => Permissions::KEYBOARD,
+        Message::MouseMove { .. } | Message::MouseDown(_) | Message::MouseUp(_) | Message::MouseScroll(_) => Permissions::POINTER,
+        Message::Clipboard(_) => Permissions::CLIPBOARD_WRITE,
+        _ => Permissions::NONE,
+    }
+}
 
-    let mut header = [0u8; 5];
+fn dispatcher<T: Read + Write>(tls: &mut T, frame_receiver: FrameReceiver, mut decoder: MessageDecoder, session: SessionConfig, client: &SessionClient, shared: &Shared) -> Result<(), Box<dyn Error>> {
+    let mut encoder = MessageEncoder::new();
+    //keys and buttons this client is holding down on the server, released when the dispatcher returns for any reason
+    let mut keyboard = Keyboard::new(shared.injector.clone());
+    let mut mouse = Mouse::new(shared.injector.clone());
+    //the session's share of the server clipboard watcher, given up when the dispatcher returns
+    let clipboard = if session.capabilities.contains(Capabilities::CLIPBOARD) {
+        let read = client.permissions.contains(Permissions::CLIPBOARD_READ);
+        let write = client.permissions.contains(Permissions::CLIPBOARD_WRITE);
+        match shared.clipboard.subscribe(read, write) {
+            Ok(clipboard) => Some(clipboard),
+            Err(e) => {
+                eprintln!("Clipboard sync unavailable: {e}");
+                None
+            }
+        }
+    } else {
+        None
+    };
+    //permissions a message was already refused for, a client that keeps moving its mouse is only told once
+    let mut refused = Permissions::NONE;
 
     loop {
         let mut sent_any = false;
-        //if frame was tramsitted from main loop, send it to client
-        while let Ok((msg_type, payload)) = frame_receiver.try_recv() {
-            send_response(tls, msg_type, &payload)?;
-            sent_any = true;
+        //if frame was tramsitted from main loop, send it to client, messages from the encode loop go in the order they were queued
+        while let Some(outgoing) = frame_receiver.try_recv() {
+            match outgoing {
+                Outgoing::Message(msg) => encoder.write(tls, &msg)?,
+                Outgoing::Frame(frame) => {
+                    let len = frame.len();
+                    encoder.write(tls, &Message::FrameDelta(frame))?;
+                    encoder.write(tls, &Message::FrameEnd)?;
+                    client.link.bytes_sent(len);
+                    client.link.frame_sent();
+                    sent_any = true;
+                }
+            }
+        }
+        //send server clipboard changes
+        while let Some(content) = clipboard.as_ref().and_then(ClipboardSubscription::next_change) {
+            client.audit.clipboard("to_client", &content);
+            encoder.write(tls, &Message::Clipboard(content))?;
         }
 
         // If we sent frames, go right back to loop to drain quickly
         if sent_any {
             continue;
         }
+        //the encode loop stopped, on an error it already reported, there won't be any more video for this client
+        if frame_receiver.finished() {
+            return Err("video stream stopped".into());
+        }
 
         //Try to read, but don't block forever
-        match tls.read(&mut header) {
+        match decoder.read_from(tls) {
             Ok(0) => {
-                println!("Client disconnected");
+                println!("{} disconnected", client.name);
                 return Ok(());
             }
-            Ok(n) if n < 5 => {
-                // partial read; read the rest next loop iteration
-                continue;
-            }
             Ok(_) => {
-                // read payload and send to match to get handled properly
-                let msg_type = MessageType::from_u8(header[0]);
-                let payload_len =
-                    u32::from_be_bytes([header[1], header[2], header[3], header[4]]);
-                let mut payload = vec![0u8; payload_len as usize];
-                tls.read_exact(&mut payload)?;
-                handle_incoming_message(msg_type, &payload)?;
+                //handle every complete message, partial ones stay buffered until the next read
+                loop {
+                    match decoder.next_message() {
+                        //the encode loop makes the keyframe, rate limited by the queue
+                        Ok(Some(Message::RequestKeyframe)) => frame_receiver.request_keyframe(),
+                        //and fits the stream to the client's window
+                        Ok(Some(Message::Resize { w, h })) => frame_receiver.request_viewer(message_type_handlers::handle_resize(w, h)),
+                        Ok(Some(msg)) => {
+                            let required = required_permission(&msg);
+                            if !client.permissions.contains(required) {
+                                if !refused.contains(required) {
+                                    refused = refused.union(required);
+                                    println!("Refusing {:?} from {}, it needs the {} permission", msg.message_type(), client.name, required);
+                                    client.audit.refused(format!("{:?}", msg.message_type()), required);
+                                    let err = ErrorMessage::new(ErrorCode::Forbidden, format!("this session doesn't have the {required} permission"));
+                                    encoder.write(tls, &Message::Error(err))?;
+                                }
+                                continue;
+                            }
+                            if let Some(reply) = handle_incoming_message(msg, &session, &mut keyboard, &mut mouse, clipboard.as_ref(), client)? {
+                                encoder.write(tls, &reply)?;
+                            }
+                        }
+                        Ok(None) => break,
+                        //an oversized header means the stream is out of sync, nothing after it can be trusted
+                        Err(e @ DecodeError::Oversized { .. }) => return Err(Box::new(e)),
+                        Err(e) => eprintln!("Dropping malformed message: {e}"),
+                    }
+                }
             }
             Err(ref e) if e.kind() == ErrorKind::WouldBlock => {
                 // no incoming data yet, continue loop
@@ -290,35 +871,26 @@ fn dispatcher<T: Read + Write>(tls: &mut T, frame_receiver: mpsc::Receiver<(Mess
     }
 }
 
-pub fn send_response<T: Write>(stream: &mut T, msg_type: MessageType, payload: &[u8],) -> Result<(), Box<dyn std::error::Error>> {
-    //build a single buffer (header + payload)
-    let mut buf = Vec::with_capacity(5 + payload.len());
-    buf.push(msg_type.to_u8());
-    buf.extend_from_slice(&(payload.len() as u32).to_be_bytes());
-    buf.extend_from_slice(payload);
-
-    //closure to handle WouldBlock
-    //keeps retrying until all bytes are written
-    let mut write_all_retry = |data: &[u8]| -> Result<(), Box<dyn std::error::Error>> {
-        let mut offset = 0;
-        let mut retries = 0;
-        while offset < data.len() {
-            match stream.write(&data[offset..]) {
-                Ok(0) => return Err("Socket closed while writing".into()),
-                Ok(n) => offset += n,
-                Err(ref e) if e.kind() == std::io::ErrorKind::WouldBlock => {
-                    retries += 1;
-                    std::thread::sleep(Duration::from_millis(1));
-                    continue;
-                }
-                Err(e) => return Err(Box::new(e)),
-            }
-        }
-        Ok(())
-    };
-
-    //send the message to client
-    write_all_retry(&buf)?;
-    let _ = stream.flush();
-    Ok(())
-}
\ No newline at end of file
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use common::{ message_type::MessageType, protocol::{ encode_message, HEADER_LEN } };
+
+    #[test]
+    fn an_oversized_message_before_the_session_is_refused_from_its_header() {
+        let password = encode_message(&Message::Auth("x".repeat(64 * 1024)));
+        let mut decoder = MessageDecoder::with_max_payload(HANDSHAKE_MAX_PAYLOAD);
+        //only the header has arrived, the payload is never waited for
+        decoder.feed(&password[..HEADER_LEN]);
+        assert!(matches!(
+            decoder.next_message(),
+            Err(DecodeError::Oversized { msg_type: MessageType::Auth, len: 65536, max: HANDSHAKE_MAX_PAYLOAD })
+        ));
+
+        //the same message is fine once the session is agreed
+        let mut decoder = MessageDecoder::with_max_payload(HANDSHAKE_MAX_PAYLOAD);
+        decoder.set_max_payload(MAX_PAYLOAD_LEN);
+        decoder.feed(&password);
+        assert!(matches!(decoder.next_message(), Ok(Some(Message::Auth(password))) if password.len() == 64 * 1024));
+    }
+}
@@ -1,29 +1,154 @@
-use std::{fs::File, io::BufReader, sync::Arc};
-use rustls::{ServerConfig};
+use std::{fs::File, io::BufReader, path::Path, sync::Arc};
+use rustls::{ServerConfig, ServerConnection, RootCertStore};
+use rustls::server::WebPkiClientVerifier;
 use rustls_pemfile::{certs, pkcs8_private_keys};
 use rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use sha2::{Digest, Sha256};
+use x509_parser::prelude::{FromDer, X509Certificate};
 use std::error::Error;
+use common::fingerprint::fingerprint_hex;
+use crate::config::AllowedClient;
 
-pub fn load_server_config() -> Result<Arc<ServerConfig>, Box<dyn Error>> {
+//client_ca: when set clients must present a certificate that chains to one of its certificates
+pub fn load_server_config(cert_path: &Path, key_path: &Path, client_ca: Option<&Path>) -> Result<Arc<ServerConfig>, Box<dyn Error>> {
     //get cert and key
-    let local_cert_file = &mut BufReader::new(File::open("../certs/server.crt")?);
-    let cert_file = &mut BufReader::new(File::open("../certs/server.crt")?);
-    let key_file = &mut BufReader::new(File::open("../certs/server.key")?);
+    let cert_file = &mut BufReader::new(File::open(cert_path)
+        .map_err(|e| format!("Failed to open certificate {}: {e}", cert_path.display()))?);
+    let key_file = &mut BufReader::new(File::open(key_path)
+        .map_err(|e| format!("Failed to open private key {}: {e}", key_path.display()))?);
 
     //get certs
     let cert_chain: Vec<CertificateDer<'static>> = certs(cert_file)
         .collect::<Result<_,_>>()?;
+    if cert_chain.is_empty() {
+        return Err(format!("No certificates found in {}", cert_path.display()).into());
+    }
 
     //get keys
     let mut keys: Vec<PrivateKeyDer<'static>> = pkcs8_private_keys(key_file)
         .map(|res| res.map(PrivateKeyDer::from))
         .collect::<Result<_,_>>()?;
+    if keys.is_empty() {
+        return Err(format!("No PKCS#8 private key found in {}", key_path.display()).into());
+    }
     let key = keys.remove(0);
 
     //build config
-    let cfg = ServerConfig::builder()
-        .with_no_client_auth()
-        .with_single_cert(cert_chain, key)?;
+    let builder = ServerConfig::builder();
+    let cfg = match client_ca {
+        None => builder.with_no_client_auth().with_single_cert(cert_chain, key)?,
+        Some(ca_path) => {
+            let ca_file = &mut BufReader::new(File::open(ca_path)
+                .map_err(|e| format!("Failed to open client CA {}: {e}", ca_path.display()))?);
+            let mut roots = RootCertStore::empty();
+            for cert in certs(ca_file) {
+                roots.add(cert?)?;
+            }
+            if roots.is_empty() {
+                return Err(format!("No certificates found in client CA {}", ca_path.display()).into());
+            }
+            let verifier = WebPkiClientVerifier::builder(Arc::new(roots)).build()?;
+            builder.with_client_cert_verifier(verifier).with_single_cert(cert_chain, key)?
+        }
+    };
 
     Ok(Arc::new(cfg))
-}
\ No newline at end of file
+}
+
+//who a client proved to be with its certificate
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ClientIdentity {
+    pub fingerprint: [u8; 32],
+    //subject common name, empty if the certificate has none
+    pub common_name: String,
+}
+
+impl ClientIdentity {
+    //identity of the certificate the client presented, None if it didn't present one
+    pub fn from_connection(conn: &ServerConnection) -> Option<Self> {
+        let cert = conn.peer_certificates()?.first()?;
+        let fingerprint: [u8; 32] = Sha256::digest(cert.as_ref()).into();
+        //the certificate was already parsed during verification, so this only fails on oddities like a non utf-8 name
+        let common_name = X509Certificate::from_der(cert.as_ref()).ok()
+            .and_then(|(_, parsed)| {
+                parsed.subject().iter_common_name().next()
+                    .and_then(|cn| cn.as_str().ok())
+                    .map(str::to_string)
+            })
+            .unwrap_or_default();
+        Some(ClientIdentity { fingerprint, common_name })
+    }
+
+    //an empty allow-list lets in every verified certificate
+    pub fn allowed_by(&self, allowed: &[AllowedClient]) -> bool {
+        allowed.is_empty() || allowed.iter().any(|entry| self.matches(entry))
+    }
+
+    pub fn matches(&self, entry: &AllowedClient) -> bool {
+        match entry {
+            AllowedClient::Fingerprint(fingerprint) => *fingerprint == self.fingerprint,
+            AllowedClient::CommonName(name) => !self.common_name.is_empty() && *name == self.common_name,
+        }
+    }
+}
+
+impl std::fmt::Display for ClientIdentity {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.common_name.is_empty() {
+            write!(f, "sha256:{}", fingerprint_hex(&self.fingerprint))
+        } else {
+            write!(f, "{} (sha256:{})", self.common_name, fingerprint_hex(&self.fingerprint))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn identity(fingerprint: u8, common_name: &str) -> ClientIdentity {
+        ClientIdentity { fingerprint: [fingerprint; 32], common_name: common_name.to_string() }
+    }
+
+    fn entry(text: &str) -> AllowedClient {
+        AllowedClient::parse(text).unwrap()
+    }
+
+    #[test]
+    fn allow_list_entries_parse_as_fingerprints_or_names() {
+        let hex = "AB".repeat(32);
+        assert_eq!(entry(&format!("sha256:{hex}")), AllowedClient::Fingerprint([0xAB; 32]));
+        assert_eq!(entry(&format!("sha256:{}", ["ab"; 32].join(":"))), AllowedClient::Fingerprint([0xAB; 32]));
+        assert_eq!(entry("laptop"), AllowedClient::CommonName("laptop".to_string()));
+        //a fingerprint that is cut short is an error, not a name
+        assert_eq!(AllowedClient::parse("sha256:AB:CD"), None);
+        assert_eq!(AllowedClient::parse(""), None);
+    }
+
+    #[test]
+    fn clients_match_by_fingerprint_or_common_name() {
+        let laptop = identity(0xAB, "laptop");
+        assert!(laptop.matches(&AllowedClient::Fingerprint([0xAB; 32])));
+        assert!(!laptop.matches(&AllowedClient::Fingerprint([0xCD; 32])));
+        assert!(laptop.matches(&entry("laptop")));
+        //names are compared exactly
+        assert!(!laptop.matches(&entry("Laptop")));
+        assert!(!laptop.matches(&entry("laptop2")));
+    }
+
+    #[test]
+    fn a_certificate_without_a_common_name_never_matches_a_name() {
+        let nameless = identity(0xAB, "");
+        assert!(!nameless.matches(&AllowedClient::CommonName(String::new())));
+        assert!(nameless.matches(&AllowedClient::Fingerprint([0xAB; 32])));
+    }
+
+    #[test]
+    fn the_allow_list_lets_in_any_match_and_everyone_when_empty() {
+        let laptop = identity(0xAB, "laptop");
+        assert!(laptop.allowed_by(&[]));
+        assert!(laptop.allowed_by(&[entry("desktop"), AllowedClient::Fingerprint([0xAB; 32])]));
+        assert!(!laptop.allowed_by(&[entry("desktop"), AllowedClient::Fingerprint([0xCD; 32])]));
+        assert!(!identity(0xCD, "").allowed_by(&[entry("laptop")]));
+    }
+}